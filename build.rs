@@ -1,7 +1,58 @@
 use std::env;
 use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `git describe` of the source tree, so deployed builds can be told apart
+fn git_describe() -> String {
+    Command::new("git")
+        .args(["describe", "--tags", "--always", "--dirty"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|describe| describe.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Today's UTC date as YYYY-MM-DD, from the days since the epoch (proleptic Gregorian calendar)
+fn build_date() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Clock before 1970")
+        .as_secs();
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Rust types and service traits for the gRPC server, with a bundled protoc
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    env::set_var(
+        "PROTOC",
+        protoc_bin_vendored::protoc_bin_path().expect("No bundled protoc"),
+    );
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/slm.proto"], &["proto"])
+        .expect("Can't compile proto/slm.proto");
+}
 
 fn main() {
+    println!("cargo:rustc-env=SLM_GIT_DESCRIBE={}", git_describe());
+    println!("cargo:rustc-env=SLM_BUILD_DATE={}", build_date());
+    #[cfg(feature = "grpc")]
+    compile_protos();
+
     let target = env::var("TARGET").unwrap();
     if target.contains("pc-windows") {
         let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
@@ -10,8 +61,7 @@ fn main() {
         if target.contains("msvc") {
             lib_dir.push("msvc");
             dll_dir.push("msvc");
-        }
-        else {
+        } else {
             lib_dir.push("gnu-mingw");
             dll_dir.push("gnu-mingw");
         }
@@ -20,13 +70,12 @@ fn main() {
         if target.contains("x86_64") {
             lib_dir.push("64");
             dll_dir.push("64");
-        }
-        else {
+        } else {
             lib_dir.push("32");
             dll_dir.push("32");
         }
         println!("cargo:rustc-link-search=all={}", lib_dir.display());
-        for entry in std::fs::read_dir(dll_dir).expect("Can't read DLL dir")  {
+        for entry in std::fs::read_dir(dll_dir).expect("Can't read DLL dir") {
             let entry_path = entry.expect("Invalid fs entry").path();
             let file_name_result = entry_path.file_name();
             let mut new_file_path = manifest_dir.clone();
@@ -34,9 +83,10 @@ fn main() {
                 let file_name = file_name.to_str().unwrap();
                 if file_name.ends_with(".dll") {
                     new_file_path.push(file_name);
-                    std::fs::copy(&entry_path, new_file_path.as_path()).expect("Can't copy from DLL dir");
+                    std::fs::copy(&entry_path, new_file_path.as_path())
+                        .expect("Can't copy from DLL dir");
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
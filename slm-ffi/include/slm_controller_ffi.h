@@ -0,0 +1,48 @@
+/* C ABI of the SLM controller pattern engine, for rigs (e.g. LabVIEW) that
+ * show the patterns themselves instead of talking MQTT.
+ *
+ * Functions returning int return 0 on success and -1 on failure;
+ * slm_last_error() then describes the failure. Only one controller can
+ * exist at a time, and it must only be used from one thread at a time. */
+#ifndef SLM_CONTROLLER_FFI_H
+#define SLM_CONTROLLER_FFI_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct SlmController SlmController;
+
+/* Load the config (e.g. "config.json") and compute the default pattern;
+ * NULL on failure. Paths in the config are relative to the working directory. */
+SlmController *slm_init(const char *config_path);
+
+/* Free a controller from slm_init; NULL is ignored. */
+void slm_free(SlmController *controller);
+
+/* Set the pattern from the JSON of its parameters, e.g. {"spot": {...}}. */
+int slm_set_pattern(SlmController *controller, const char *pattern_json);
+
+int slm_set_fresnel(SlmController *controller, uint32_t fresnel);
+
+int slm_set_wavelength(SlmController *controller, uint32_t wavelength);
+
+/* Width and height in pixels of the patterns slm_render writes. */
+int slm_pattern_size(const SlmController *controller, uint32_t *width, uint32_t *height);
+
+/* Copy the current pattern as 8-bit gray levels, row by row, into a buffer
+ * of width * height bytes. */
+int slm_render(const SlmController *controller, uint8_t *buffer, size_t length);
+
+/* Message of the last failure on this thread, valid until the next call;
+ * NULL if there was none. */
+const char *slm_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,212 @@
+//! C ABI of the pattern engine (`include/slm_controller_ffi.h`), so legacy
+//! LabVIEW rigs can compute patterns without MQTT and show them themselves
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use sdl2::Sdl;
+
+use rasp_pi::{read_config_file, schema::PatternParams, Context, Result, SlmError};
+
+pub struct SlmController {
+    // declared first, so it's dropped before the SDL context it borrows
+    context: Context<'static>,
+    _sdl_context: Box<Sdl>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).expect("NULs are replaced");
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+/// Run `f`, turning errors and panics into -1 and the last error; unwinding
+/// into the caller would abort it
+fn call(f: impl FnOnce() -> Result<()>) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => 0,
+        Ok(Err(err)) => {
+            set_last_error(err.to_string());
+            -1
+        }
+        Err(_) => {
+            set_last_error("Internal error".to_owned());
+            -1
+        }
+    }
+}
+
+fn null_argument(name: &str) -> SlmError {
+    SlmError::Request(format!("{} is NULL", name))
+}
+
+unsafe fn string_argument<'a>(string: *const c_char, name: &str) -> Result<&'a str> {
+    if string.is_null() {
+        Err(null_argument(name))?
+    }
+    CStr::from_ptr(string)
+        .to_str()
+        .map_err(|_| SlmError::Request(format!("{} is not UTF-8", name)))
+}
+
+unsafe fn controller_argument<'a>(controller: *const SlmController) -> Result<&'a SlmController> {
+    controller
+        .as_ref()
+        .ok_or_else(|| null_argument("controller"))
+}
+
+unsafe fn controller_argument_mut<'a>(
+    controller: *mut SlmController,
+) -> Result<&'a mut SlmController> {
+    controller
+        .as_mut()
+        .ok_or_else(|| null_argument("controller"))
+}
+
+/// Controller for the config at `config_path`, or NULL on failure
+///
+/// # Safety
+/// `config_path` is NULL or a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn slm_init(config_path: *const c_char) -> *mut SlmController {
+    let mut controller = ptr::null_mut();
+    call(|| {
+        let config = read_config_file(Path::new(string_argument(config_path, "config_path")?))?;
+        let sdl_context = Box::new(sdl2::init().map_err(SlmError::Display)?);
+        // the box keeps it in place for as long as the controller lives
+        let sdl_ref: &'static Sdl = &*(&*sdl_context as *const Sdl);
+        let mut context = Context::offline(config, sdl_ref)?;
+        context.update_state(None, None, None)?;
+        controller = Box::into_raw(Box::new(SlmController {
+            context,
+            _sdl_context: sdl_context,
+        }));
+        Ok(())
+    });
+    controller
+}
+
+/// # Safety
+/// `controller` is NULL or from `slm_init`, and isn't used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn slm_free(controller: *mut SlmController) {
+    if !controller.is_null() {
+        drop(Box::from_raw(controller));
+    }
+}
+
+/// # Safety
+/// `controller` is NULL or from `slm_init`; `pattern_json` is NULL or a
+/// NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn slm_set_pattern(
+    controller: *mut SlmController,
+    pattern_json: *const c_char,
+) -> c_int {
+    call(|| {
+        let controller = controller_argument_mut(controller)?;
+        let pattern: PatternParams =
+            serde_json::from_str(string_argument(pattern_json, "pattern_json")?)?;
+        controller.context.update_state(Some(pattern), None, None)?;
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `controller` is NULL or from `slm_init`
+#[no_mangle]
+pub unsafe extern "C" fn slm_set_fresnel(controller: *mut SlmController, fresnel: u32) -> c_int {
+    call(|| {
+        let controller = controller_argument_mut(controller)?;
+        controller.context.update_state(None, Some(fresnel), None)?;
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `controller` is NULL or from `slm_init`
+#[no_mangle]
+pub unsafe extern "C" fn slm_set_wavelength(
+    controller: *mut SlmController,
+    wavelength: u32,
+) -> c_int {
+    call(|| {
+        let controller = controller_argument_mut(controller)?;
+        controller
+            .context
+            .update_state(None, None, Some(wavelength))?;
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `controller` is NULL or from `slm_init`; `width` and `height` are NULL or
+/// writable
+#[no_mangle]
+pub unsafe extern "C" fn slm_pattern_size(
+    controller: *const SlmController,
+    width: *mut u32,
+    height: *mut u32,
+) -> c_int {
+    call(|| {
+        let controller = controller_argument(controller)?;
+        if width.is_null() || height.is_null() {
+            Err(null_argument("width or height"))?
+        }
+        let (pattern_width, pattern_height) = controller.context.config.screen.size;
+        *width = pattern_width;
+        *height = pattern_height;
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `controller` is NULL or from `slm_init`; `buffer` is NULL or writable for
+/// `length` bytes
+#[no_mangle]
+pub unsafe extern "C" fn slm_render(
+    controller: *const SlmController,
+    buffer: *mut u8,
+    length: usize,
+) -> c_int {
+    call(|| {
+        let controller = controller_argument(controller)?;
+        if buffer.is_null() {
+            Err(null_argument("buffer"))?
+        }
+        let pattern = controller
+            .context
+            .state
+            .displayed
+            .as_ref()
+            .expect("slm_init shows a pattern");
+        let (width, height) = pattern.dim();
+        if length < width * height {
+            Err(SlmError::Request(format!(
+                "Buffer of {} bytes is too small for {}x{} pixels",
+                length, width, height
+            )))?
+        }
+        let buffer = std::slice::from_raw_parts_mut(buffer, width * height);
+        // indexed by (x, y), written row by row
+        for ((x, y), value) in pattern.indexed_iter() {
+            buffer[y * width + x] = *value;
+        }
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn slm_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
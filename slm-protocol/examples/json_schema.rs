@@ -0,0 +1,7 @@
+//! Print the JSON Schema of the protocol:
+//! `cargo run -p slm-protocol --example json_schema --features json-schema`
+
+fn main() {
+    let schema = slm_protocol::json_schema();
+    println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+}
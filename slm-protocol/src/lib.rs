@@ -0,0 +1,1685 @@
+//! Messages exchanged between the SLM controller and its clients
+//! (LuxControl GUI, SLM calibration software, embedded controller).
+//!
+//! The crate version is the protocol version: additions that old clients can ignore
+//! bump the minor version, anything that changes or removes existing fields bumps the major.
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[cfg(feature = "json-schema")]
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{
+    de::{Deserializer, Error, MapAccess, Visitor},
+    ser::{SerializeMap, Serializer},
+    Deserialize, Serialize,
+};
+
+pub const PROTOCOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MessageType {
+    Log,
+    Device,
+    Status,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct LaserState {
+    pub name: String,
+    /// Whether the laser is on; sent as 0 or 1, as older firmware expects, and
+    /// accepted as a boolean too, as newer firmware sends it
+    #[serde(
+        deserialize_with = "deserialize_laser_on",
+        serialize_with = "serialize_laser_on"
+    )]
+    pub state: bool,
+    pub wavelength: u32,
+    /// In percent of the laser's maximum power; older firmware sends integers
+    pub intensity: f32,
+}
+
+struct LaserOnVisitor {}
+
+impl<'de> Visitor<'de> for LaserOnVisitor {
+    type Value = bool;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a boolean or an integer")
+    }
+
+    fn visit_bool<E: Error>(self, value: bool) -> Result<Self::Value, E> {
+        Ok(value)
+    }
+
+    fn visit_u64<E: Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(value != 0)
+    }
+
+    fn visit_i64<E: Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(value != 0)
+    }
+}
+
+fn deserialize_laser_on<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(LaserOnVisitor {})
+}
+
+fn serialize_laser_on<S>(on: &bool, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_u32(*on as u32)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "command")]
+pub enum LaserCommand {
+    #[serde(rename = "get")]
+    Get,
+    #[serde(rename = "availablePatterns")]
+    AvailablePatterns,
+    #[serde(rename = "set")]
+    Set { lasers: Vec<LaserState> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct AimState {
+    pub pattern: PatternParams,
+    pub fresnel: u32,
+    /// Space the pattern positions are given in
+    #[serde(default)]
+    pub space: Space,
+    /// Show the gray levels of the pattern image as they are, without blaze,
+    /// fresnel, corrections or calibration scaling
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub raw: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Space positions are given in; camera positions are converted with the registration
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Space {
+    /// The controller's coordinate system (see `Capabilities`)
+    #[default]
+    Slm,
+    /// Camera pixels
+    Camera,
+}
+
+/// The same point in camera pixels and in SLM coordinates
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct PointPair {
+    pub camera_xy: (f32, f32),
+    pub slm_xy: (f32, f32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RegistrationSource {
+    /// Camera to SLM homography, row-major; affine transforms have (0, 0, 1) as the last row
+    Matrix { matrix: [[f64; 3]; 3] },
+    /// Least-squares fit of an affine transform (3 pairs or more),
+    /// or of a homography if `homography` is set (4 pairs or more)
+    PointPairs {
+        pairs: Vec<PointPair>,
+        #[serde(default)]
+        homography: bool,
+    },
+}
+
+/// Mapping from camera pixels to SLM coordinates
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct Registration {
+    /// Camera to SLM homography, row-major
+    pub matrix: [[f64; 3]; 3],
+    /// Root mean square distance between the fitted and the given SLM points,
+    /// if the registration was fitted
+    #[serde(default)]
+    pub rms_residual: Option<f32>,
+    #[serde(default)]
+    pub max_residual: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct StateReport {
+    pub pattern: PatternParams,
+    pub fresnel: u32,
+    pub wavelength: u32,
+    pub tilt_xy: (f32, f32),
+    pub attenuation: f32,
+    pub defect_masks: Vec<DefectMask>,
+    /// Corrections that were actually applied to the displayed pattern
+    pub applied_corrections: Vec<String>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub astigmatic_fresnel: Option<AstigmaticFresnel>,
+    /// Identifies the pattern parameters, for command preconditions
+    #[serde(default)]
+    pub pattern_hash: String,
+    /// Client holding control, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub controlled_by: Option<String>,
+    /// Client (or topic, if it didn't send one) of the last command that changed state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified_by: Option<String>,
+    #[serde(default)]
+    pub maintenance: bool,
+    /// The pattern image is shown as it is
+    #[serde(default)]
+    pub raw: bool,
+    /// The panel is blanked by the schedule until the next pattern is set
+    #[serde(default)]
+    pub blanked: bool,
+    #[serde(default)]
+    pub diffraction_order: DiffractionOrder,
+}
+
+/// First diffraction order the blaze steers the light into; which one reaches
+/// the sample depends on the optical train
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum DiffractionOrder {
+    /// The blaze phase falls along x
+    #[default]
+    MinusFirst,
+    /// The blaze phase rises along x
+    PlusFirst,
+}
+
+/// A change of the state, published on `<serial>/aim/events` whatever caused
+/// it, for clients mirroring the state without asking for it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct StateEvent {
+    /// Increases by one with every event, from 1 when the controller starts
+    pub id: u64,
+    /// RFC 3339
+    pub time: String,
+    pub state: StateReport,
+}
+
+/// Lens with different focal powers along two perpendicular axes (e.g. a cylindrical
+/// lens), added to the fresnel; powers are in the units of `fresnel`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct AstigmaticFresnel {
+    pub power_xy: (f32, f32),
+    /// Counterclockwise rotation of the axes from x and y, in degrees
+    #[serde(default)]
+    pub rotation_deg: f32,
+}
+
+/// Operation on custom patterns, by file name, with phases in radians
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PatternOperation {
+    Add {
+        a: String,
+        b: String,
+    },
+    /// `a - b`
+    Subtract {
+        a: String,
+        b: String,
+    },
+    Scale {
+        pattern: String,
+        factor: f32,
+    },
+    /// Into [0, 2 pi)
+    Wrap {
+        pattern: String,
+    },
+    /// `high` where the phase is at least `level`, `low` elsewhere
+    Threshold {
+        pattern: String,
+        level: f32,
+        low: f32,
+        high: f32,
+    },
+}
+
+/// A rectangular region of the panel (e.g. a damaged area)
+/// that is held at a constant phase instead of the computed pattern
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct DefectMask {
+    pub name: String,
+    pub position_xy: (usize, usize),
+    pub size_xy: (usize, usize),
+    pub phase: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct CorrectionPatternDeltas {
+    pub wavelength: u32,
+    pub imagedata: BinaryData,
+    pub shape_xy: [usize; 2],
+    /// Recompute the displayed pattern right away if it uses this wavelength
+    #[serde(default)]
+    pub recompute: bool,
+}
+
+/// Raw data, a base64 string in JSON and a byte string in the binary encodings
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryData {
+    Base64(String),
+    Bytes(Vec<u8>),
+}
+
+struct BinaryDataVisitor {}
+
+impl<'de> Visitor<'de> for BinaryDataVisitor {
+    type Value = BinaryData;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a base64 string or a byte string")
+    }
+
+    fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(BinaryData::Base64(value.to_owned()))
+    }
+
+    fn visit_string<E: Error>(self, value: String) -> Result<Self::Value, E> {
+        Ok(BinaryData::Base64(value))
+    }
+
+    fn visit_bytes<E: Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        Ok(BinaryData::Bytes(value.to_owned()))
+    }
+
+    fn visit_byte_buf<E: Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(BinaryData::Bytes(value))
+    }
+}
+
+impl<'de> Deserialize<'de> for BinaryData {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(BinaryDataVisitor {})
+    }
+}
+
+impl Serialize for BinaryData {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            BinaryData::Base64(base64) => serializer.serialize_str(base64),
+            BinaryData::Bytes(bytes) => serializer.serialize_bytes(bytes),
+        }
+    }
+}
+
+#[cfg(feature = "json-schema")]
+impl JsonSchema for BinaryData {
+    fn schema_name() -> String {
+        "BinaryData".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct SensorReading {
+    pub name: String,
+    pub celsius: Option<f32>,
+}
+
+/// Identifies the controller build a microscope is running
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct BuildInfo {
+    pub version: String,
+    /// `git describe` of the source tree
+    pub git: String,
+    pub build_date: String,
+    pub protocol_version: String,
+}
+
+/// Effective configuration, published once at startup so misconfigurations
+/// are obvious right away
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct StartupSummary {
+    pub build: BuildInfo,
+    pub directories: Vec<DirectoryStatus>,
+    /// Panel size from the config
+    pub screen_size: (u32, u32),
+    /// Display mode the video backend actually got; `None` for serial panels
+    pub screen_mode: Option<ScreenMode>,
+    /// Wavelengths with calibration data
+    pub wavelengths: Vec<u32>,
+    /// Flatness corrections and base patterns whose size isn't the screen size
+    #[serde(default)]
+    pub size_mismatches: Vec<SizeMismatch>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct SizeMismatch {
+    pub path: String,
+    pub size: (u32, u32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct DirectoryStatus {
+    pub name: String,
+    /// Absolute path
+    pub path: String,
+    pub exists: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct ScreenMode {
+    pub size: (u32, u32),
+    pub refresh_hz: i32,
+}
+
+/// Where the pixel the position-bearing fields (spots, annuli, knife edges, scans)
+/// are measured from lies on the panel
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// The top-left pixel, or the bottom-left one if the y axis points up
+    #[default]
+    Corner,
+    Center,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum YAxis {
+    #[default]
+    Down,
+    Up,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Units {
+    #[default]
+    Pixels,
+    /// Converted with the pixel pitch of the `SlmGeometry`
+    Micrometers,
+}
+
+/// Coordinate convention of all positions, sizes and gradients of computed patterns;
+/// angles are counterclockwise in this system, so they flip on the panel with the y axis
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct CoordinateSystem {
+    #[serde(default)]
+    pub origin: Origin,
+    #[serde(default)]
+    pub y_axis: YAxis,
+    #[serde(default)]
+    pub units: Units,
+}
+
+/// Pixels of the panel that are illuminated, e.g. a smaller region on a large panel
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct ActiveArea {
+    pub offset_xy: (u32, u32),
+    pub size_xy: (u32, u32),
+}
+
+fn default_gray_level_step() -> u8 {
+    1
+}
+
+fn default_pixel_pitch() -> (f32, f32) {
+    (12.5, 12.5)
+}
+
+fn default_fill_factor() -> f32 {
+    1.0
+}
+
+/// Physical layout of the panel
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct SlmGeometry {
+    /// Center to center distance of the pixels along x and y, in micrometers
+    #[serde(default = "default_pixel_pitch")]
+    pub pixel_pitch_um: (f32, f32),
+    /// The whole panel if not set
+    #[serde(default)]
+    pub active_area: Option<ActiveArea>,
+    /// Fraction of the pixel area that modulates the light
+    #[serde(default = "default_fill_factor")]
+    pub fill_factor: f32,
+}
+
+impl Default for SlmGeometry {
+    fn default() -> Self {
+        SlmGeometry {
+            pixel_pitch_um: default_pixel_pitch(),
+            active_area: None,
+            fill_factor: default_fill_factor(),
+        }
+    }
+}
+
+/// What a controller supports, so clients don't have to assume it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct Capabilities {
+    pub coordinates: CoordinateSystem,
+    /// Panel size in pixels
+    pub panel_size: (u32, u32),
+    #[serde(default)]
+    pub geometry: SlmGeometry,
+}
+
+/// A parameter clients can set at runtime, described so a GUI can generate
+/// a control for it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct Parameter {
+    pub name: String,
+    /// Device the command is sent to, if not `aim`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Command setting the parameter, as named in the `command` field
+    pub command: String,
+    /// Field of the command holding the value; fields of nested objects are
+    /// separated by dots, and elements of pairs are numbered from 0
+    pub field: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(flatten)]
+    pub value: ParameterValue,
+}
+
+/// Type of a parameter, with its range and the value it has after startup
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParameterValue {
+    Integer {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<i64>,
+        default: i64,
+    },
+    Number {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<f64>,
+        default: f64,
+    },
+    Boolean {
+        default: bool,
+    },
+    /// One of the given strings; `null` by default if `default` is missing
+    Choice {
+        choices: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        default: Option<String>,
+    },
+}
+
+/// Published periodically, independent of any requests
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct StatusReport {
+    pub temperatures: Vec<SensorReading>,
+    /// Number of received messages waiting to be processed
+    #[serde(default)]
+    pub backlog: usize,
+    #[serde(default)]
+    pub build: BuildInfo,
+    /// Whether burn-in protection replaced the pattern because no commands arrived
+    #[serde(default)]
+    pub idle: bool,
+    #[serde(default)]
+    pub health: SystemHealth,
+    /// Processing of each command received within the metrics window
+    #[serde(default)]
+    pub commands: Vec<CommandMetrics>,
+}
+
+/// How many of a command were processed recently, and how long they took
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct CommandMetrics {
+    /// As named in the `command` field
+    pub command: String,
+    pub count: usize,
+    /// Of `count`, how many failed
+    pub errors: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+}
+
+/// Resources of the computer running the controller; values that can't be
+/// read are left out
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct SystemHealth {
+    /// Free space on the partition holding the base patterns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_free_bytes: Option<u64>,
+    /// Load average over the last minute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_average: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_used_percent: Option<f32>,
+    /// Total size of the log files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_bytes: Option<u64>,
+    /// `false` if the clock isn't synchronized to a time server or is off by
+    /// more than the configured limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_synchronized: Option<bool>,
+    /// Offset of the system clock from the time server, positive if it's ahead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_offset_ms: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct HistogramBin {
+    /// Upper bound of the bin in milliseconds, `None` for the overflow bin
+    pub upper_ms: Option<f64>,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct StageLatency {
+    pub stage: String,
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+    pub histogram: Vec<HistogramBin>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct LatencyReport {
+    pub iterations: u32,
+    pub stages: Vec<StageLatency>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct PatternStats {
+    /// Number of pixels at each of the 256 gray levels of the displayed pattern
+    pub histogram: Vec<u32>,
+    /// Fraction of pixels whose phase was outside [0, 2 pi) before wrapping
+    pub wrapped_fraction: f32,
+    /// Phase range in radians before wrapping
+    pub phase_min: f32,
+    pub phase_max: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct CorrectionDeltaResult {
+    pub wavelength: u32,
+    pub success: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseCode {
+    PrestackDone,
+    ProbeSequenceDone,
+    FresnelSweepDone,
+    MorphDone,
+    ScanDone,
+    RegistrationDone,
+    GrayLevelsDone,
+    BatchDone,
+    UpdateInstalled,
+    BackupRestored,
+    Maintenance,
+}
+
+impl ResponseCode {
+    /// English text sent along with the code
+    pub fn text(self) -> &'static str {
+        match self {
+            ResponseCode::PrestackDone => "PreStack done",
+            ResponseCode::ProbeSequenceDone => "Probe sequence done",
+            ResponseCode::FresnelSweepDone => "Fresnel sweep done",
+            ResponseCode::MorphDone => "Morph done",
+            ResponseCode::ScanDone => "Scan done",
+            ResponseCode::RegistrationDone => "Registration done",
+            ResponseCode::GrayLevelsDone => "Gray level sequence done",
+            ResponseCode::BatchDone => "Batch done",
+            ResponseCode::UpdateInstalled => "Update installed; rebooting",
+            ResponseCode::BackupRestored => "Backup restored; restart to apply all of the config",
+            ResponseCode::Maintenance => "maintenance; not applied",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum WarningCode {
+    SpotDiameterClamped,
+    SpotPositionClamped,
+}
+
+/// Probe patterns shown one after another for aberration measurements,
+/// on top of the current pattern
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProbeSequence {
+    /// Uniform phase offsets of 2 pi k / steps, for phase stepping interferometry
+    PhaseSteps { steps: u32 },
+    /// Each cell of a grid over the panel in turn gets an additional tilt
+    SubApertures {
+        grid_xy: (u32, u32),
+        /// Tilt in radians per pixel
+        tilt_xy: (f32, f32),
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SampleType {
+    U8,
+    U16,
+    F32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Layout and scaling of uploaded phase samples, e.g. quantitative phase
+/// masks from analysis software; samples go row by row
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct PhaseSamples {
+    /// Width and height
+    pub size: (u32, u32),
+    pub sample_type: SampleType,
+    #[serde(default)]
+    pub endianness: Endianness,
+    /// Sample values mapped linearly onto `phase_range`; if not given, 0 to 256
+    /// for u8 and 0 to 65536 for u16 as for images, and radians for f32
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_range: Option<(f32, f32)>,
+    /// In radians, e.g. 0 to 4 pi for masks spanning two waves
+    #[serde(default = "default_phase_range")]
+    pub phase_range: (f32, f32),
+}
+
+fn default_phase_range() -> (f32, f32) {
+    (0.0, std::f32::consts::TAU)
+}
+
+impl PhaseSamples {
+    pub fn value_range(&self) -> (f32, f32) {
+        self.value_range.unwrap_or(match self.sample_type {
+            SampleType::U8 => (0.0, 256.0),
+            SampleType::U16 => (0.0, 65536.0),
+            SampleType::F32 => (0.0, std::f32::consts::TAU),
+        })
+    }
+}
+
+/// Progress of a sequence, kept on disk so it can be resumed after a restart
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct SequenceCheckpoint {
+    /// Command that started the sequence
+    pub sequence: Box<AimCommand>,
+    /// Step on the panel when the checkpoint was written; it's shown again
+    /// on resuming, since its acquisition may not have finished
+    pub step: u32,
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ScheduledAction {
+    /// Show a constant zero pattern until the next pattern is set
+    Blank,
+    ReloadCalibration,
+    ApplyPreset {
+        preset: String,
+    },
+}
+
+/// Action run every day at a fixed local time
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct ScheduledTask {
+    pub name: String,
+    /// Local time of day as "HH:MM"
+    pub time: String,
+    #[serde(flatten)]
+    pub action: ScheduledAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct Waypoint {
+    pub position_xy: (f32, f32),
+    pub dwell_ms: u64,
+}
+
+/// Path the active spot is moved along, in the controller's coordinate system
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScanTrajectory {
+    /// Row by row from `from_xy` to `to_xy`
+    Raster {
+        from_xy: (f32, f32),
+        to_xy: (f32, f32),
+        step: f32,
+    },
+    /// Archimedean spiral outwards from `center_xy`, with `step` both
+    /// between neighbouring points and between turns
+    Spiral {
+        center_xy: (f32, f32),
+        max_radius: f32,
+        step: f32,
+    },
+}
+
+/// Type all values of a pattern property parse as
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum PropertyKind {
+    Int,
+    Float,
+    #[default]
+    String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct APatternProp {
+    pub values: Vec<String>,
+    #[serde(default)]
+    pub kind: PropertyKind,
+    /// Range of the values of a numeric property
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+impl APatternProp {
+    /// Infer the kind and range from the values
+    pub fn infer_kind(&mut self) {
+        let numbers: Option<Vec<f64>> = self
+            .values
+            .iter()
+            .map(|value| value.parse::<f64>().ok().filter(|x| x.is_finite()))
+            .collect();
+        let numbers = match numbers {
+            Some(numbers) if !numbers.is_empty() => numbers,
+            _ => {
+                self.kind = PropertyKind::String;
+                self.min = None;
+                self.max = None;
+                return;
+            }
+        };
+        self.kind = if self.values.iter().all(|value| value.parse::<i64>().is_ok()) {
+            PropertyKind::Int
+        } else {
+            PropertyKind::Float
+        };
+        self.min = numbers.iter().copied().reduce(f64::min);
+        self.max = numbers.iter().copied().reduce(f64::max);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct APattern {
+    #[serde(flatten)]
+    pub property_values: HashMap<String, APatternProp>,
+    pub properties: Vec<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct AvailablePatterns {
+    #[serde(flatten)]
+    pub patterns: HashMap<String, APattern>,
+    #[serde(rename = "patternNames")]
+    pub pattern_names: Vec<String>,
+}
+
+/// Part of the custom patterns in an `availablePatterns` reply
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct PatternPage {
+    pub offset: usize,
+    /// Custom patterns matching the prefix, on all pages
+    pub total: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "command")]
+pub enum AimCommand {
+    #[serde(rename = "get")]
+    Get,
+    /// Without any of the fields, the whole library is sent as before;
+    /// `offset` and `limit` page through the custom patterns, and `prefix`
+    /// filters both pattern names and custom pattern files
+    #[serde(rename = "getAllPatterns")]
+    GetAllPatterns {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prefix: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
+    #[serde(rename = "set")]
+    Set(AimState),
+    PreStack(AimState),
+    #[serde(rename = "setpattern")]
+    SetPattern {
+        pattern: PatternParams,
+        /// As for `set`
+        #[serde(default, skip_serializing_if = "is_false")]
+        raw: bool,
+    },
+    /// Run the commands in order, publishing the state and showing the pattern
+    /// once at the end; if one fails, the state from before the batch is restored
+    #[serde(rename = "batch")]
+    Batch {
+        commands: Vec<AimCommand>,
+    },
+    /// Steer the beam by tilt angles on top of the blaze, and optionally defocus it
+    #[serde(rename = "steerBeam")]
+    SteerBeam {
+        tilt_x_mrad: f32,
+        tilt_y_mrad: f32,
+        /// Focal power in diopters, like `fresnel`; left unchanged if not given
+        #[serde(default)]
+        defocus: Option<u32>,
+    },
+    /// Set, or clear with `null`, the astigmatic part of the fresnel
+    #[serde(rename = "setAstigmaticFresnel")]
+    SetAstigmaticFresnel {
+        fresnel: Option<AstigmaticFresnel>,
+    },
+    #[serde(rename = "setfresnel")]
+    SetFresnel {
+        value: u32,
+    },
+    /// Reduce first-order power by scaling down the phase modulation depth
+    #[serde(rename = "setattenuation")]
+    SetAttenuation {
+        percent: f32,
+    },
+    #[serde(rename = "setDiffractionOrder")]
+    SetDiffractionOrder {
+        order: DiffractionOrder,
+    },
+    #[serde(rename = "setprofile")]
+    SetProfile {
+        name: String,
+    },
+    #[serde(rename = "response")]
+    Response {
+        code: ResponseCode,
+        /// Human-readable text for display only; clients should match on `code`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply: Option<String>,
+    },
+    #[serde(rename = "warning")]
+    Warning {
+        /// Identifies warnings clients may want to handle, e.g. clamped parameters
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<WarningCode>,
+        message: String,
+    },
+    /// Acknowledges a command that carried a sequence number
+    #[serde(rename = "ack")]
+    Ack {
+        seq: u64,
+        duplicate: bool,
+    },
+    /// A request failed; `code` identifies the error category
+    #[serde(rename = "error")]
+    Error {
+        code: u32,
+        category: String,
+        message: String,
+        /// Sequence number of the failed command, if it carried one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    /// `imagedata` is a `data:` URI with base64 data, or just the base64
+    #[serde(rename = "uploadimage")]
+    UploadImage {
+        name: String,
+        imagedata: String,
+        /// Extension the image is saved with, e.g. `png`; taken from the
+        /// `data:` URI or the image itself if not given
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        extension: Option<String>,
+        /// If given, `imagedata` holds raw phase samples instead of an image
+        /// file; they're saved as an npy file of the phase in radians
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        samples: Option<PhaseSamples>,
+    },
+    #[serde(rename = "deleteimage")]
+    DeleteImage {
+        name: String,
+    },
+    /// Apply `operation` and save the result as the custom pattern `output`
+    /// (an npy file, so the phase isn't quantized)
+    #[serde(rename = "derivePattern")]
+    DerivePattern {
+        operation: PatternOperation,
+        output: String,
+    },
+    #[serde(rename = "disconnect")]
+    Disconnect,
+    #[serde(rename = "setCorrectionPatternDeltas")]
+    SetCorrectionPatternDeltas(CorrectionPatternDeltas),
+    // Skip deserializing, because it has the same name as setCorrectionPatternDeltas
+    #[serde(rename = "setCorrectionPatternDeltas", skip_deserializing)]
+    SetCorrectionPatternDeltasResponse {
+        wavelength: u32,
+        success: bool,
+    },
+    /// Deltas for several wavelengths, saved only if all of them are valid
+    #[serde(rename = "setCorrectionPatternDeltasBatch")]
+    SetCorrectionPatternDeltasBatch {
+        deltas: Vec<CorrectionPatternDeltas>,
+    },
+    #[serde(rename = "setCorrectionPatternDeltasBatch", skip_deserializing)]
+    SetCorrectionPatternDeltasBatchResponse {
+        results: Vec<CorrectionDeltaResult>,
+        applied: bool,
+    },
+    #[serde(rename = "availablePatterns")]
+    AvailablePatterns {
+        patterns: AvailablePatterns,
+        /// Set if only a part of the library was requested
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        page: Option<PatternPage>,
+    },
+    #[serde(rename = "getAvailableWavelengths")]
+    GetAvailableWavelengths,
+    #[serde(rename = "availableWavelengths")]
+    AvailableWavelengths {
+        wavelengths: Vec<u32>,
+    },
+    /// Re-read the calibration files and recompute the displayed pattern
+    #[serde(rename = "reloadCalibration")]
+    ReloadCalibration,
+    #[serde(rename = "adddefectmask")]
+    AddDefectMask(DefectMask),
+    #[serde(rename = "removedefectmask")]
+    RemoveDefectMask {
+        name: String,
+    },
+    /// Run a number of pattern updates and report how long each stage took
+    #[serde(rename = "measureLatency")]
+    MeasureLatency {
+        iterations: u32,
+        #[serde(default)]
+        photodiode: bool,
+    },
+    #[serde(rename = "latency")]
+    Latency(LatencyReport),
+    /// Show a sequence of probe patterns, advancing every `interval_ms`
+    /// or on every `nextProbe` command if no interval is given
+    #[serde(rename = "startProbeSequence")]
+    StartProbeSequence {
+        sequence: ProbeSequence,
+        #[serde(default)]
+        interval_ms: Option<u64>,
+    },
+    #[serde(rename = "nextProbe")]
+    NextProbe,
+    /// Move the active spot along the trajectory, advancing every `dwell_ms`
+    /// or on every `scanTrigger` command if `triggered` is set
+    #[serde(rename = "scanSpot")]
+    ScanSpot {
+        trajectory: ScanTrajectory,
+        dwell_ms: u64,
+        #[serde(default)]
+        triggered: bool,
+        #[serde(default)]
+        space: Space,
+    },
+    /// Move the active spot through the waypoints, starting over
+    /// after the last one if `looping` is set; controlled like `scanSpot`
+    #[serde(rename = "followWaypoints")]
+    FollowWaypoints {
+        waypoints: Vec<Waypoint>,
+        #[serde(default)]
+        looping: bool,
+        #[serde(default)]
+        space: Space,
+    },
+    #[serde(rename = "scanTrigger")]
+    ScanTrigger,
+    #[serde(rename = "pauseScan")]
+    PauseScan,
+    #[serde(rename = "resumeScan")]
+    ResumeScan,
+    #[serde(rename = "stopScan")]
+    StopScan,
+    /// Published whenever the scanned spot moves
+    #[serde(rename = "scanPosition")]
+    ScanPosition {
+        index: u32,
+        total: u32,
+        position_xy: (f32, f32),
+    },
+    /// Step the fresnel through `steps` values from `from` to `to`,
+    /// then return to the previous value
+    #[serde(rename = "fresnelSweep")]
+    FresnelSweep {
+        from: u32,
+        to: u32,
+        steps: u32,
+        dwell_ms: u64,
+    },
+    /// Published when a fresnel sweep step is on the panel
+    #[serde(rename = "sweepStep")]
+    SweepStep {
+        index: u32,
+        total: u32,
+        fresnel: u32,
+    },
+    /// Interpolate the phase linearly from `from` to `to` in `steps` steps,
+    /// e.g. to move trapped particles adiabatically; `to` is kept afterwards
+    #[serde(rename = "morph")]
+    Morph {
+        from: PatternParams,
+        to: PatternParams,
+        steps: u32,
+        dwell_ms: u64,
+    },
+    /// Published when a morph step is on the panel
+    #[serde(rename = "morphStep")]
+    MorphStep {
+        index: u32,
+        total: u32,
+    },
+    #[serde(rename = "stopProbeSequence")]
+    StopProbeSequence,
+    /// Published at startup if a probe sequence, fresnel sweep, morph or gray
+    /// level sequence was running when the controller stopped
+    #[serde(rename = "resumeAvailable")]
+    ResumeAvailable(SequenceCheckpoint),
+    /// Continue the interrupted sequence from its checkpoint
+    #[serde(rename = "resumeSequence")]
+    ResumeSequence,
+    #[serde(rename = "discardSequence")]
+    DiscardSequence,
+    /// Show uniform frames of every `step`th gray level from 0 up, advancing every
+    /// `interval_ms`, or on every `nextGrayLevel` command if not given
+    #[serde(rename = "startGrayLevels")]
+    StartGrayLevels {
+        #[serde(default = "default_gray_level_step")]
+        step: u8,
+        #[serde(default)]
+        interval_ms: Option<u64>,
+    },
+    #[serde(rename = "nextGrayLevel")]
+    NextGrayLevel,
+    #[serde(rename = "stopGrayLevels")]
+    StopGrayLevels,
+    /// Published whenever a gray level is on the panel
+    #[serde(rename = "grayLevel")]
+    GrayLevel {
+        index: u32,
+        total: u32,
+        level: u8,
+    },
+    /// Published whenever a probe pattern is on the panel
+    #[serde(rename = "probe")]
+    Probe {
+        index: u32,
+        total: u32,
+    },
+    /// Simulate the focal-plane intensity of the displayed pattern
+    #[serde(rename = "simulateFarField")]
+    SimulateFarField {
+        /// Size of the longer side of the thumbnail, in pixels
+        #[serde(default)]
+        thumbnail_size: Option<u32>,
+    },
+    #[serde(rename = "farField")]
+    FarField {
+        width: u32,
+        height: u32,
+        /// PNG data URI, log-scaled intensity
+        imagedata: String,
+    },
+    /// Add a task, replacing the one with the same name
+    #[serde(rename = "addScheduledTask")]
+    AddScheduledTask(ScheduledTask),
+    #[serde(rename = "removeScheduledTask")]
+    RemoveScheduledTask {
+        name: String,
+    },
+    #[serde(rename = "getSchedule")]
+    GetSchedule,
+    #[serde(rename = "schedule")]
+    Schedule {
+        tasks: Vec<ScheduledTask>,
+    },
+    #[serde(rename = "getPatternStats")]
+    GetPatternStats,
+    #[serde(rename = "patternStats")]
+    PatternStats(PatternStats),
+    #[serde(rename = "identify")]
+    Identify,
+    #[serde(rename = "identity")]
+    Identity(BuildInfo),
+    /// Set the mapping used for positions given in camera space
+    #[serde(rename = "setRegistration")]
+    SetRegistration(RegistrationSource),
+    #[serde(rename = "clearRegistration")]
+    ClearRegistration,
+    #[serde(rename = "getRegistration")]
+    GetRegistration,
+    #[serde(rename = "registration")]
+    Registration {
+        registration: Option<Registration>,
+    },
+    /// Show the active spot at each of `positions` (SLM coordinates; a 3x3 grid
+    /// over the panel if empty) in turn, registering the camera positions
+    /// reported back with `fiducialDetected`
+    #[serde(rename = "startFiducials")]
+    StartFiducials {
+        #[serde(default)]
+        positions: Vec<(f32, f32)>,
+        #[serde(default)]
+        homography: bool,
+    },
+    /// Published whenever a fiducial is on the panel
+    #[serde(rename = "fiducial")]
+    Fiducial {
+        index: u32,
+        total: u32,
+        slm_xy: (f32, f32),
+    },
+    /// Camera position of the fiducial on the panel, `None` if it wasn't found
+    #[serde(rename = "fiducialDetected")]
+    FiducialDetected {
+        camera_xy: Option<(f32, f32)>,
+    },
+    #[serde(rename = "stopFiducials")]
+    StopFiducials,
+    /// Published when the SLM output changed size (e.g. after a cable reseat);
+    /// patterns are computed for the new size from then on
+    #[serde(rename = "displayChanged")]
+    DisplayChanged {
+        size: (u32, u32),
+        previous_size: (u32, u32),
+    },
+    /// Published when burn-in protection starts, and when the next command ends it
+    #[serde(rename = "idle")]
+    Idle {
+        idle: bool,
+    },
+    #[serde(rename = "getCapabilities")]
+    GetCapabilities,
+    #[serde(rename = "capabilities")]
+    Capabilities(Capabilities),
+    /// Describe the parameters settable at runtime
+    #[serde(rename = "getParameterSchema")]
+    GetParameterSchema,
+    #[serde(rename = "parameterSchema")]
+    ParameterSchema {
+        parameters: Vec<Parameter>,
+    },
+    /// Reject state-changing commands from other clients until released
+    #[serde(rename = "acquireControl")]
+    AcquireControl,
+    #[serde(rename = "releaseControl")]
+    ReleaseControl,
+    /// While on, laser messages, `initdone` and `set`/`PreStack` from clients
+    /// not holding control are answered but not applied
+    #[serde(rename = "setMaintenanceMode")]
+    SetMaintenanceMode {
+        on: bool,
+    },
+    #[serde(rename = "startup")]
+    Startup(StartupSummary),
+    /// Save the displayed pattern and a JSON sidecar with the settings into the
+    /// session directory, as a record of an acquisition; answered with `frameCaptured`
+    #[serde(rename = "captureFrame")]
+    CaptureFrame {
+        label: String,
+    },
+    /// `path` is that of the saved image; the sidecar has the `.json` extension
+    #[serde(rename = "frameCaptured")]
+    FrameCaptured {
+        label: String,
+        path: String,
+    },
+    /// Archive the custom patterns, flatness corrections, calibration store and
+    /// config (with the presets) into the backup directory; answered with
+    /// `backupCreated`, followed by the archive in `backupChunk`s if `publish`
+    #[serde(rename = "createBackup")]
+    CreateBackup {
+        #[serde(default)]
+        publish: bool,
+    },
+    /// `name` is that of the archive in the backup directory
+    #[serde(rename = "backupCreated")]
+    BackupCreated {
+        name: String,
+        size: u64,
+        /// Number of `backupChunk`s that follow, 0 unless published
+        chunks: u32,
+    },
+    #[serde(rename = "backupChunk")]
+    BackupChunk {
+        name: String,
+        index: u32,
+        total: u32,
+        data: BinaryData,
+    },
+    /// Unpack an archive from the backup directory over the current files and
+    /// reload the calibration; existing factory files are kept. Of the config,
+    /// only the presets apply before a restart
+    #[serde(rename = "restoreBackup")]
+    RestoreBackup {
+        name: String,
+    },
+    #[serde(rename = "reboot")]
+    Reboot,
+    /// Replace the controller binary with the one at `url` and reboot;
+    /// it's only installed if its SHA-256 (hex) matches
+    #[serde(rename = "update")]
+    Update {
+        url: String,
+        sha256: String,
+    },
+    /// Sent every few seconds while an update downloads
+    #[serde(rename = "updateProgress")]
+    UpdateProgress {
+        downloaded_bytes: u64,
+    },
+    #[serde(rename = "state")]
+    State(Box<StateReport>),
+    #[serde(rename = "stateEvent")]
+    StateEvent(Box<StateEvent>),
+    #[serde(rename = "status")]
+    Status(Box<StatusReport>),
+}
+
+impl AimCommand {
+    /// The `command` tag this command is sent with
+    pub fn name(&self) -> &'static str {
+        match self {
+            AimCommand::Get => "get",
+            AimCommand::GetAllPatterns { .. } => "getAllPatterns",
+            AimCommand::Set(_) => "set",
+            AimCommand::PreStack(_) => "PreStack",
+            AimCommand::SetPattern { .. } => "setpattern",
+            AimCommand::Batch { .. } => "batch",
+            AimCommand::SteerBeam { .. } => "steerBeam",
+            AimCommand::SetAstigmaticFresnel { .. } => "setAstigmaticFresnel",
+            AimCommand::SetFresnel { .. } => "setfresnel",
+            AimCommand::SetAttenuation { .. } => "setattenuation",
+            AimCommand::SetDiffractionOrder { .. } => "setDiffractionOrder",
+            AimCommand::SetProfile { .. } => "setprofile",
+            AimCommand::Response { .. } => "response",
+            AimCommand::Warning { .. } => "warning",
+            AimCommand::Ack { .. } => "ack",
+            AimCommand::Error { .. } => "error",
+            AimCommand::UploadImage { .. } => "uploadimage",
+            AimCommand::DeleteImage { .. } => "deleteimage",
+            AimCommand::DerivePattern { .. } => "derivePattern",
+            AimCommand::Disconnect => "disconnect",
+            AimCommand::SetCorrectionPatternDeltas(_)
+            | AimCommand::SetCorrectionPatternDeltasResponse { .. } => "setCorrectionPatternDeltas",
+            AimCommand::SetCorrectionPatternDeltasBatch { .. }
+            | AimCommand::SetCorrectionPatternDeltasBatchResponse { .. } => {
+                "setCorrectionPatternDeltasBatch"
+            }
+            AimCommand::AvailablePatterns { .. } => "availablePatterns",
+            AimCommand::GetAvailableWavelengths => "getAvailableWavelengths",
+            AimCommand::AvailableWavelengths { .. } => "availableWavelengths",
+            AimCommand::ReloadCalibration => "reloadCalibration",
+            AimCommand::AddDefectMask(_) => "adddefectmask",
+            AimCommand::RemoveDefectMask { .. } => "removedefectmask",
+            AimCommand::MeasureLatency { .. } => "measureLatency",
+            AimCommand::Latency(_) => "latency",
+            AimCommand::StartProbeSequence { .. } => "startProbeSequence",
+            AimCommand::NextProbe => "nextProbe",
+            AimCommand::ScanSpot { .. } => "scanSpot",
+            AimCommand::FollowWaypoints { .. } => "followWaypoints",
+            AimCommand::ScanTrigger => "scanTrigger",
+            AimCommand::PauseScan => "pauseScan",
+            AimCommand::ResumeScan => "resumeScan",
+            AimCommand::StopScan => "stopScan",
+            AimCommand::ScanPosition { .. } => "scanPosition",
+            AimCommand::FresnelSweep { .. } => "fresnelSweep",
+            AimCommand::SweepStep { .. } => "sweepStep",
+            AimCommand::Morph { .. } => "morph",
+            AimCommand::MorphStep { .. } => "morphStep",
+            AimCommand::StopProbeSequence => "stopProbeSequence",
+            AimCommand::ResumeAvailable(_) => "resumeAvailable",
+            AimCommand::ResumeSequence => "resumeSequence",
+            AimCommand::DiscardSequence => "discardSequence",
+            AimCommand::StartGrayLevels { .. } => "startGrayLevels",
+            AimCommand::NextGrayLevel => "nextGrayLevel",
+            AimCommand::StopGrayLevels => "stopGrayLevels",
+            AimCommand::GrayLevel { .. } => "grayLevel",
+            AimCommand::Probe { .. } => "probe",
+            AimCommand::SimulateFarField { .. } => "simulateFarField",
+            AimCommand::FarField { .. } => "farField",
+            AimCommand::AddScheduledTask(_) => "addScheduledTask",
+            AimCommand::RemoveScheduledTask { .. } => "removeScheduledTask",
+            AimCommand::GetSchedule => "getSchedule",
+            AimCommand::Schedule { .. } => "schedule",
+            AimCommand::GetPatternStats => "getPatternStats",
+            AimCommand::PatternStats(_) => "patternStats",
+            AimCommand::Identify => "identify",
+            AimCommand::Identity(_) => "identity",
+            AimCommand::SetRegistration(_) => "setRegistration",
+            AimCommand::ClearRegistration => "clearRegistration",
+            AimCommand::GetRegistration => "getRegistration",
+            AimCommand::Registration { .. } => "registration",
+            AimCommand::StartFiducials { .. } => "startFiducials",
+            AimCommand::Fiducial { .. } => "fiducial",
+            AimCommand::FiducialDetected { .. } => "fiducialDetected",
+            AimCommand::StopFiducials => "stopFiducials",
+            AimCommand::DisplayChanged { .. } => "displayChanged",
+            AimCommand::Idle { .. } => "idle",
+            AimCommand::GetCapabilities => "getCapabilities",
+            AimCommand::Capabilities(_) => "capabilities",
+            AimCommand::GetParameterSchema => "getParameterSchema",
+            AimCommand::ParameterSchema { .. } => "parameterSchema",
+            AimCommand::AcquireControl => "acquireControl",
+            AimCommand::ReleaseControl => "releaseControl",
+            AimCommand::SetMaintenanceMode { .. } => "setMaintenanceMode",
+            AimCommand::Startup(_) => "startup",
+            AimCommand::CaptureFrame { .. } => "captureFrame",
+            AimCommand::FrameCaptured { .. } => "frameCaptured",
+            AimCommand::CreateBackup { .. } => "createBackup",
+            AimCommand::BackupCreated { .. } => "backupCreated",
+            AimCommand::BackupChunk { .. } => "backupChunk",
+            AimCommand::RestoreBackup { .. } => "restoreBackup",
+            AimCommand::Reboot => "reboot",
+            AimCommand::Update { .. } => "update",
+            AimCommand::UpdateProgress { .. } => "updateProgress",
+            AimCommand::State(_) => "state",
+            AimCommand::StateEvent(_) => "stateEvent",
+            AimCommand::Status(_) => "status",
+        }
+    }
+
+    /// Whether the command only reads state, so it's accepted while
+    /// another client holds control
+    pub fn is_query(&self) -> bool {
+        match self {
+            AimCommand::Get
+            | AimCommand::GetAllPatterns { .. }
+            | AimCommand::GetAvailableWavelengths
+            | AimCommand::GetSchedule
+            | AimCommand::GetPatternStats
+            | AimCommand::Identify
+            | AimCommand::GetRegistration
+            | AimCommand::GetCapabilities
+            | AimCommand::GetParameterSchema
+            | AimCommand::CaptureFrame { .. } => true,
+            AimCommand::Batch { commands } => commands.iter().all(AimCommand::is_query),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "command")]
+pub enum EmbeddedCommand {
+    #[serde(rename = "initdone")]
+    InitDone,
+    #[serde(rename = "set")]
+    Set,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(tag = "device", rename_all = "snake_case")]
+pub enum MessageData {
+    Embedded(EmbeddedCommand),
+    Lasers(LaserCommand),
+    Aim(AimCommand),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct Message {
+    #[serde(rename = "type")]
+    pub m_type: MessageType, // type is a Rust keyword
+    pub data: MessageData,
+    /// Optional monotonic sequence number of the sender, used to detect
+    /// redelivered and out-of-order commands; counted per topic and `client`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// Reject the command unless the controller is in this state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<Precondition>,
+    /// Identifies the sending client, for `acquireControl`, `seq` and the
+    /// journal of state changes
+    #[serde(default, alias = "origin", skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+}
+
+/// State a command expects the controller to be in, so clients don't
+/// overwrite each other's changes
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct Precondition {
+    #[serde(default)]
+    pub wavelength: Option<u32>,
+    /// `pattern_hash` of the last state report
+    #[serde(default)]
+    pub pattern_hash: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct SpotPattern {
+    /// Center in the controller's coordinate system (see `Capabilities`),
+    /// fractions of a pixel included
+    pub position_xy: (f32, f32),
+    /// Diameter of a round spot, or the major axis of an elliptical one
+    pub diameter: f32,
+    /// Minor axis of an elliptical spot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minor_diameter: Option<f32>,
+    /// Counterclockwise rotation of the major axis from the x axis, in degrees
+    #[serde(default)]
+    pub rotation_deg: f32,
+    pub gradient_xy: (f32, f32),
+    /// Counterclockwise rotation of the gradient inside the spot, in degrees
+    #[serde(default)]
+    pub gradient_rotation_deg: f32,
+    pub background_gradient_xy: (f32, f32),
+}
+
+/// A ring around `position_xy`; the hole and the area outside get the background gradient
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct AnnulusPattern {
+    pub position_xy: (f32, f32),
+    pub inner_diameter: f32,
+    pub outer_diameter: f32,
+    pub gradient_xy: (f32, f32),
+    pub background_gradient_xy: (f32, f32),
+}
+
+/// A straight edge through `position_xy`; the half-plane to the left of the edge
+/// (looking along `orientation_deg`) is shifted by `phase_step`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct KnifeEdgePattern {
+    pub position_xy: (f32, f32),
+    /// Counterclockwise angle of the edge from the x axis, in degrees
+    pub orientation_deg: f32,
+    /// Phase step height in radians
+    pub phase_step: f32,
+}
+
+/// A complex field, stored as separate amplitude and phase files in the base patterns directory
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct ComplexPattern {
+    pub amplitude: String,
+    pub phase: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+pub struct CustomPattern {
+    pub filename: String,
+}
+
+struct BasePatternVistior {}
+
+impl<'de> Visitor<'de> for BasePatternVistior {
+    type Value = BasePattern;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a very special map")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let entry: (String, HashMap<String, String>) = map
+            .next_entry()?
+            .ok_or(Error::custom("empty base pattern"))?;
+        Ok(BasePattern {
+            filename: entry.0.clone(),
+            properties: entry.1,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for BasePattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(BasePatternVistior {})
+    }
+}
+/// Sent as a single-entry map `{ filename: { property: value, .. } }`
+#[derive(Debug, Clone)]
+pub struct BasePattern {
+    pub filename: String,
+    pub properties: HashMap<String, String>,
+}
+
+impl Serialize for BasePattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(&self.filename, &self.properties)?;
+        map.end()
+    }
+}
+
+#[cfg(feature = "json-schema")]
+impl JsonSchema for BasePattern {
+    fn schema_name() -> String {
+        "BasePattern".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        <HashMap<String, HashMap<String, String>>>::json_schema(gen)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[cfg_attr(feature = "json-schema", derive(JsonSchema))]
+#[serde(untagged)]
+pub enum PatternParams {
+    Spot {
+        spot: SpotPattern,
+    },
+    Annulus {
+        annulus: AnnulusPattern,
+    },
+    KnifeEdge {
+        knife_edge: KnifeEdgePattern,
+    },
+    Complex {
+        complex: ComplexPattern,
+    },
+    Custom {
+        custom: CustomPattern,
+    },
+    Base {
+        #[serde(flatten)]
+        base: BasePattern,
+    },
+}
+
+/// JSON Schema of `Message`, for clients that validate or generate code from it
+#[cfg(feature = "json-schema")]
+pub fn json_schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(Message)
+}
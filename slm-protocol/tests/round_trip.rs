@@ -0,0 +1,889 @@
+use serde::{
+    de::{value::BytesDeserializer, DeserializeOwned},
+    Deserialize, Serialize,
+};
+use serde_json::{json, Value};
+
+use slm_protocol::*;
+
+/// Serialize, parse back and serialize again; both encodings have to match
+fn round_trip<T: Serialize + DeserializeOwned>(value: &T) -> Value {
+    let encoded = serde_json::to_value(value).unwrap();
+    let decoded: T = serde_json::from_value(encoded.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&decoded).unwrap(), encoded);
+    encoded
+}
+
+fn aim_message(command: AimCommand) -> Message {
+    Message {
+        m_type: MessageType::Device,
+        seq: None,
+        expect: None,
+        client: None,
+        data: MessageData::Aim(command),
+    }
+}
+
+fn spot() -> PatternParams {
+    PatternParams::Spot {
+        spot: SpotPattern {
+            position_xy: (100.0, 200.0),
+            diameter: 50.0,
+            minor_diameter: None,
+            rotation_deg: 0.0,
+            gradient_xy: (0.1, 0.2),
+            gradient_rotation_deg: 0.0,
+            background_gradient_xy: (0.0, 0.0),
+        },
+    }
+}
+
+#[test]
+fn round_spot_without_ellipse_fields() {
+    let spot: SpotPattern = serde_json::from_value(json!({
+        "position_xy": [100.0, 200.0],
+        "diameter": 50.0,
+        "gradient_xy": [0.1, 0.2],
+        "background_gradient_xy": [0.0, 0.0]
+    }))
+    .unwrap();
+    assert!(spot.minor_diameter.is_none());
+    assert_eq!(spot.rotation_deg, 0.0);
+}
+
+fn base() -> PatternParams {
+    PatternParams::Base {
+        base: BasePattern {
+            filename: "grating".to_owned(),
+            properties: vec![("period".to_owned(), "20".to_owned())]
+                .into_iter()
+                .collect(),
+        },
+    }
+}
+
+#[test]
+fn pattern_params() {
+    round_trip(&spot());
+    round_trip(&PatternParams::Spot {
+        spot: SpotPattern {
+            position_xy: (100.0, 200.0),
+            diameter: 80.0,
+            minor_diameter: Some(20.0),
+            rotation_deg: 30.0,
+            gradient_xy: (0.1, 0.0),
+            gradient_rotation_deg: 45.0,
+            background_gradient_xy: (0.0, 0.0),
+        },
+    });
+    round_trip(&base());
+    round_trip(&PatternParams::Annulus {
+        annulus: AnnulusPattern {
+            position_xy: (640.0, 512.0),
+            inner_diameter: 100.0,
+            outer_diameter: 300.0,
+            gradient_xy: (0.1, 0.0),
+            background_gradient_xy: (0.0, 0.1),
+        },
+    });
+    round_trip(&PatternParams::KnifeEdge {
+        knife_edge: KnifeEdgePattern {
+            position_xy: (640.0, 512.0),
+            orientation_deg: 90.0,
+            phase_step: std::f32::consts::PI,
+        },
+    });
+    round_trip(&PatternParams::Complex {
+        complex: ComplexPattern {
+            amplitude: "field_amplitude.npy".to_owned(),
+            phase: "field_phase.npy".to_owned(),
+        },
+    });
+    round_trip(&PatternParams::Custom {
+        custom: CustomPattern {
+            filename: "donut.png".to_owned(),
+        },
+    });
+}
+
+#[test]
+fn base_pattern_is_a_single_entry_map() {
+    assert_eq!(
+        round_trip(&base()),
+        json!({ "grating": { "period": "20" } })
+    );
+}
+
+#[test]
+fn aim_commands() {
+    let commands = vec![
+        AimCommand::Get,
+        AimCommand::GetAllPatterns {
+            prefix: None,
+            offset: None,
+            limit: None,
+        },
+        AimCommand::GetAllPatterns {
+            prefix: Some("donut".to_owned()),
+            offset: Some(100),
+            limit: Some(50),
+        },
+        AimCommand::AvailablePatterns {
+            patterns: AvailablePatterns::default(),
+            page: Some(PatternPage {
+                offset: 100,
+                total: 1200,
+            }),
+        },
+        AimCommand::Set(AimState {
+            pattern: spot(),
+            fresnel: 3,
+            space: Space::Camera,
+            raw: false,
+        }),
+        AimCommand::PreStack(AimState {
+            pattern: base(),
+            fresnel: 0,
+            space: Space::Slm,
+            raw: true,
+        }),
+        AimCommand::SetPattern {
+            pattern: base(),
+            raw: true,
+        },
+        AimCommand::SetFresnel { value: 7 },
+        AimCommand::Batch {
+            commands: vec![
+                AimCommand::SetPattern {
+                    pattern: spot(),
+                    raw: false,
+                },
+                AimCommand::SetFresnel { value: 2 },
+                AimCommand::SetAttenuation { percent: 50.0 },
+            ],
+        },
+        AimCommand::SteerBeam {
+            tilt_x_mrad: 1.5,
+            tilt_y_mrad: -0.25,
+            defocus: Some(3),
+        },
+        AimCommand::SetAstigmaticFresnel {
+            fresnel: Some(AstigmaticFresnel {
+                power_xy: (2.5, -1.0),
+                rotation_deg: 30.0,
+            }),
+        },
+        AimCommand::SetAstigmaticFresnel { fresnel: None },
+        AimCommand::SetAttenuation { percent: 25.0 },
+        AimCommand::SetDiffractionOrder {
+            order: DiffractionOrder::PlusFirst,
+        },
+        AimCommand::SetProfile {
+            name: "tweezers".to_owned(),
+        },
+        AimCommand::UploadImage {
+            name: "donut".to_owned(),
+            imagedata: "data:image/png;base64,AAAA".to_owned(),
+            extension: None,
+            samples: None,
+        },
+        AimCommand::UploadImage {
+            name: "donut".to_owned(),
+            imagedata: "AAAA".to_owned(),
+            extension: Some("png".to_owned()),
+            samples: None,
+        },
+        AimCommand::UploadImage {
+            name: "aberration".to_owned(),
+            imagedata: "AAAAAA==".to_owned(),
+            extension: None,
+            samples: Some(PhaseSamples {
+                size: (2, 1),
+                sample_type: SampleType::U16,
+                endianness: Endianness::Big,
+                value_range: Some((0.0, 4096.0)),
+                phase_range: (0.0, 4.0 * std::f32::consts::PI),
+            }),
+        },
+        AimCommand::DeleteImage {
+            name: "donut.png".to_owned(),
+        },
+        AimCommand::DerivePattern {
+            operation: PatternOperation::Subtract {
+                a: "donut.png".to_owned(),
+                b: "tilt.npy".to_owned(),
+            },
+            output: "donut_flat".to_owned(),
+        },
+        AimCommand::DerivePattern {
+            operation: PatternOperation::Threshold {
+                pattern: "donut.png".to_owned(),
+                level: 3.0,
+                low: 0.0,
+                high: 2.5,
+            },
+            output: "donut_binary".to_owned(),
+        },
+        AimCommand::Disconnect,
+        AimCommand::SetCorrectionPatternDeltas(CorrectionPatternDeltas {
+            wavelength: 488,
+            imagedata: BinaryData::Base64("AAAAAA==".to_owned()),
+            shape_xy: [1, 1],
+            recompute: true,
+        }),
+        AimCommand::ReloadCalibration,
+        AimCommand::GetAvailableWavelengths,
+        AimCommand::AddDefectMask(DefectMask {
+            name: "scratch".to_owned(),
+            position_xy: (10, 20),
+            size_xy: (5, 100),
+            phase: 1.5,
+        }),
+        AimCommand::MeasureLatency {
+            iterations: 10,
+            photodiode: false,
+        },
+        AimCommand::StartProbeSequence {
+            sequence: ProbeSequence::SubApertures {
+                grid_xy: (4, 3),
+                tilt_xy: (0.1, 0.0),
+            },
+            interval_ms: Some(200),
+        },
+        AimCommand::StartProbeSequence {
+            sequence: ProbeSequence::PhaseSteps { steps: 4 },
+            interval_ms: None,
+        },
+        AimCommand::NextProbe,
+        AimCommand::Warning {
+            code: Some(WarningCode::SpotPositionClamped),
+            message: "Spot moved onto the panel".to_owned(),
+        },
+        AimCommand::ScanSpot {
+            trajectory: ScanTrajectory::Raster {
+                from_xy: (100.0, 100.0),
+                to_xy: (200.0, 150.0),
+                step: 10.0,
+            },
+            dwell_ms: 50,
+            triggered: false,
+            space: Space::Slm,
+        },
+        AimCommand::ScanSpot {
+            trajectory: ScanTrajectory::Spiral {
+                center_xy: (640.0, 512.0),
+                max_radius: 100.0,
+                step: 5.0,
+            },
+            dwell_ms: 0,
+            triggered: true,
+            space: Space::Camera,
+        },
+        AimCommand::FollowWaypoints {
+            waypoints: vec![
+                Waypoint {
+                    position_xy: (10.0, 20.0),
+                    dwell_ms: 100,
+                },
+                Waypoint {
+                    position_xy: (30.0, 40.0),
+                    dwell_ms: 250,
+                },
+            ],
+            looping: true,
+            space: Space::Slm,
+        },
+        AimCommand::PauseScan,
+        AimCommand::ScanPosition {
+            index: 2,
+            total: 66,
+            position_xy: (120.0, 100.0),
+        },
+        AimCommand::FresnelSweep {
+            from: 0,
+            to: 20,
+            steps: 11,
+            dwell_ms: 100,
+        },
+        AimCommand::SweepStep {
+            index: 3,
+            total: 11,
+            fresnel: 6,
+        },
+        AimCommand::Morph {
+            from: PatternParams::Custom {
+                custom: CustomPattern {
+                    filename: "traps_a.png".to_owned(),
+                },
+            },
+            to: PatternParams::Custom {
+                custom: CustomPattern {
+                    filename: "traps_b.png".to_owned(),
+                },
+            },
+            steps: 50,
+            dwell_ms: 40,
+        },
+        AimCommand::MorphStep {
+            index: 7,
+            total: 50,
+        },
+        AimCommand::ResumeAvailable(SequenceCheckpoint {
+            sequence: Box::new(AimCommand::FresnelSweep {
+                from: 0,
+                to: 20,
+                steps: 11,
+                dwell_ms: 500,
+            }),
+            step: 4,
+            total: 11,
+        }),
+        AimCommand::ResumeSequence,
+        AimCommand::DiscardSequence,
+        AimCommand::Response {
+            code: ResponseCode::PrestackDone,
+            reply: Some(ResponseCode::PrestackDone.text().to_owned()),
+        },
+        AimCommand::Probe { index: 1, total: 4 },
+        AimCommand::Ack {
+            seq: 3,
+            duplicate: true,
+        },
+        AimCommand::Error {
+            code: 9,
+            category: "conflict".to_owned(),
+            message: "conflict: acquisition-pc holds control".to_owned(),
+            seq: Some(4),
+        },
+        AimCommand::SimulateFarField {
+            thumbnail_size: Some(128),
+        },
+        AimCommand::FarField {
+            width: 128,
+            height: 77,
+            imagedata: "data:image/png;base64,AAAA".to_owned(),
+        },
+        AimCommand::AddScheduledTask(ScheduledTask {
+            name: "morning".to_owned(),
+            time: "06:00".to_owned(),
+            action: ScheduledAction::ReloadCalibration,
+        }),
+        AimCommand::Schedule {
+            tasks: vec![ScheduledTask {
+                name: "evening".to_owned(),
+                time: "20:00".to_owned(),
+                action: ScheduledAction::ApplyPreset {
+                    preset: "parked".to_owned(),
+                },
+            }],
+        },
+        AimCommand::GetPatternStats,
+        AimCommand::PatternStats(PatternStats {
+            histogram: vec![0; 256],
+            wrapped_fraction: 0.25,
+            phase_min: -1.0,
+            phase_max: 9.5,
+        }),
+        AimCommand::Identify,
+        AimCommand::Identity(BuildInfo {
+            version: "0.1.0".to_owned(),
+            git: "v0.1.0-3-gabcdef0".to_owned(),
+            build_date: "2020-06-01".to_owned(),
+            protocol_version: PROTOCOL_VERSION.to_owned(),
+        }),
+        AimCommand::SetRegistration(RegistrationSource::Matrix {
+            matrix: [[0.5, 0.0, 10.0], [0.0, 0.5, -4.0], [0.0, 0.0, 1.0]],
+        }),
+        AimCommand::SetRegistration(RegistrationSource::PointPairs {
+            pairs: vec![
+                PointPair {
+                    camera_xy: (0.0, 0.0),
+                    slm_xy: (10.0, -4.0),
+                },
+                PointPair {
+                    camera_xy: (100.0, 0.0),
+                    slm_xy: (60.0, -4.0),
+                },
+                PointPair {
+                    camera_xy: (0.0, 100.0),
+                    slm_xy: (10.0, 46.0),
+                },
+            ],
+            homography: false,
+        }),
+        AimCommand::ClearRegistration,
+        AimCommand::GetRegistration,
+        AimCommand::Registration {
+            registration: Some(Registration {
+                matrix: [[0.5, 0.0, 10.0], [0.0, 0.5, -4.0], [0.0, 0.0, 1.0]],
+                rms_residual: Some(0.2),
+                max_residual: Some(0.4),
+            }),
+        },
+        AimCommand::Registration { registration: None },
+        AimCommand::StartFiducials {
+            positions: vec![(100.0, 100.0), (900.0, 100.0), (500.0, 700.0)],
+            homography: false,
+        },
+        AimCommand::Fiducial {
+            index: 1,
+            total: 3,
+            slm_xy: (900.0, 100.0),
+        },
+        AimCommand::FiducialDetected {
+            camera_xy: Some((412.5, 80.25)),
+        },
+        AimCommand::FiducialDetected { camera_xy: None },
+        AimCommand::StopFiducials,
+        AimCommand::DisplayChanged {
+            size: (1024, 768),
+            previous_size: (1920, 1152),
+        },
+        AimCommand::Idle { idle: true },
+        AimCommand::StartGrayLevels {
+            step: 4,
+            interval_ms: Some(200),
+        },
+        AimCommand::StartGrayLevels {
+            step: 1,
+            interval_ms: None,
+        },
+        AimCommand::NextGrayLevel,
+        AimCommand::StopGrayLevels,
+        AimCommand::GrayLevel {
+            index: 3,
+            total: 64,
+            level: 12,
+        },
+        AimCommand::GetCapabilities,
+        AimCommand::Capabilities(Capabilities {
+            coordinates: CoordinateSystem {
+                origin: Origin::Center,
+                y_axis: YAxis::Up,
+                units: Units::Micrometers,
+            },
+            panel_size: (1920, 1152),
+            geometry: SlmGeometry {
+                pixel_pitch_um: (8.0, 9.2),
+                active_area: Some(ActiveArea {
+                    offset_xy: (320, 0),
+                    size_xy: (1152, 1152),
+                }),
+                fill_factor: 0.93,
+            },
+        }),
+        AimCommand::GetParameterSchema,
+        AimCommand::ParameterSchema {
+            parameters: vec![
+                Parameter {
+                    name: "fresnel".to_owned(),
+                    device: None,
+                    command: "setfresnel".to_owned(),
+                    field: "value".to_owned(),
+                    unit: Some("D".to_owned()),
+                    value: ParameterValue::Integer {
+                        min: Some(0),
+                        max: Some(50),
+                        default: 0,
+                    },
+                },
+                Parameter {
+                    name: "attenuation".to_owned(),
+                    device: None,
+                    command: "setattenuation".to_owned(),
+                    field: "percent".to_owned(),
+                    unit: Some("%".to_owned()),
+                    value: ParameterValue::Number {
+                        min: Some(0.0),
+                        max: Some(100.0),
+                        default: 0.0,
+                    },
+                },
+                Parameter {
+                    name: "maintenance".to_owned(),
+                    device: None,
+                    command: "setMaintenanceMode".to_owned(),
+                    field: "on".to_owned(),
+                    unit: None,
+                    value: ParameterValue::Boolean { default: false },
+                },
+                Parameter {
+                    name: "wavelength".to_owned(),
+                    device: Some("lasers".to_owned()),
+                    command: "set".to_owned(),
+                    field: "lasers.wavelength".to_owned(),
+                    unit: Some("nm".to_owned()),
+                    value: ParameterValue::Choice {
+                        choices: vec!["488".to_owned(), "561".to_owned()],
+                        default: Some("488".to_owned()),
+                    },
+                },
+                Parameter {
+                    name: "profile".to_owned(),
+                    device: None,
+                    command: "setprofile".to_owned(),
+                    field: "name".to_owned(),
+                    unit: None,
+                    value: ParameterValue::Choice {
+                        choices: vec!["SIM".to_owned(), "tweezers".to_owned()],
+                        default: None,
+                    },
+                },
+            ],
+        },
+        AimCommand::AcquireControl,
+        AimCommand::ReleaseControl,
+        AimCommand::SetMaintenanceMode { on: true },
+        AimCommand::Startup(StartupSummary {
+            build: BuildInfo::default(),
+            directories: vec![DirectoryStatus {
+                name: "base_patterns".to_owned(),
+                path: "/home/pi/patterns".to_owned(),
+                exists: true,
+            }],
+            screen_size: (1920, 1080),
+            screen_mode: Some(ScreenMode {
+                size: (1920, 1080),
+                refresh_hz: 60,
+            }),
+            wavelengths: vec![488, 561],
+            size_mismatches: vec![SizeMismatch {
+                path: "flatness/flatness_wavelength_561.png".to_owned(),
+                size: (1280, 1024),
+            }],
+        }),
+        AimCommand::CaptureFrame {
+            label: "cell 3, 488 nm".to_owned(),
+        },
+        AimCommand::FrameCaptured {
+            label: "cell 3, 488 nm".to_owned(),
+            path: "captures/20261018-091500/0001-cell_3__488_nm.png".to_owned(),
+        },
+        AimCommand::CreateBackup { publish: true },
+        AimCommand::BackupCreated {
+            name: "backup-20261018-091500.tar".to_owned(),
+            size: 10240,
+            chunks: 1,
+        },
+        AimCommand::BackupChunk {
+            name: "backup-20261018-091500.tar".to_owned(),
+            index: 0,
+            total: 1,
+            data: BinaryData::Base64("AAEC".to_owned()),
+        },
+        AimCommand::RestoreBackup {
+            name: "backup-20261018-091500.tar".to_owned(),
+        },
+        AimCommand::Reboot,
+        AimCommand::Update {
+            url: "http://updates.local/rasp_pi".to_owned(),
+            sha256: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_owned(),
+        },
+        AimCommand::UpdateProgress {
+            downloaded_bytes: 4_194_304,
+        },
+    ];
+
+    for command in commands {
+        let name = command.name();
+        let encoded = round_trip(&aim_message(command));
+        assert_eq!(encoded["data"]["command"], name);
+    }
+}
+
+#[test]
+fn state_report() {
+    let state = StateReport {
+        pattern: base(),
+        fresnel: 2,
+        wavelength: 561,
+        tilt_xy: (0.0, 0.01),
+        attenuation: 0.0,
+        defect_masks: Vec::new(),
+        applied_corrections: vec!["flatness".to_owned()],
+        profile: Some("SIM".to_owned()),
+        astigmatic_fresnel: Some(AstigmaticFresnel {
+            power_xy: (0.0, 4.0),
+            rotation_deg: 0.0,
+        }),
+        pattern_hash: "9a3c5e1f00b2d4e6".to_owned(),
+        controlled_by: Some("acquisition-pc".to_owned()),
+        last_modified_by: Some("acquisition-pc".to_owned()),
+        maintenance: false,
+        raw: false,
+        blanked: false,
+        diffraction_order: DiffractionOrder::MinusFirst,
+    };
+    round_trip(&aim_message(AimCommand::State(Box::new(state.clone()))));
+    round_trip(&aim_message(AimCommand::StateEvent(Box::new(StateEvent {
+        id: 17,
+        time: "2024-05-03T09:41:07.215+02:00".to_owned(),
+        state,
+    }))));
+}
+
+#[test]
+fn coordinate_system_defaults_to_panel_pixels() {
+    let coordinates: CoordinateSystem = serde_json::from_value(json!({})).unwrap();
+    assert_eq!(coordinates, CoordinateSystem::default());
+    assert_eq!(coordinates.origin, Origin::Corner);
+    assert_eq!(coordinates.y_axis, YAxis::Down);
+    assert_eq!(coordinates.units, Units::Pixels);
+}
+
+#[test]
+fn geometry_defaults_to_square_pixels() {
+    let geometry: SlmGeometry = serde_json::from_value(json!({})).unwrap();
+    assert_eq!(geometry, SlmGeometry::default());
+    assert_eq!(geometry.pixel_pitch_um, (12.5, 12.5));
+    assert_eq!(geometry.active_area, None);
+}
+
+#[test]
+fn positions_default_to_slm_space() {
+    let state: AimState = serde_json::from_value(json!({
+        "pattern": { "flat": {} },
+        "fresnel": 0
+    }))
+    .unwrap();
+    assert_eq!(state.space, Space::Slm);
+}
+
+#[test]
+fn precondition_is_optional() {
+    let message: Message = serde_json::from_value(json!({
+        "type": "device",
+        "expect": { "wavelength": 488 },
+        "data": { "device": "aim", "command": "setfresnel", "value": 3 }
+    }))
+    .unwrap();
+    let expect = message.expect.unwrap();
+    assert_eq!(expect.wavelength, Some(488));
+    assert_eq!(expect.pattern_hash, None);
+
+    let encoded = round_trip(&aim_message(AimCommand::Get));
+    assert!(encoded.get("expect").is_none());
+}
+
+#[test]
+fn status_report() {
+    let encoded = round_trip(&aim_message(AimCommand::Status(Box::new(StatusReport {
+        temperatures: vec![SensorReading {
+            name: "panel".to_owned(),
+            celsius: Some(31.5),
+        }],
+        backlog: 0,
+        build: BuildInfo::default(),
+        idle: false,
+        health: SystemHealth {
+            disk_free_bytes: Some(12 << 30),
+            load_average: Some(0.4),
+            memory_used_percent: None,
+            log_bytes: Some(700_000),
+            clock_synchronized: Some(true),
+            clock_offset_ms: Some(-0.25),
+        },
+        commands: vec![CommandMetrics {
+            command: "setCorrectionPatternDeltas".to_owned(),
+            count: 12,
+            errors: 1,
+            p50_ms: 35.0,
+            p95_ms: 80.5,
+        }],
+    }))));
+    assert!(encoded["data"]["health"]
+        .get("memory_used_percent")
+        .is_none());
+    assert_eq!(
+        encoded["data"]["commands"][0]["command"],
+        "setCorrectionPatternDeltas"
+    );
+}
+
+#[test]
+fn binary_data_is_base64_or_bytes() {
+    let base64 = BinaryData::Base64("AAAAAA==".to_owned());
+    assert_eq!(round_trip(&base64), json!("AAAAAA=="));
+
+    let bytes: BinaryData =
+        Deserialize::deserialize(BytesDeserializer::<serde::de::value::Error>::new(&[
+            0, 0, 128, 63,
+        ]))
+        .unwrap();
+    assert_eq!(bytes, BinaryData::Bytes(vec![0, 0, 128, 63]));
+}
+
+#[test]
+fn property_kind_is_inferred() {
+    let inferred = |values: &[&str]| {
+        let mut prop = APatternProp {
+            values: values.iter().map(|value| value.to_string()).collect(),
+            ..Default::default()
+        };
+        prop.infer_kind();
+        (prop.kind, prop.min, prop.max)
+    };
+    assert_eq!(
+        inferred(&["20", "40", "-5"]),
+        (PropertyKind::Int, Some(-5.0), Some(40.0))
+    );
+    assert_eq!(
+        inferred(&["0.5", "2"]),
+        (PropertyKind::Float, Some(0.5), Some(2.0))
+    );
+    assert_eq!(
+        inferred(&["20", "wide"]),
+        (PropertyKind::String, None, None)
+    );
+    assert_eq!(inferred(&[]), (PropertyKind::String, None, None));
+
+    let encoded = round_trip(&APatternProp {
+        values: vec!["20".to_owned()],
+        kind: PropertyKind::Int,
+        min: Some(20.0),
+        max: Some(20.0),
+    });
+    assert_eq!(encoded["kind"], "int");
+    let legacy: APatternProp = serde_json::from_value(json!({ "values": ["a"] })).unwrap();
+    assert_eq!(legacy.kind, PropertyKind::String);
+}
+
+#[test]
+fn legacy_get_all_patterns_is_unpaged() {
+    let message: Message = serde_json::from_value(json!({
+        "type": "device",
+        "data": { "device": "aim", "command": "getAllPatterns" }
+    }))
+    .unwrap();
+    assert!(matches!(
+        message.data,
+        MessageData::Aim(AimCommand::GetAllPatterns {
+            prefix: None,
+            offset: None,
+            limit: None,
+        })
+    ));
+}
+
+#[test]
+fn phase_samples_default_to_a_wave() {
+    let samples: PhaseSamples = serde_json::from_value(json!({
+        "size": [1920, 1080],
+        "sample_type": "u8"
+    }))
+    .unwrap();
+    assert_eq!(samples.endianness, Endianness::Little);
+    assert_eq!(samples.value_range(), (0.0, 256.0));
+    assert_eq!(samples.phase_range, (0.0, std::f32::consts::TAU));
+}
+
+#[test]
+fn parameters_are_flat() {
+    let parameter = Parameter {
+        name: "tilt_x".to_owned(),
+        device: None,
+        command: "steerBeam".to_owned(),
+        field: "tilt_x_mrad".to_owned(),
+        unit: Some("mrad".to_owned()),
+        value: ParameterValue::Number {
+            min: None,
+            max: None,
+            default: 0.0,
+        },
+    };
+    assert_eq!(
+        round_trip(&parameter),
+        json!({
+            "name": "tilt_x",
+            "command": "steerBeam",
+            "field": "tilt_x_mrad",
+            "unit": "mrad",
+            "type": "number",
+            "default": 0.0
+        })
+    );
+}
+
+#[test]
+fn origin_is_accepted_for_client() {
+    let message: Message = serde_json::from_value(json!({
+        "type": "device",
+        "origin": "acquisition-pc",
+        "data": { "device": "aim", "command": "get" }
+    }))
+    .unwrap();
+    assert_eq!(message.client.as_deref(), Some("acquisition-pc"));
+}
+
+#[test]
+fn queries_are_accepted_under_control() {
+    assert!(AimCommand::Get.is_query());
+    assert!(!AimCommand::SetFresnel { value: 3 }.is_query());
+    assert!(AimCommand::Batch {
+        commands: vec![AimCommand::Get, AimCommand::GetRegistration],
+    }
+    .is_query());
+    assert!(!AimCommand::Batch {
+        commands: vec![AimCommand::Get, AimCommand::Reboot],
+    }
+    .is_query());
+}
+
+#[test]
+fn sequence_number_is_optional() {
+    let message: Message = serde_json::from_value(json!({
+        "type": "device",
+        "seq": 42,
+        "data": { "device": "aim", "command": "get" }
+    }))
+    .unwrap();
+    assert_eq!(message.seq, Some(42));
+
+    let encoded = round_trip(&aim_message(AimCommand::Get));
+    assert!(encoded.get("seq").is_none());
+}
+
+#[test]
+fn laser_set_wire_format() {
+    let message: Message = serde_json::from_value(json!({
+        "type": "device",
+        "data": {
+            "device": "lasers",
+            "command": "set",
+            "lasers": [
+                { "name": "488", "state": 1, "wavelength": 488, "intensity": 50 },
+                { "name": "561", "state": true, "wavelength": 561, "intensity": 37.5 },
+                { "name": "640", "state": false, "wavelength": 640, "intensity": 80 },
+                { "name": "led", "state": 0, "wavelength": 0, "intensity": 0 }
+            ]
+        }
+    }))
+    .unwrap();
+
+    match message.data {
+        MessageData::Lasers(LaserCommand::Set { lasers }) => {
+            assert_eq!(lasers.len(), 4);
+            assert_eq!(lasers[0].intensity, 50.0);
+            assert_eq!(lasers[1].intensity, 37.5);
+            let on: Vec<_> = lasers.iter().map(|laser| laser.state).collect();
+            assert_eq!(on, vec![true, true, false, false]);
+            // the format older firmware expects
+            assert_eq!(serde_json::to_value(&lasers[1]).unwrap()["state"], json!(1));
+        }
+        other => panic!("unexpected message {:?}", other),
+    }
+}
+
+#[test]
+fn embedded_init_done() {
+    let message: Message = serde_json::from_value(json!({
+        "type": "status",
+        "data": { "device": "embedded", "command": "initdone" }
+    }))
+    .unwrap();
+
+    assert!(matches!(
+        message.data,
+        MessageData::Embedded(EmbeddedCommand::InitDone)
+    ));
+}
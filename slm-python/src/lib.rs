@@ -0,0 +1,120 @@
+//! Python bindings for the pattern computation, so corrections can be
+//! prototyped in Jupyter with exactly the math the controller runs:
+//!
+//! ```python
+//! import json, numpy as np, slm_controller
+//!
+//! state = {"pattern": {"spot": {...}}, "fresnel": 0}
+//! pattern = np.array(slm_controller.compute_pattern(json.dumps(state), 488))
+//! ```
+//!
+//! Arrays are passed as nested lists, which `np.array` converts. They are in
+//! image order, one row per panel line, so `pattern[y][x]` is the pixel at
+//! (x, y) and the shape is `(height, width)`; the controller itself indexes
+//! its arrays by (x, y). Paths in the config are relative to the working
+//! directory, as for the controller.
+
+use std::path::Path;
+
+use ndarray::Array2;
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+use rasp_pi::{
+    read_config_file,
+    schema::{AimState, PatternParams},
+    storage, Context, SlmError,
+};
+
+fn runtime_error(err: SlmError) -> PyErr {
+    PyRuntimeError::new_err(err.to_string())
+}
+
+fn value_error(err: serde_json::Error) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+/// Lines of an (x, y)-indexed array, top to bottom
+fn rows<T: Copy>(array: &Array2<T>) -> Vec<Vec<T>> {
+    array
+        .t()
+        .genrows()
+        .into_iter()
+        .map(|row| row.to_vec())
+        .collect()
+}
+
+/// (x, y)-indexed array from its lines, top to bottom
+fn from_rows(rows: Vec<Vec<f32>>) -> PyResult<Array2<f32>> {
+    let width = rows.first().map_or(0, Vec::len);
+    if rows.iter().any(|row| row.len() != width) {
+        return Err(PyValueError::new_err("Rows differ in length"));
+    }
+    let height = rows.len();
+    Ok(Array2::from_shape_fn((width, height), |(x, y)| rows[y][x]))
+}
+
+/// Run `f` on an offline controller context with the given config
+fn with_context<T>(
+    config: &str,
+    f: impl FnOnce(&mut Context) -> rasp_pi::Result<T>,
+) -> PyResult<T> {
+    let config = read_config_file(Path::new(config)).map_err(runtime_error)?;
+    let sdl_context = sdl2::init().map_err(PyRuntimeError::new_err)?;
+    let mut context = Context::offline(config, &sdl_context).map_err(runtime_error)?;
+    f(&mut context).map_err(runtime_error)
+}
+
+/// Gray levels the SLM would show for an aim state (JSON) at a wavelength,
+/// with the calibration and corrections the config points to
+#[pyfunction]
+#[pyo3(signature = (aim_state, wavelength, config = "config.json"))]
+fn compute_pattern(aim_state: &str, wavelength: u32, config: &str) -> PyResult<Vec<Vec<u8>>> {
+    let aim_state: AimState = serde_json::from_str(aim_state).map_err(value_error)?;
+    let pattern = with_context(config, |context| {
+        context.render_pattern(aim_state, wavelength)
+    })?;
+    Ok(rows(&pattern))
+}
+
+/// Phase in radians of a pattern (`PatternParams` JSON, e.g. a spot) before
+/// corrections and wrapping
+#[pyfunction]
+#[pyo3(signature = (pattern, config = "config.json"))]
+fn generate_pattern(pattern: &str, config: &str) -> PyResult<Vec<Vec<f32>>> {
+    let pattern: PatternParams = serde_json::from_str(pattern).map_err(value_error)?;
+    let phase = with_context(config, |context| context.base_pattern(&pattern))?;
+    Ok(rows(&phase))
+}
+
+/// Phase in radians from a grayscale image or (compressed) npy file, as the
+/// controller reads pattern files
+#[pyfunction]
+fn load_pattern(path: &str) -> PyResult<Vec<Vec<f32>>> {
+    let phase = storage::read_image_from_file(Path::new(path), None).map_err(runtime_error)?;
+    Ok(rows(&phase))
+}
+
+/// Write a phase in radians as the controller stores patterns: npy for
+/// `.npy` and `.npy.zst`, a grayscale image otherwise
+#[pyfunction]
+fn save_pattern(path: &str, phase: Vec<Vec<f32>>) -> PyResult<()> {
+    let path = Path::new(path);
+    let phase = from_rows(phase)?;
+    let saved = if storage::is_npy(path) {
+        storage::save_npy(path, &phase)
+    } else {
+        storage::save_image(path, &phase)
+    };
+    saved.map_err(runtime_error)
+}
+
+#[pymodule]
+fn slm_controller(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(compute_pattern, module)?)?;
+    module.add_function(wrap_pyfunction!(generate_pattern, module)?)?;
+    module.add_function(wrap_pyfunction!(load_pattern, module)?)?;
+    module.add_function(wrap_pyfunction!(save_pattern, module)?)?;
+    Ok(())
+}
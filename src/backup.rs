@@ -0,0 +1,165 @@
+//! `createBackup` and `restoreBackup`: the files a controller accumulates in
+//! operation in one tar archive, so reinstalling its PC doesn't lose them
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::Local;
+use log::{info, warn};
+use tar::{Archive, Builder};
+
+use crate::{
+    read_config,
+    schema::{AimCommand, BinaryData, Message, MessageData, MessageType},
+    storage::{self, is_factory_file},
+    Context, Result, SlmError,
+};
+
+/// Size of the published pieces of an archive, before base64
+const CHUNK_BYTES: usize = 256 * 1024;
+
+/// The presets are part of the config
+const CONFIG_FILE: &str = "config.json";
+const CUSTOM_PATTERNS: &str = "custom_patterns";
+const FLATNESS_CORRECTIONS: &str = "flatness_corr_patterns";
+
+impl<'a> Context<'a> {
+    /// Path of the archive `name` in the backup directory; `name` can't
+    /// point anywhere else
+    fn backup_path(&self, name: &str) -> Result<PathBuf> {
+        if Path::new(name).file_name() != Some(name.as_ref()) {
+            Err(SlmError::Request(format!("{} is not a backup name", name)))?
+        }
+        Ok(self.config.backup_dir.join(name))
+    }
+
+    fn custom_patterns_dir(&self) -> PathBuf {
+        self.config.dir_path.base_patterns.join(CUSTOM_PATTERNS)
+    }
+
+    /// Where an archived file goes, `None` for anything the archive
+    /// shouldn't contain
+    fn restore_target(&self, archived: &Path) -> Option<PathBuf> {
+        let mut parts = Vec::new();
+        for component in archived.components() {
+            match component {
+                Component::Normal(part) => parts.push(part),
+                Component::CurDir => (),
+                _ => return None,
+            }
+        }
+        let (first, rest) = parts.split_first()?;
+        let dir = match first.to_str()? {
+            CONFIG_FILE if rest.is_empty() => return Some(PathBuf::from(CONFIG_FILE)),
+            CUSTOM_PATTERNS => self.custom_patterns_dir(),
+            FLATNESS_CORRECTIONS => self.config.dir_path.flatness_corr_patterns.clone(),
+            _ => return None,
+        };
+        Some(rest.iter().fold(dir, |path, part| path.join(part)))
+    }
+
+    /// Archive the custom patterns, the flatness corrections with the
+    /// calibration store next to them, and the config
+    pub fn create_backup(&mut self, publish: bool) -> Result<&mut Self> {
+        fs::create_dir_all(&self.config.backup_dir)?;
+        let name = format!("backup-{}.tar", Local::now().format("%Y%m%d-%H%M%S"));
+        let path = self.backup_path(&name)?;
+
+        let mut builder = Builder::new(File::create(&path)?);
+        let custom_patterns = self.custom_patterns_dir();
+        if custom_patterns.is_dir() {
+            builder.append_dir_all(CUSTOM_PATTERNS, &custom_patterns)?;
+        }
+        builder.append_dir_all(
+            FLATNESS_CORRECTIONS,
+            &self.config.dir_path.flatness_corr_patterns,
+        )?;
+        builder.append_path_with_name(CONFIG_FILE, CONFIG_FILE)?;
+        builder.into_inner()?.sync_all()?;
+        info!("Backed up to {}", path.display());
+
+        let mut archive = Vec::new();
+        if publish {
+            File::open(&path)?.read_to_end(&mut archive)?;
+        }
+        let chunks: Vec<_> = archive.chunks(CHUNK_BYTES).collect();
+        let total = chunks.len() as u32;
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::BackupCreated {
+                name: name.clone(),
+                size: fs::metadata(&path)?.len(),
+                chunks: total,
+            }),
+        })?;
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            self.send_aim_message(&Message {
+                m_type: MessageType::Device,
+                seq: None,
+                expect: None,
+                client: None,
+                data: MessageData::Aim(AimCommand::BackupChunk {
+                    name: name.clone(),
+                    index: index as u32,
+                    total,
+                    data: BinaryData::Base64(base64::encode(chunk)),
+                }),
+            })?;
+        }
+        Ok(self)
+    }
+
+    /// Unpack an archive made by `create_backup` and take it into use; the
+    /// config it replaces is kept with the `.previous` extension. Only the
+    /// presets of the restored config apply before a restart
+    pub fn restore_backup(&mut self, name: &str) -> Result<&mut Self> {
+        let path = self.backup_path(name)?;
+        info!("Restoring backup {}", path.display());
+        let mut archive = Archive::new(File::open(&path)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let archived = entry.path()?.into_owned();
+            let target = match self.restore_target(&archived) {
+                Some(target) => target,
+                None => {
+                    warn!("Skipping {} in backup {}", archived.display(), name);
+                    continue;
+                }
+            };
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_dir() {
+                fs::create_dir_all(&target)?;
+                continue;
+            }
+            // links could point the restored files anywhere
+            if !entry_type.is_file() {
+                warn!("Skipping {} in backup {}", archived.display(), name);
+                continue;
+            }
+            let factory = is_factory_file(&target);
+            if factory && target.exists() {
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if target == Path::new(CONFIG_FILE) && target.exists() {
+                fs::copy(&target, target.with_extension("json.previous"))?;
+            }
+            entry.unpack(&target)?;
+            if factory {
+                storage::protect(&target)?;
+            }
+        }
+
+        let config = read_config()?;
+        self.config.presets = config.presets;
+        self.config.laser_presets = config.laser_presets;
+        self.state.available_patterns_changed = true;
+        self.reload_calibration()
+    }
+}
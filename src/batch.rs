@@ -0,0 +1,99 @@
+//! Several commands applied together (e.g. pattern, fresnel and attenuation from
+//! the GUI), with the panel and the published state updated once at the end
+
+use log::info;
+
+use crate::{
+    schema::{AimCommand, AstigmaticFresnel, DiffractionOrder, PatternParams, ResponseCode},
+    Context, Result, SlmError,
+};
+
+/// The state restored if any command of a batch fails
+pub struct Snapshot {
+    pattern_params: PatternParams,
+    fresnel: u32,
+    wavelength: u32,
+    tilt_xy: (f32, f32),
+    attenuation: f32,
+    diffraction_order: DiffractionOrder,
+    astigmatic_fresnel: Option<AstigmaticFresnel>,
+    profile: Option<String>,
+    raw: bool,
+}
+
+impl<'a> Context<'a> {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pattern_params: self.state.pattern_params.clone(),
+            fresnel: self.state.fresnel,
+            wavelength: self.state.wavelength,
+            tilt_xy: self.state.tilt_xy,
+            attenuation: self.state.attenuation,
+            diffraction_order: self.state.diffraction_order,
+            astigmatic_fresnel: self.state.astigmatic_fresnel.clone(),
+            profile: self.state.profile.clone(),
+            raw: self.state.raw,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.state.pattern_params = snapshot.pattern_params;
+        self.state.fresnel = snapshot.fresnel;
+        self.state.wavelength = snapshot.wavelength;
+        self.state.tilt_xy = snapshot.tilt_xy;
+        self.state.attenuation = snapshot.attenuation;
+        self.state.diffraction_order = snapshot.diffraction_order;
+        self.state.astigmatic_fresnel = snapshot.astigmatic_fresnel;
+        self.state.profile = snapshot.profile;
+        self.state.raw = snapshot.raw;
+    }
+
+    /// Run the commands in order; if one of them fails, the state from before
+    /// the batch is restored. Files written by the commands (e.g. uploads) are kept.
+    pub fn execute_batch(&mut self, commands: Vec<AimCommand>, topic: &str) -> Result<&mut Self> {
+        for command in &commands {
+            match command {
+                AimCommand::Batch { .. } => {
+                    Err(SlmError::Request("Batches can't be nested".to_owned()))?
+                }
+                AimCommand::AcquireControl | AimCommand::ReleaseControl => Err(SlmError::Request(
+                    "Control can't be acquired in a batch".to_owned(),
+                ))?,
+                _ => (),
+            }
+            if !self.config.is_permitted(command.name(), topic) {
+                Err(SlmError::Request(format!(
+                    "Command {} is not permitted on {}",
+                    command.name(),
+                    topic
+                )))?
+            }
+        }
+
+        info!("Running batch of {} commands", commands.len());
+        let snapshot = self.snapshot();
+        self.state.batching = true;
+        let result = commands
+            .into_iter()
+            .enumerate()
+            .try_for_each(|(index, command)| {
+                let name = command.name();
+                self.execute(command).map_err(|err| {
+                    SlmError::Request(format!(
+                        "Command {} ({}) of the batch failed: {}",
+                        index, name, err
+                    ))
+                })
+            });
+        self.state.batching = false;
+
+        if let Err(err) = result.and_then(|_| self.update_state(None, None, None).map(|_| ())) {
+            info!("Batch failed; restoring the previous state");
+            self.restore(snapshot);
+            self.update_state(None, None, None)?;
+            Err(err)?
+        }
+        self.send_current_state()?
+            .send_response(ResponseCode::BatchDone)
+    }
+}
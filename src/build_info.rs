@@ -0,0 +1,12 @@
+//! Version and build metadata embedded by the build script
+
+use crate::schema::{BuildInfo, PROTOCOL_VERSION};
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        git: env!("SLM_GIT_DESCRIBE").to_owned(),
+        build_date: env!("SLM_BUILD_DATE").to_owned(),
+        protocol_version: PROTOCOL_VERSION.to_owned(),
+    }
+}
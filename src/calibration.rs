@@ -0,0 +1,30 @@
+//! Calibration data that is edited at runtime and persisted between restarts
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{schema::DefectMask, Result};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CalibrationStore {
+    #[serde(default)]
+    pub defect_masks: Vec<DefectMask>,
+}
+
+impl CalibrationStore {
+    /// Load the store, starting with an empty one if it hasn't been saved yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            return Ok(Default::default());
+        }
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
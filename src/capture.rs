@@ -0,0 +1,111 @@
+//! `captureFrame`: the displayed pattern saved with the settings it was computed
+//! from, so experiments have an exact record of the SLM for each acquisition
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use chrono::Local;
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    build_info::build_info,
+    schema::{AimCommand, BuildInfo, Message, MessageData, MessageType, StateReport},
+    util::sha256_hex,
+    Context, Result, SlmError,
+};
+
+/// Contents of the JSON sidecar next to a captured frame
+#[derive(Serialize)]
+struct FrameMetadata<'a> {
+    label: &'a str,
+    timestamp: String,
+    build: BuildInfo,
+    state: StateReport,
+    /// SHA-256 of the config and calibration files the pattern was computed with
+    calibration: BTreeMap<String, String>,
+}
+
+/// Label as part of a file name
+fn file_label(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn add_file_hash(hashes: &mut BTreeMap<String, String>, path: &Path) -> Result<()> {
+    if path.is_file() {
+        hashes.insert(path.display().to_string(), sha256_hex(path)?);
+    }
+    Ok(())
+}
+
+impl<'a> Context<'a> {
+    fn calibration_hashes(&self) -> Result<BTreeMap<String, String>> {
+        let mut hashes = BTreeMap::new();
+        add_file_hash(&mut hashes, Path::new("config.json"))?;
+        add_file_hash(&mut hashes, &self.config.dir_path.calibration_store())?;
+        if self
+            .state
+            .applied_corrections
+            .iter()
+            .any(|correction| correction == "flatness")
+        {
+            let path = self.get_file_path_for_flatness_corr_pattern(self.state.wavelength)?;
+            add_file_hash(&mut hashes, &path)?;
+        }
+        Ok(hashes)
+    }
+
+    /// Save the displayed pattern as `<number>-<label>.png` in the directory of
+    /// this run, with the metadata in a `.json` file of the same name
+    pub fn capture_frame(&mut self, label: String) -> Result<&mut Self> {
+        let pattern = self
+            .state
+            .displayed
+            .as_ref()
+            .ok_or_else(|| SlmError::Request("No pattern is displayed".to_owned()))?;
+        let directory = self.config.capture_dir.join(&self.state.capture_session);
+        fs::create_dir_all(&directory)?;
+        self.state.frames_captured += 1;
+        let path = directory.join(format!(
+            "{:04}-{}.png",
+            self.state.frames_captured,
+            file_label(&label)
+        ));
+
+        ndarray_image::save_gray_image(&path, pattern.view())?;
+        let metadata = FrameMetadata {
+            label: &label,
+            timestamp: Local::now().to_rfc3339(),
+            build: build_info(),
+            state: self.state_report()?,
+            calibration: self.calibration_hashes()?,
+        };
+        serde_json::to_writer_pretty(
+            BufWriter::new(File::create(path.with_extension("json"))?),
+            &metadata,
+        )?;
+        info!("Captured frame {} to {}", label, path.display());
+
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::FrameCaptured {
+                label,
+                path: path.display().to_string(),
+            }),
+        })
+    }
+}
@@ -0,0 +1,139 @@
+//! Checkpoints of running sequences, so an acquisition interrupted by a
+//! controller crash can continue from where it was instead of from zero
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+use log::{error, info};
+
+use crate::{
+    schema::{AimCommand, Message, MessageData, MessageType, SequenceCheckpoint},
+    Context, Result, SlmError,
+};
+
+const CHECKPOINT_FILE: &str = "sequence_checkpoint.json";
+
+/// Written next to the checkpoint and renamed over it, so a crash while
+/// writing leaves the previous one
+fn save(checkpoint: &SequenceCheckpoint) -> Result<()> {
+    let staged = Path::new(CHECKPOINT_FILE).with_extension("json.tmp");
+    serde_json::to_writer(File::create(&staged)?, checkpoint)?;
+    fs::rename(staged, CHECKPOINT_FILE)?;
+    Ok(())
+}
+
+fn load() -> Result<Option<SequenceCheckpoint>> {
+    if !Path::new(CHECKPOINT_FILE).is_file() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_reader(BufReader::new(File::open(
+        CHECKPOINT_FILE,
+    )?))?))
+}
+
+impl<'a> Context<'a> {
+    /// A sequence started by `sequence` is starting; any interrupted one
+    /// can't be resumed anymore
+    pub fn begin_checkpoint(&mut self, sequence: AimCommand, total: u32) {
+        self.state.resumable = None;
+        self.state.checkpoint = Some(SequenceCheckpoint {
+            sequence: Box::new(sequence),
+            step: 0,
+            total,
+        });
+    }
+
+    /// Record the step on the panel; errors are only logged, the sequence
+    /// goes on regardless
+    pub fn checkpoint(&mut self, step: u32) {
+        if let Some(checkpoint) = &mut self.state.checkpoint {
+            checkpoint.step = step;
+            if let Err(err) = save(checkpoint) {
+                error!(
+                    "Error {} while saving the sequence checkpoint; continuing",
+                    err
+                );
+            }
+        }
+    }
+
+    /// The sequence is over, whether finished or stopped
+    pub fn clear_checkpoint(&mut self) {
+        if self.state.checkpoint.take().is_some() {
+            if let Err(err) = fs::remove_file(CHECKPOINT_FILE) {
+                error!(
+                    "Error {} while removing the sequence checkpoint; continuing",
+                    err
+                );
+            }
+        }
+    }
+
+    /// Publish `resumeAvailable` if a sequence was interrupted by a restart
+    pub fn offer_resume(&mut self) -> Result<&mut Self> {
+        let checkpoint = match load()? {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(self),
+        };
+        info!(
+            "{} was interrupted at step {} of {}; it can be resumed",
+            checkpoint.sequence.name(),
+            checkpoint.step,
+            checkpoint.total
+        );
+        self.state.resumable = Some(checkpoint.clone());
+        self.send_aim_message(&Message {
+            m_type: MessageType::Status,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::ResumeAvailable(checkpoint)),
+        })
+    }
+
+    pub fn resume_sequence(&mut self) -> Result<&mut Self> {
+        let checkpoint = match self.state.resumable.take() {
+            Some(checkpoint) => checkpoint,
+            None => Err(SlmError::Request("No sequence to resume".to_owned()))?,
+        };
+        info!(
+            "Resuming {} at step {}",
+            checkpoint.sequence.name(),
+            checkpoint.step
+        );
+        let first = checkpoint.step;
+        match *checkpoint.sequence {
+            AimCommand::StartProbeSequence {
+                sequence,
+                interval_ms,
+            } => self.start_probe_sequence(sequence, interval_ms, first),
+            AimCommand::FresnelSweep {
+                from,
+                to,
+                steps,
+                dwell_ms,
+            } => self.start_fresnel_sweep(from, to, steps, dwell_ms, first),
+            AimCommand::Morph {
+                from,
+                to,
+                steps,
+                dwell_ms,
+            } => self.start_morph(from, to, steps, dwell_ms, first),
+            AimCommand::StartGrayLevels { step, interval_ms } => {
+                self.start_gray_levels(step, interval_ms, first)
+            }
+            sequence => Err(SlmError::Request(format!(
+                "{} can't be resumed",
+                sequence.name()
+            )))?,
+        }
+    }
+
+    pub fn discard_sequence(&mut self) -> Result<&mut Self> {
+        if self.state.resumable.take().is_some() && self.state.checkpoint.is_none() {
+            fs::remove_file(CHECKPOINT_FILE)?;
+        }
+        Ok(self)
+    }
+}
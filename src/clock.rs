@@ -0,0 +1,89 @@
+//! Check of the system clock against the time server; the microscope LAN is often
+//! isolated, and journals and saved corrections depend on correct timestamps
+
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::{Context, Result};
+
+#[derive(Default)]
+pub struct ClockCheck {
+    checked_at: Option<Instant>,
+    pub synchronized: Option<bool>,
+    pub offset_ms: Option<f64>,
+}
+
+/// Offset from `chronyc` if chrony is the time client; it reports the
+/// system time in seconds, ahead of the time server if positive
+fn chrony_offset_ms() -> Option<f64> {
+    let output = Command::new("chronyc")
+        .args(["-c", "tracking"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .split(',')
+        .nth(4)
+        .and_then(|offset| offset.trim().parse::<f64>().ok())
+        .map(|offset| offset * 1000.0)
+}
+
+/// Whether systemd considers the clock synchronized
+fn timedatectl_synchronized() -> Option<bool> {
+    let output = Command::new("timedatectl")
+        .args(["show", "--property=NTPSynchronized", "--value"])
+        .output()
+        .ok()?;
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+impl<'a> Context<'a> {
+    /// Check the clock at startup and then every `check_interval_secs`,
+    /// warning when it becomes unsynchronized
+    pub fn poll_clock(&mut self) -> Result<()> {
+        let interval = Duration::from_secs(self.config.clock.check_interval_secs);
+        let first = match self.state.clock.checked_at {
+            Some(checked_at) if checked_at.elapsed() < interval => return Ok(()),
+            checked_at => checked_at.is_none(),
+        };
+        self.state.clock.checked_at = Some(Instant::now());
+
+        let offset_ms = chrony_offset_ms();
+        let synchronized = match offset_ms {
+            Some(offset_ms) => Some(offset_ms.abs() <= self.config.clock.max_offset_ms),
+            None => timedatectl_synchronized(),
+        };
+        let was_synchronized = self.state.clock.synchronized;
+        self.state.clock.offset_ms = offset_ms;
+        self.state.clock.synchronized = synchronized;
+
+        match (synchronized, was_synchronized) {
+            (Some(false), Some(false)) => (),
+            (Some(false), _) => {
+                let warning = match offset_ms {
+                    Some(offset_ms) => format!(
+                        "System clock is off by {:.0} ms from the time server",
+                        offset_ms
+                    ),
+                    None => "System clock is not synchronized to a time server".to_owned(),
+                };
+                self.send_warning(warning)?;
+            }
+            (Some(true), Some(false)) => info!("System clock is synchronized again"),
+            // logged when it becomes unknown, not on every check
+            (None, was_synchronized) if first || was_synchronized.is_some() => {
+                info!("Can't tell whether the system clock is synchronized")
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+//! Transparent compression of MQTT payloads; a compressed payload is
+//! recognized by the header of its format, as JSON never starts with it
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+use flate2::{read::GzDecoder, write::GzEncoder};
+
+use crate::{
+    schema::{CompressionConfig, CompressionFormat},
+    Result, SlmError,
+};
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Limit of a decompressed payload unless configured, far above the largest pattern
+pub const DEFAULT_MAX_DECOMPRESSED_BYTES: u64 = 256 * 1024 * 1024;
+
+fn format_of(payload: &[u8]) -> Option<CompressionFormat> {
+    if payload.starts_with(&ZSTD_MAGIC) {
+        Some(CompressionFormat::Zstd)
+    } else if payload.starts_with(&GZIP_MAGIC) {
+        Some(CompressionFormat::Gzip)
+    } else {
+        None
+    }
+}
+
+/// The JSON of a received payload, compressed or not; payloads decompressing
+/// to more than `max_bytes` are rejected, so a small one can't exhaust memory
+pub fn decompress(payload: &[u8], max_bytes: u64) -> Result<Cow<'_, [u8]>> {
+    let mut decompressed = Vec::new();
+    // one byte more tells an oversized payload from one at the limit
+    let limit = max_bytes.saturating_add(1);
+    match format_of(payload) {
+        None => return Ok(Cow::Borrowed(payload)),
+        Some(CompressionFormat::Zstd) => {
+            zstd::Decoder::new(payload)?
+                .take(limit)
+                .read_to_end(&mut decompressed)?;
+        }
+        Some(CompressionFormat::Gzip) => {
+            GzDecoder::new(payload)
+                .take(limit)
+                .read_to_end(&mut decompressed)?;
+        }
+    }
+    if decompressed.len() as u64 > max_bytes {
+        Err(SlmError::Request(format!(
+            "Payload decompresses to more than {} bytes",
+            max_bytes
+        )))?
+    }
+    Ok(Cow::Owned(decompressed))
+}
+
+/// Payload to publish; compressed if configured and large enough
+pub fn compress(json: Vec<u8>, config: Option<&CompressionConfig>) -> Result<Vec<u8>> {
+    let config = match config {
+        Some(config) if json.len() >= config.min_bytes => config,
+        _ => return Ok(json),
+    };
+    Ok(match config.format {
+        CompressionFormat::Zstd => zstd::encode_all(json.as_slice(), 0)?,
+        CompressionFormat::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&json)?;
+            encoder.finish()?
+        }
+    })
+}
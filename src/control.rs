@@ -0,0 +1,68 @@
+//! Advisory control lock for shared microscopes: while a client holds control,
+//! other clients can only query the state
+
+use log::info;
+
+use crate::{schema::AimCommand, Context, Result, SlmError};
+
+impl<'a> Context<'a> {
+    /// Reject commands changing state while another client holds control
+    pub fn check_control(
+        &self,
+        aim_command: &AimCommand,
+        client: Option<&str>,
+        topic: &str,
+    ) -> Result<()> {
+        let holder = match &self.state.controlled_by {
+            Some(holder) => holder,
+            None => return Ok(()),
+        };
+        let exempt = match aim_command {
+            // these report conflicts themselves
+            AimCommand::AcquireControl | AimCommand::ReleaseControl => true,
+            aim_command => aim_command.is_query(),
+        };
+        if exempt || client == Some(holder.as_str()) || self.config.overrides_control(topic) {
+            return Ok(());
+        }
+        Err(SlmError::Conflict(format!(
+            "{} holds control; command {} rejected",
+            holder,
+            aim_command.name()
+        )))?
+    }
+
+    pub fn acquire_control(&mut self, client: Option<&str>) -> Result<&mut Self> {
+        let client = match client {
+            Some(client) => client,
+            None => Err(SlmError::Request(
+                "Acquiring control needs a client id".to_owned(),
+            ))?,
+        };
+        match &self.state.controlled_by {
+            Some(holder) if holder != client => Err(SlmError::Conflict(format!(
+                "{} already holds control",
+                holder
+            )))?,
+            _ => (),
+        }
+        info!("{} acquired control", client);
+        self.state.controlled_by = Some(client.to_owned());
+        Ok(self)
+    }
+
+    /// Only the holder may release control, or a client on an override subtopic
+    pub fn release_control(&mut self, client: Option<&str>, topic: &str) -> Result<&mut Self> {
+        match &self.state.controlled_by {
+            Some(holder)
+                if client != Some(holder.as_str()) && !self.config.overrides_control(topic) =>
+            {
+                Err(SlmError::Conflict(format!("{} holds control", holder)))?
+            }
+            Some(holder) => info!("{} released control", holder),
+            None => (),
+        }
+        self.state.controlled_by = None;
+        Ok(self)
+    }
+}
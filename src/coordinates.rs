@@ -0,0 +1,55 @@
+//! Conversion from panel pixels to the coordinate system clients give
+//! the positions of computed patterns in
+
+use crate::{
+    schema::{Config, Origin, Units, YAxis},
+    Array,
+};
+
+/// Size of a panel pixel along x and y, in the units of the coordinate system
+pub fn pixel_size(config: &Config) -> (f32, f32) {
+    match config.coordinates.units {
+        Units::Pixels => (1.0, 1.0),
+        Units::Micrometers => config.slm_geometry.pixel_pitch_um,
+    }
+}
+
+/// Position of the panel pixel `(x, y)` (origin in the top-left pixel, y down)
+pub fn from_panel(config: &Config, (x, y): (f32, f32)) -> (f32, f32) {
+    let coordinates = &config.coordinates;
+    let (size_x, size_y) = config.screen.size;
+    let (x, y) = match coordinates.origin {
+        Origin::Corner => (x, y),
+        Origin::Center => (x - size_x as f32 / 2.0, y - size_y as f32 / 2.0),
+    };
+    let y = match (coordinates.y_axis, coordinates.origin) {
+        (YAxis::Down, _) => y,
+        (YAxis::Up, Origin::Corner) => (size_y - 1) as f32 - y,
+        (YAxis::Up, Origin::Center) => -y,
+    };
+    let (pixel_x, pixel_y) = pixel_size(config);
+    (x * pixel_x, y * pixel_y)
+}
+
+/// Panel pixel grids converted to the coordinate system
+pub fn grids(config: &Config, xx: &Array, yy: &Array) -> (Array, Array) {
+    let mut cx = xx.clone();
+    let mut cy = yy.clone();
+    ndarray::Zip::from(&mut cx).and(&mut cy).apply(|x, y| {
+        let (x_, y_) = from_panel(config, (*x, *y));
+        *x = x_;
+        *y = y_;
+    });
+    (cx, cy)
+}
+
+/// Smallest and largest coordinates of the panel pixel centers
+pub fn bounds(config: &Config) -> ((f32, f32), (f32, f32)) {
+    let (size_x, size_y) = config.screen.size;
+    let first = from_panel(config, (0.0, 0.0));
+    let last = from_panel(config, ((size_x - 1) as f32, (size_y - 1) as f32));
+    (
+        (first.0.min(last.0), first.1.min(last.1)),
+        (first.0.max(last.0), first.1.max(last.1)),
+    )
+}
@@ -0,0 +1,158 @@
+//! Image data sent by clients: `data:` URIs in their MIME variants, or raw
+//! base64 of the file, in the standard or the URL-safe alphabet; also raw
+//! phase samples encoded the same way
+
+use image::ImageFormat;
+
+use crate::{
+    schema::{Endianness, PhaseSamples, SampleType},
+    Array64, Result, SlmError,
+};
+
+/// Decoded image file and the extension it's saved with
+pub struct ImageData {
+    pub extension: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Extension of an image MIME type, e.g. `png` for `image/x-png`
+fn mime_extension(mime: &str) -> Option<String> {
+    let mime = mime.trim().to_ascii_lowercase();
+    let subtype = mime.strip_prefix("image/")?;
+    let subtype = subtype
+        .trim_start_matches("x-ms-")
+        .trim_start_matches("x-")
+        .split('+')
+        .next()?;
+    let extension = match subtype {
+        "pjpeg" => "jpeg",
+        "" => return None,
+        subtype => subtype,
+    };
+    Some(extension.to_owned())
+}
+
+/// Extension of an image recognized by its first bytes
+fn sniffed_extension(bytes: &[u8]) -> Option<String> {
+    let extension = match image::guess_format(bytes).ok()? {
+        ImageFormat::Png => "png",
+        ImageFormat::Jpeg => "jpeg",
+        ImageFormat::Gif => "gif",
+        ImageFormat::Bmp => "bmp",
+        ImageFormat::Tiff => "tiff",
+        _ => return None,
+    };
+    Some(extension.to_owned())
+}
+
+/// Base64 with or without padding, line breaks and whitespace
+fn decode_base64(body: &str) -> Result<Vec<u8>> {
+    let body: String = body.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let body = body.trim_end_matches('=');
+    let config = if body.contains(['-', '_']) {
+        base64::URL_SAFE_NO_PAD
+    } else {
+        base64::STANDARD_NO_PAD
+    };
+    Ok(base64::decode_config(body, config)?)
+}
+
+/// MIME type of a `data:` URI, if it is one, and the decoded data
+fn decode_data(data: &str) -> Result<(Option<&str>, Vec<u8>)> {
+    let data = data.trim();
+    let (mime, body) = match data.get(..5) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("data:") => {
+            let uri = &data[5..];
+            let comma = uri
+                .find(',')
+                .ok_or_else(|| SlmError::Request("image data doesn't have a body".to_owned()))?;
+            let (header, body) = (&uri[..comma], &uri[comma + 1..]);
+            let mut parameters = header.split(';');
+            let mime = parameters.next().unwrap_or_default();
+            if !parameters.any(|parameter| parameter.trim().eq_ignore_ascii_case("base64")) {
+                Err(SlmError::Request(format!(
+                    "image data of type {} isn't base64",
+                    mime
+                )))?
+            }
+            (Some(mime), body)
+        }
+        _ => (None, data),
+    };
+    Ok((mime, decode_base64(body)?))
+}
+
+/// Decode `data`; the extension is `extension` if given, otherwise that of
+/// the MIME type in the header, otherwise recognized from the image itself
+pub fn parse_image_data(data: &str, extension: Option<&str>) -> Result<ImageData> {
+    let (mime, bytes) = decode_data(data)?;
+    let extension = extension
+        .map(|extension| extension.trim_start_matches('.').to_ascii_lowercase())
+        .or_else(|| mime.and_then(mime_extension))
+        .or_else(|| sniffed_extension(&bytes))
+        .ok_or_else(|| {
+            SlmError::Request(match mime {
+                Some(mime) => format!("image data of type {} has no extension", mime),
+                None => "image data has neither a header nor an extension".to_owned(),
+            })
+        })?;
+    if extension.is_empty() || !extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Err(SlmError::Request(format!(
+            "{} is not an image extension",
+            extension
+        )))?
+    }
+    Ok(ImageData { extension, bytes })
+}
+
+/// Phase in radians of raw samples, row by row, in `data` encoded like images
+pub fn parse_phase_samples(data: &str, samples: &PhaseSamples) -> Result<Array64> {
+    let (_, bytes) = decode_data(data)?;
+    let (size_x, size_y) = (samples.size.0 as usize, samples.size.1 as usize);
+    let width = match samples.sample_type {
+        SampleType::U8 => 1,
+        SampleType::U16 => 2,
+        SampleType::F32 => 4,
+    };
+    // the size comes from the client, and may overflow on 32-bit targets
+    let expected = size_x
+        .checked_mul(size_y)
+        .and_then(|count| count.checked_mul(width));
+    if expected != Some(bytes.len()) {
+        Err(SlmError::Request(format!(
+            "{} bytes of phase samples don't make {}x{} samples of {:?}",
+            bytes.len(),
+            size_x,
+            size_y,
+            samples.sample_type
+        )))?
+    }
+
+    let (value_low, value_high) = samples.value_range();
+    let (phase_low, phase_high) = samples.phase_range;
+    if value_low == value_high {
+        Err(SlmError::Request(
+            "Value range of the phase samples is empty".to_owned(),
+        ))?
+    }
+    let scale = f64::from(phase_high - phase_low) / f64::from(value_high - value_low);
+    let big_endian = samples.endianness == Endianness::Big;
+    let value = |sample: &[u8]| -> f64 {
+        match (samples.sample_type, big_endian) {
+            (SampleType::U8, _) => f64::from(sample[0]),
+            (SampleType::U16, false) => f64::from(u16::from_le_bytes([sample[0], sample[1]])),
+            (SampleType::U16, true) => f64::from(u16::from_be_bytes([sample[0], sample[1]])),
+            (SampleType::F32, false) => f64::from(f32::from_le_bytes([
+                sample[0], sample[1], sample[2], sample[3],
+            ])),
+            (SampleType::F32, true) => f64::from(f32::from_be_bytes([
+                sample[0], sample[1], sample[2], sample[3],
+            ])),
+        }
+    };
+    Ok(Array64::from_shape_fn((size_x, size_y), |(x, y)| {
+        let offset = (y * size_x + x) * width;
+        let sample = value(&bytes[offset..offset + width]);
+        f64::from(phase_low) + (sample - f64::from(value_low)) * scale
+    }))
+}
@@ -0,0 +1,154 @@
+//! Backends that put computed patterns on the SLM
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use sdl2::{
+    pixels::PixelFormatEnum,
+    render::{Canvas, Texture, TextureCreator},
+    video::{Window, WindowContext},
+};
+use serialport::{SerialPort, SerialPortSettings};
+
+use crate::{
+    schema::{LengthPrefix, SerialDisplayConfig},
+    Result, SlmError,
+};
+
+pub trait Display {
+    /// Show a quantized pattern indexed by (x, y)
+    fn show(&mut self, pattern: &ndarray::Array2<u8>) -> Result<()>;
+
+    /// Adapt to a changed output (e.g. a new resolution after a cable reseat),
+    /// returning its size if the backend can tell
+    fn reinitialize(&mut self) -> Result<Option<(u32, u32)>> {
+        Ok(None)
+    }
+}
+
+/// Shows nothing, for computing patterns offline
+pub struct NullDisplay;
+
+impl Display for NullDisplay {
+    fn show(&mut self, _pattern: &ndarray::Array2<u8>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// SLM driven as a monitor, through a fullscreen window
+pub struct VideoDisplay<'a, 'b> {
+    canvas: &'a mut Canvas<Window>,
+    creator: &'b TextureCreator<WindowContext>,
+    texture: Texture<'b>,
+    pixels: Vec<u8>,
+    size: (u32, u32),
+}
+
+impl<'a, 'b> VideoDisplay<'a, 'b> {
+    pub fn new(
+        canvas: &'a mut Canvas<Window>,
+        creator: &'b TextureCreator<WindowContext>,
+        (width, height): (u32, u32),
+    ) -> Result<Self> {
+        Ok(VideoDisplay {
+            canvas,
+            creator,
+            texture: creator.create_texture_target(PixelFormatEnum::ARGB8888, width, height)?,
+            pixels: vec![0; (width * height * 4) as usize],
+            size: (width, height),
+        })
+    }
+}
+
+impl<'a, 'b> Display for VideoDisplay<'a, 'b> {
+    fn show(&mut self, pattern: &ndarray::Array2<u8>) -> Result<()> {
+        let (width, height) = self.size;
+        if pattern.dim() != (width as usize, height as usize) {
+            Err(SlmError::Display(format!(
+                "Pattern is {:?} pixels, the display {}x{}",
+                pattern.dim(),
+                width,
+                height
+            )))?
+        }
+        let pixels = &mut self.pixels;
+
+        for (id, value) in pattern.indexed_iter() {
+            let idd = (id.1 * width as usize + id.0) * 4;
+            pixels[idd] = *value;
+            pixels[idd + 1] = *value;
+            pixels[idd + 2] = *value;
+        }
+        let pitch = PixelFormatEnum::ARGB8888.byte_size_of_pixels(width as usize);
+        self.texture.update(None, pixels, pitch)?;
+        self.canvas
+            .copy(&self.texture, None, None)
+            .map_err(SlmError::Display)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    /// Recreate the texture and pixel buffer at the current output size;
+    /// the old texture may be lost after a render device reset
+    fn reinitialize(&mut self) -> Result<Option<(u32, u32)>> {
+        let (width, height) = self.canvas.output_size().map_err(SlmError::Display)?;
+        self.texture =
+            self.creator
+                .create_texture_target(PixelFormatEnum::ARGB8888, width, height)?;
+        self.pixels = vec![0; (width * height * 4) as usize];
+        self.size = (width, height);
+        Ok(Some(self.size))
+    }
+}
+
+/// SLM that takes frames over a serial port, framed as described in the config
+pub struct SerialDisplay {
+    port: Box<dyn SerialPort>,
+    config: SerialDisplayConfig,
+}
+
+impl SerialDisplay {
+    pub fn open(config: &SerialDisplayConfig) -> Result<Self> {
+        let settings = SerialPortSettings {
+            baud_rate: config.baud_rate,
+            timeout: Duration::from_millis(config.timeout_ms),
+            ..Default::default()
+        };
+        Ok(SerialDisplay {
+            port: serialport::open_with_settings(&config.port, &settings)?,
+            config: config.clone(),
+        })
+    }
+}
+
+impl Display for SerialDisplay {
+    fn show(&mut self, pattern: &ndarray::Array2<u8>) -> Result<()> {
+        let mut frame = self.config.header.clone();
+
+        let length = pattern.len() as u32;
+        match self.config.length_prefix {
+            Some(LengthPrefix::U32Le) => frame.extend_from_slice(&length.to_le_bytes()),
+            Some(LengthPrefix::U32Be) => frame.extend_from_slice(&length.to_be_bytes()),
+            None => (),
+        }
+
+        // pattern is indexed by (x, y), rows are sent top to bottom
+        frame.extend(pattern.t().iter());
+        frame.extend_from_slice(&self.config.footer);
+
+        self.port.write_all(&frame)?;
+
+        if let Some(ack) = &self.config.ack {
+            let mut reply = vec![0; ack.len()];
+            self.port.read_exact(&mut reply)?;
+            if &reply != ack {
+                Err(SlmError::Display(format!(
+                    "Serial SLM replied {:?} instead of {:?}",
+                    reply, ack
+                )))?
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+//! Commands from JSON files dropped into a local directory, for debugging on the
+//! microscope when the broker is down; the messages sent in reply are written
+//! next to each command file
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::{Context, Result};
+
+/// How often the directory is checked for new files
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+impl<'a> Context<'a> {
+    /// Process one command file as if its contents arrived on the configured
+    /// subtopic; it's renamed to `*.json.done` afterwards, and the replies go to
+    /// `*.response.json`
+    fn process_command_file(&mut self, path: &Path, subtopic: &str) -> Result<()> {
+        info!("Processing command file {}", path.display());
+        let replies = self.process_local_message(subtopic, fs::read(path)?)?;
+
+        let response = path.with_extension("response.json");
+        serde_json::to_writer_pretty(BufWriter::new(File::create(response)?), &replies)?;
+        fs::rename(path, path.with_extension("json.done"))?;
+        Ok(())
+    }
+
+    pub fn poll_drop_dir(&mut self) -> Result<()> {
+        let drop_dir = match &self.config.drop_dir {
+            Some(drop_dir) => drop_dir.clone(),
+            None => return Ok(()),
+        };
+        if self.state.drop_dir_checked.elapsed() < CHECK_INTERVAL {
+            return Ok(());
+        }
+        self.state.drop_dir_checked = Instant::now();
+
+        let mut paths: Vec<_> = fs::read_dir(&drop_dir.path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let name = path.to_string_lossy();
+                name.ends_with(".json") && !name.ends_with(".response.json")
+            })
+            .collect();
+        // in the order the files are named, e.g. `01-blank.json`, `02-set.json`
+        paths.sort();
+        for path in paths {
+            self.process_command_file(&path, &drop_dir.subtopic)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+//! Binary encodings of messages besides JSON, chosen by a suffix of the topic,
+//! e.g. `<serial>/gui/aim/cbor`; they carry calibration data as byte strings
+//! instead of base64
+
+use mqtt::Message as MqttMessage;
+
+use crate::{
+    compression,
+    schema::{Encoding, Message},
+    Result,
+};
+
+pub const BINARY_ENCODINGS: [Encoding; 2] = [Encoding::Cbor, Encoding::Msgpack];
+
+impl Encoding {
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Encoding::Json => "",
+            Encoding::Cbor => "cbor",
+            Encoding::Msgpack => "msgpack",
+        }
+    }
+
+    pub fn encode(self, message: &Message) -> Result<Vec<u8>> {
+        Ok(match self {
+            Encoding::Json => serde_json::to_vec(message)?,
+            Encoding::Cbor => {
+                let mut payload = Vec::new();
+                ciborium::ser::into_writer(message, &mut payload)?;
+                payload
+            }
+            Encoding::Msgpack => rmp_serde::to_vec_named(message)?,
+        })
+    }
+
+    pub fn decode(self, payload: &[u8]) -> Result<Message> {
+        Ok(match self {
+            Encoding::Json => serde_json::from_slice(payload)?,
+            Encoding::Cbor => ciborium::de::from_reader(payload)?,
+            Encoding::Msgpack => rmp_serde::from_slice(payload)?,
+        })
+    }
+}
+
+/// Topic without the encoding suffix, and the encoding it names
+pub fn split_encoding(topic: &str) -> (&str, Encoding) {
+    for &encoding in BINARY_ENCODINGS.iter() {
+        if let Some(base) = topic
+            .strip_suffix(encoding.suffix())
+            .and_then(|base| base.strip_suffix('/'))
+        {
+            return (base, encoding);
+        }
+    }
+    (topic, Encoding::Json)
+}
+
+/// Topic the message was sent to without the encoding suffix, and the message
+pub fn decode_message(
+    mqtt_message: &MqttMessage,
+    max_decompressed_bytes: u64,
+) -> Result<(&str, Message)> {
+    let (topic, encoding) = split_encoding(mqtt_message.topic());
+    let payload = compression::decompress(mqtt_message.payload(), max_decompressed_bytes)?;
+    let message = encoding.decode(&payload)?;
+    Ok((topic, message))
+}
@@ -0,0 +1,177 @@
+//! Error type of the controller. Every variant is a category with its own code,
+//! which is reported to the client whose request failed.
+
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SlmError {
+    #[error("config error: {0}")]
+    Config(String),
+    #[error("MQTT error: {0}")]
+    Mqtt(#[from] mqtt::MqttError),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// Malformed or invalid request from a client
+    #[error("invalid request: {0}")]
+    Request(String),
+    #[error("pattern error: {0}")]
+    Pattern(String),
+    #[error("calibration error: {0}")]
+    Calibration(String),
+    #[error(
+        "no correction or calibration data for wavelength {wavelength}; available wavelengths: {available:?}"
+    )]
+    UnavailableWavelength {
+        wavelength: u32,
+        available: Vec<u32>,
+    },
+    #[error("display error: {0}")]
+    Display(String),
+    #[error("sensor error: {0}")]
+    Sensor(String),
+    /// The controller isn't in the state the request expected
+    #[error("conflict: {0}")]
+    Conflict(String),
+    /// Factory calibration files are never written or deleted
+    #[error("{0} is a factory file and can't be changed")]
+    Protected(String),
+}
+
+impl SlmError {
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::Config(_) => 1,
+            Self::Mqtt(_) => 2,
+            Self::Io(_) => 3,
+            Self::Request(_) => 4,
+            Self::Pattern(_) => 5,
+            Self::Calibration(_) | Self::UnavailableWavelength { .. } => 6,
+            Self::Display(_) => 7,
+            Self::Sensor(_) => 8,
+            Self::Conflict(_) => 9,
+            Self::Protected(_) => 10,
+        }
+    }
+
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::Config(_) => "config",
+            Self::Mqtt(_) => "mqtt",
+            Self::Io(_) => "io",
+            Self::Request(_) => "request",
+            Self::Pattern(_) => "pattern",
+            Self::Calibration(_) | Self::UnavailableWavelength { .. } => "calibration",
+            Self::Display(_) => "display",
+            Self::Sensor(_) => "sensor",
+            Self::Conflict(_) => "conflict",
+            Self::Protected(_) => "protected",
+        }
+    }
+}
+
+// Conversions for errors of dependencies, sorted into the category they occur in
+
+impl From<serde_json::Error> for SlmError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Request(err.to_string())
+    }
+}
+
+impl From<ciborium::de::Error<io::Error>> for SlmError {
+    fn from(err: ciborium::de::Error<io::Error>) -> Self {
+        Self::Request(format!("invalid CBOR: {}", err))
+    }
+}
+
+impl From<ciborium::ser::Error<io::Error>> for SlmError {
+    fn from(err: ciborium::ser::Error<io::Error>) -> Self {
+        Self::Request(err.to_string())
+    }
+}
+
+impl From<rmp_serde::decode::Error> for SlmError {
+    fn from(err: rmp_serde::decode::Error) -> Self {
+        Self::Request(format!("invalid MessagePack: {}", err))
+    }
+}
+
+impl From<rmp_serde::encode::Error> for SlmError {
+    fn from(err: rmp_serde::encode::Error) -> Self {
+        Self::Request(err.to_string())
+    }
+}
+
+impl From<base64::DecodeError> for SlmError {
+    fn from(err: base64::DecodeError) -> Self {
+        Self::Request(format!("invalid base64: {}", err))
+    }
+}
+
+impl From<ndarray::ShapeError> for SlmError {
+    fn from(err: ndarray::ShapeError) -> Self {
+        Self::Pattern(err.to_string())
+    }
+}
+
+impl From<image::ImageError> for SlmError {
+    fn from(err: image::ImageError) -> Self {
+        Self::Pattern(err.to_string())
+    }
+}
+
+impl From<ndarray_npy::ReadNpyError> for SlmError {
+    fn from(err: ndarray_npy::ReadNpyError) -> Self {
+        Self::Pattern(err.to_string())
+    }
+}
+
+impl From<ndarray_npy::WriteNpyError> for SlmError {
+    fn from(err: ndarray_npy::WriteNpyError) -> Self {
+        Self::Pattern(err.to_string())
+    }
+}
+
+impl From<serialport::Error> for SlmError {
+    fn from(err: serialport::Error) -> Self {
+        Self::Io(err.into())
+    }
+}
+
+#[cfg(feature = "zeromq")]
+impl From<zmq::Error> for SlmError {
+    fn from(err: zmq::Error) -> Self {
+        Self::Io(io::Error::other(err))
+    }
+}
+
+impl From<flexi_logger::FlexiLoggerError> for SlmError {
+    fn from(err: flexi_logger::FlexiLoggerError) -> Self {
+        Self::Config(err.to_string())
+    }
+}
+
+impl From<sdl2::video::WindowBuildError> for SlmError {
+    fn from(err: sdl2::video::WindowBuildError) -> Self {
+        Self::Display(err.to_string())
+    }
+}
+
+impl From<sdl2::IntegerOrSdlError> for SlmError {
+    fn from(err: sdl2::IntegerOrSdlError) -> Self {
+        Self::Display(err.to_string())
+    }
+}
+
+impl From<sdl2::render::TextureValueError> for SlmError {
+    fn from(err: sdl2::render::TextureValueError) -> Self {
+        Self::Display(err.to_string())
+    }
+}
+
+impl From<sdl2::render::UpdateTextureError> for SlmError {
+    fn from(err: sdl2::render::UpdateTextureError) -> Self {
+        Self::Display(err.to_string())
+    }
+}
@@ -0,0 +1,54 @@
+//! Every change of the state on `<serial>/aim/events`, whether a request, a
+//! running sequence or the lasers caused it, so passive observers (a logging
+//! service, a second GUI) can mirror the state without polling `get`
+
+use chrono::Local;
+use log::error;
+
+use crate::{
+    message_loop::send_message,
+    schema::{AimCommand, Message, MessageData, MessageType, StateEvent},
+    util::Subtopic,
+    Context, Result,
+};
+
+impl<'a> Context<'a> {
+    fn events_topic(&self) -> String {
+        self.main_topic_aim.as_str().subtopic("events")
+    }
+
+    /// Publish the state as the next event, unless it's the state of the last one
+    fn publish_state_event(&mut self) -> Result<()> {
+        let state = self.state_report()?;
+        let encoded = serde_json::to_string(&state)?;
+        if self.state.last_event_state.as_ref() == Some(&encoded) {
+            return Ok(());
+        }
+        self.state.last_event_id += 1;
+        self.state.last_event_state = Some(encoded);
+
+        let message = Message {
+            m_type: MessageType::Status,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::StateEvent(Box::new(StateEvent {
+                id: self.state.last_event_id,
+                time: Local::now().to_rfc3339(),
+                state,
+            }))),
+        };
+        if self.offline {
+            return Ok(());
+        }
+        let topic = self.events_topic();
+        send_message(&mut self.client, &topic, &message, &self.config.mqtt)
+    }
+
+    /// Errors are only logged; the change itself went through
+    pub fn state_changed(&mut self) {
+        if let Err(err) = self.publish_state_event() {
+            error!("Error {} while publishing a state event; continuing", err);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Preview of the focal-plane intensity of the displayed pattern,
+//! for sanity-checking holograms without a camera
+
+#[cfg(feature = "far-field")]
+use crate::schema::{AimCommand, Message, MessageData, MessageType};
+use crate::{Context, Result, SlmError};
+
+#[cfg(feature = "far-field")]
+mod simulation {
+    use image::{ImageBuffer, ImageOutputFormat, Luma};
+    use rustfft::{num_complex::Complex, FftPlanner};
+
+    use crate::{phase::level_phase, Array, Result};
+
+    /// Intensity of the Fourier transform of the field, zero frequency in the center
+    pub fn intensity(mut field: ndarray::Array2<Complex<f32>>) -> Array {
+        let (nx, ny) = field.dim();
+        let mut planner = FftPlanner::new();
+
+        let fft_y = planner.plan_fft_forward(ny);
+        for mut row in field.genrows_mut() {
+            let mut buffer = row.to_vec();
+            fft_y.process(&mut buffer);
+            row.assign(&ndarray::Array1::from(buffer));
+        }
+        let fft_x = planner.plan_fft_forward(nx);
+        for mut column in field.gencolumns_mut() {
+            let mut buffer = column.to_vec();
+            fft_x.process(&mut buffer);
+            column.assign(&ndarray::Array1::from(buffer));
+        }
+
+        Array::from_shape_fn((nx, ny), |(x, y)| {
+            field[((x + nx / 2) % nx, (y + ny / 2) % ny)].norm_sqr()
+        })
+    }
+
+    /// Field leaving the panel; binary patterns modulate the amplitude, the others the phase
+    pub fn field(
+        pattern: &ndarray::Array2<u8>,
+        scale: Option<f32>,
+    ) -> ndarray::Array2<Complex<f32>> {
+        match scale {
+            Some(scale) => pattern.mapv(|e| Complex::from_polar(1.0, level_phase(e, scale))),
+            None => pattern.mapv(|e| Complex::new(e as f32 / u8::MAX as f32, 0.0)),
+        }
+    }
+
+    /// Sum blocks of pixels so the longer side fits `size`, then encode the
+    /// log-scaled intensity as a PNG
+    pub fn thumbnail(intensity: &Array, size: u32) -> Result<(u32, u32, Vec<u8>)> {
+        let (nx, ny) = intensity.dim();
+        let block = nx.max(ny).div_ceil(size as usize).max(1);
+        let (width, height) = (nx / block, ny / block);
+
+        let binned = Array::from_shape_fn((width, height), |(x, y)| {
+            let block = intensity.slice(ndarray::s![
+                x * block..(x + 1) * block,
+                y * block..(y + 1) * block
+            ]);
+            block.sum().ln_1p()
+        });
+        let max = binned.fold(0.0_f32, |max, &e| max.max(e));
+
+        let image = ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+            let value = if max > 0.0 {
+                binned[(x as usize, y as usize)] / max
+            } else {
+                0.0
+            };
+            Luma([(value * u8::MAX as f32) as u8])
+        });
+        let mut png = Vec::new();
+        image::DynamicImage::ImageLuma8(image).write_to(&mut png, ImageOutputFormat::Png)?;
+        Ok((width as u32, height as u32, png))
+    }
+}
+
+impl<'a> Context<'a> {
+    #[cfg(feature = "far-field")]
+    pub fn send_far_field(&mut self, thumbnail_size: u32) -> Result<&mut Self> {
+        let pattern = match &self.state.displayed {
+            Some(pattern) => pattern.clone(),
+            None => Err(SlmError::Request("No pattern displayed yet".to_owned()))?,
+        };
+        let scale = match self.config.compute_pattern.binary {
+            Some(_) => None,
+            None => Some(self.scale_factor(self.state.wavelength)?),
+        };
+
+        let intensity = simulation::intensity(simulation::field(&pattern, scale));
+        let (width, height, png) = simulation::thumbnail(&intensity, thumbnail_size.max(1))?;
+
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::FarField {
+                width,
+                height,
+                imagedata: format!("data:image/png;base64,{}", base64::encode(&png)),
+            }),
+        })
+    }
+
+    #[cfg(not(feature = "far-field"))]
+    pub fn send_far_field(&mut self, _thumbnail_size: u32) -> Result<&mut Self> {
+        Err(SlmError::Request(
+            "Far-field simulation needs the far-field feature".to_owned(),
+        ))
+    }
+}
@@ -0,0 +1,139 @@
+//! Semi-automatic registration: the active spot is shown at known SLM positions
+//! one after another, and the camera-side software reports where it sees it
+
+use log::info;
+
+use crate::{
+    coordinates, registration,
+    schema::{
+        AimCommand, Message, MessageData, MessageType, PatternParams, PointPair, ResponseCode,
+        SpotPattern,
+    },
+    Context, Result, SlmError,
+};
+
+pub struct FiducialRun {
+    positions: Vec<(f32, f32)>,
+    index: usize,
+    homography: bool,
+    pairs: Vec<PointPair>,
+    spot: SpotPattern,
+    /// Pattern restored once the routine is over
+    previous: PatternParams,
+}
+
+/// Fractions of the panel size the default fiducial grid is placed at
+const GRID: [f32; 3] = [0.1, 0.5, 0.9];
+
+impl<'a> Context<'a> {
+    fn default_fiducials(&self) -> Vec<(f32, f32)> {
+        let (size_x, size_y) = self.config.screen.size;
+        GRID.iter()
+            .flat_map(|&fy| {
+                GRID.iter().map(move |&fx| {
+                    (
+                        (fx * (size_x - 1) as f32).round(),
+                        (fy * (size_y - 1) as f32).round(),
+                    )
+                })
+            })
+            .map(|point| coordinates::from_panel(&self.config, point))
+            .collect()
+    }
+
+    fn show_fiducial(&mut self, run: &FiducialRun) -> Result<()> {
+        let slm_xy = run.positions[run.index];
+        let spot = SpotPattern {
+            position_xy: slm_xy,
+            ..run.spot.clone()
+        };
+        self.update_state(Some(PatternParams::Spot { spot }), None, None)?;
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Fiducial {
+                index: run.index as u32,
+                total: run.positions.len() as u32,
+                slm_xy,
+            }),
+        })?;
+        Ok(())
+    }
+
+    pub fn start_fiducials(
+        &mut self,
+        positions: Vec<(f32, f32)>,
+        homography: bool,
+    ) -> Result<&mut Self> {
+        self.stop_fiducials()?;
+
+        let spot = match &self.state.pattern_params {
+            PatternParams::Spot { spot } => spot.clone(),
+            _ => Err(SlmError::Request(
+                "Registration fiducials need an active spot pattern".to_owned(),
+            ))?,
+        };
+        let positions = if positions.is_empty() {
+            self.default_fiducials()
+        } else {
+            positions
+        };
+
+        info!("Showing {} registration fiducials", positions.len());
+        let run = FiducialRun {
+            positions,
+            index: 0,
+            homography,
+            pairs: Vec::new(),
+            spot,
+            previous: self.state.pattern_params.clone(),
+        };
+        self.show_fiducial(&run)?;
+        self.state.fiducial_run = Some(run);
+        Ok(self)
+    }
+
+    /// Record where the camera sees the current fiducial and show the next one;
+    /// after the last one, fit and store the registration
+    pub fn fiducial_detected(&mut self, camera_xy: Option<(f32, f32)>) -> Result<&mut Self> {
+        let mut run = match self.state.fiducial_run.take() {
+            Some(run) => run,
+            None => Err(SlmError::Request(
+                "No registration fiducials shown".to_owned(),
+            ))?,
+        };
+
+        match camera_xy {
+            Some(camera_xy) => run.pairs.push(PointPair {
+                camera_xy,
+                slm_xy: run.positions[run.index],
+            }),
+            None => info!("Fiducial {} was not detected; skipping it", run.index),
+        }
+        run.index += 1;
+        if run.index < run.positions.len() {
+            self.show_fiducial(&run)?;
+            self.state.fiducial_run = Some(run);
+            return Ok(self);
+        }
+
+        info!("Fitting registration to {} fiducials", run.pairs.len());
+        self.update_state(Some(run.previous), None, None)?
+            .send_current_state()?;
+        let registration = registration::fit(&run.pairs, run.homography)?;
+        self.store_registration(registration)?
+            .send_response(ResponseCode::RegistrationDone)
+    }
+
+    /// Restore the pattern shown before the routine, if one is running
+    pub fn stop_fiducials(&mut self) -> Result<&mut Self> {
+        if let Some(run) = self.state.fiducial_run.take() {
+            info!("Stopping registration at fiducial {}", run.index);
+            self.update_state(Some(run.previous), None, None)?
+                .send_current_state()?;
+        }
+        Ok(self)
+    }
+}
@@ -0,0 +1,95 @@
+//! Live tilt and fresnel adjustment from an SDL game controller,
+//! so the beam can be aligned by hand at the microscope.
+
+use std::time::{Duration, Instant};
+
+use log::info;
+use sdl2::controller::{Axis, GameController};
+
+use crate::{Context, Result, SlmError};
+
+pub struct Gamepad {
+    controller: GameController,
+    last_poll: Instant,
+    // Fresnel is an integer, so keep the fractional part between polls
+    fresnel_remainder: f32,
+}
+
+impl<'a> Context<'a> {
+    /// Open the first connected game controller, if gamepad control is configured
+    pub fn open_gamepad(&self) -> Result<Option<Gamepad>> {
+        if self.config.gamepad.is_none() {
+            return Ok(None);
+        }
+
+        let subsystem = self
+            .screen_context
+            .sdl_context
+            .game_controller()
+            .map_err(SlmError::Display)?;
+        for id in 0..subsystem.num_joysticks().map_err(SlmError::Display)? {
+            if subsystem.is_game_controller(id) {
+                let controller = subsystem.open(id)?;
+                info!("Opened game controller {}", controller.name());
+                return Ok(Some(Gamepad {
+                    controller,
+                    last_poll: Instant::now(),
+                    fresnel_remainder: 0.0,
+                }));
+            }
+        }
+
+        info!("No game controller found");
+        Ok(None)
+    }
+
+    /// Left stick moves the tilt, right stick (vertical) changes the fresnel.
+    /// Deflection sets the rate of change, so holding the stick keeps moving.
+    pub fn poll_gamepad(&mut self, gamepad: &mut Gamepad) -> Result<()> {
+        let config = match &self.config.gamepad {
+            Some(config) => config.clone(),
+            None => return Ok(()),
+        };
+
+        let elapsed = gamepad.last_poll.elapsed();
+        if elapsed < Duration::from_millis(config.poll_interval_ms) {
+            return Ok(());
+        }
+        gamepad.last_poll = Instant::now();
+        let dt = elapsed.as_secs_f32();
+
+        let axis = |axis| {
+            let value = gamepad.controller.axis(axis) as f32 / i16::MAX as f32;
+            if value.abs() < config.dead_zone {
+                0.0
+            } else {
+                value
+            }
+        };
+        // SDL reports "up" as negative
+        let (tilt_x, tilt_y, fresnel) =
+            (axis(Axis::LeftX), -axis(Axis::LeftY), -axis(Axis::RightY));
+
+        if tilt_x == 0.0 && tilt_y == 0.0 && fresnel == 0.0 {
+            return Ok(());
+        }
+
+        let previous_tilt = self.state.tilt_xy;
+        self.state.tilt_xy.0 += tilt_x * config.tilt_sensitivity * dt;
+        self.state.tilt_xy.1 += tilt_y * config.tilt_sensitivity * dt;
+
+        gamepad.fresnel_remainder += fresnel * config.fresnel_sensitivity * dt;
+        let fresnel_step = gamepad.fresnel_remainder.trunc();
+        gamepad.fresnel_remainder -= fresnel_step;
+        let fresnel = (self.state.fresnel as f32 + fresnel_step).max(0.0) as u32;
+
+        // a tilt that isn't shown mustn't keep accumulating
+        if let Err(err) = self.update_state(None, Some(fresnel), None) {
+            self.state.tilt_xy = previous_tilt;
+            Err(err)?
+        }
+        self.send_current_state()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Uniform frames stepped through the gray levels, for measuring the phase
+//! response curve of the panel with a photodiode
+
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::{
+    schema::{AimCommand, Message, MessageData, MessageType, ResponseCode},
+    Context, Result, SlmError,
+};
+
+pub struct GrayLevelRun {
+    levels: Vec<u8>,
+    index: usize,
+    /// Advance on `nextGrayLevel` commands if not set
+    interval: Option<Duration>,
+    shown_at: Instant,
+}
+
+impl<'a> Context<'a> {
+    fn show_gray_level(&mut self, run: &mut GrayLevelRun) -> Result<()> {
+        let level = run.levels[run.index];
+        let (size_x, size_y) = self.config.screen.size;
+        // raw frames, without any corrections
+        self.put_pattern(&ndarray::Array2::from_elem(
+            (size_x as usize, size_y as usize),
+            level,
+        ))?;
+        run.shown_at = Instant::now();
+        self.checkpoint(run.index as u32);
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::GrayLevel {
+                index: run.index as u32,
+                total: run.levels.len() as u32,
+                level,
+            }),
+        })?;
+        Ok(())
+    }
+
+    /// `first` is the index of the first level shown, when resuming
+    pub fn start_gray_levels(
+        &mut self,
+        step: u8,
+        interval_ms: Option<u64>,
+        first: u32,
+    ) -> Result<&mut Self> {
+        if step == 0 {
+            Err(SlmError::Request(
+                "Gray level step must be positive".to_owned(),
+            ))?
+        }
+        self.state.gray_levels = None;
+
+        let levels: Vec<u8> = (0..=u8::MAX).step_by(step as usize).collect();
+        if first as usize >= levels.len() {
+            Err(SlmError::Request(format!(
+                "Gray level sequence has no level {}",
+                first
+            )))?
+        }
+        info!("Stepping through {} gray levels", levels.len());
+        self.begin_checkpoint(
+            AimCommand::StartGrayLevels { step, interval_ms },
+            levels.len() as u32,
+        );
+        let mut run = GrayLevelRun {
+            levels,
+            index: first as usize,
+            interval: interval_ms.map(Duration::from_millis),
+            shown_at: Instant::now(),
+        };
+        self.show_gray_level(&mut run)?;
+        self.state.gray_levels = Some(run);
+        Ok(self)
+    }
+
+    pub fn next_gray_level(&mut self) -> Result<&mut Self> {
+        let mut run = match self.state.gray_levels.take() {
+            Some(run) => run,
+            None => Err(SlmError::Request(
+                "No gray level sequence running".to_owned(),
+            ))?,
+        };
+
+        run.index += 1;
+        if run.index < run.levels.len() {
+            self.show_gray_level(&mut run)?;
+            self.state.gray_levels = Some(run);
+            Ok(self)
+        } else {
+            info!("Gray level sequence done");
+            self.clear_checkpoint();
+            self.update_state(None, None, None)?
+                .send_response(ResponseCode::GrayLevelsDone)
+        }
+    }
+
+    /// Show the computed pattern again, if a sequence is running
+    pub fn stop_gray_levels(&mut self) -> Result<&mut Self> {
+        if let Some(run) = self.state.gray_levels.take() {
+            info!(
+                "Stopping gray level sequence at level {}",
+                run.levels[run.index]
+            );
+            self.clear_checkpoint();
+            self.update_state(None, None, None)?;
+        }
+        Ok(self)
+    }
+
+    /// Advance timed sequences
+    pub fn poll_gray_levels(&mut self) -> Result<()> {
+        let due = match &self.state.gray_levels {
+            Some(GrayLevelRun {
+                interval: Some(interval),
+                shown_at,
+                ..
+            }) => shown_at.elapsed() >= *interval,
+            _ => false,
+        };
+        if due {
+            self.next_gray_level()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+//! gRPC server mirroring the aim commands (`proto/slm.proto`), for acquisition
+//! platforms that speak it natively; requests are handed to the message loop
+//! and processed like MQTT messages
+
+#[cfg(feature = "grpc")]
+use std::pin::Pin;
+
+#[cfg(feature = "grpc")]
+use log::{error, info};
+#[cfg(feature = "grpc")]
+use tokio::sync::{broadcast, mpsc, oneshot};
+#[cfg(feature = "grpc")]
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+#[cfg(feature = "grpc")]
+use tonic::{transport::Server, Request, Response, Status};
+
+#[cfg(feature = "grpc")]
+use crate::schema::{AimCommand, MessageData, MessageType, StateReport};
+use crate::{schema::Message, Context, Result, SlmError};
+
+#[cfg(feature = "grpc")]
+mod proto {
+    tonic::include_proto!("slm");
+}
+
+/// A request waiting for the message loop
+#[cfg(feature = "grpc")]
+struct Pending {
+    payload: Vec<u8>,
+    reply: oneshot::Sender<std::result::Result<Vec<Message>, String>>,
+}
+
+#[cfg(feature = "grpc")]
+pub struct Grpc {
+    requests: mpsc::UnboundedReceiver<Pending>,
+    states: broadcast::Sender<proto::State>,
+}
+
+#[cfg(feature = "grpc")]
+fn state(report: &StateReport) -> proto::State {
+    proto::State {
+        fresnel: report.fresnel,
+        wavelength: report.wavelength,
+        pattern_hash: report.pattern_hash.clone(),
+        report: serde_json::to_string(report).unwrap_or_default(),
+    }
+}
+
+#[cfg(feature = "grpc")]
+// the handlers have to return tonic's Status as is
+#[allow(clippy::result_large_err)]
+fn reply(replies: Vec<Message>) -> std::result::Result<Response<proto::Reply>, Status> {
+    let messages = replies
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|err| Status::internal(err.to_string()))?;
+    Ok(Response::new(proto::Reply { messages }))
+}
+
+#[cfg(feature = "grpc")]
+fn invalid_json(err: serde_json::Error) -> Status {
+    Status::invalid_argument(err.to_string())
+}
+
+#[cfg(feature = "grpc")]
+struct AimService {
+    requests: mpsc::UnboundedSender<Pending>,
+    states: broadcast::Sender<proto::State>,
+}
+
+#[cfg(feature = "grpc")]
+impl AimService {
+    /// Run the command in the message loop; a failure is reported as an
+    /// error message, like over MQTT, and turned into the status here
+    async fn execute(
+        &self,
+        command: AimCommand,
+        client: String,
+    ) -> std::result::Result<Vec<Message>, Status> {
+        let message = Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: Some(client).filter(|client| !client.is_empty()),
+            data: MessageData::Aim(command),
+        };
+        let payload =
+            serde_json::to_vec(&message).map_err(|err| Status::internal(err.to_string()))?;
+        let (reply, replied) = oneshot::channel();
+        self.requests
+            .send(Pending { payload, reply })
+            .map_err(|_| Status::unavailable("Message loop stopped"))?;
+        let replies = replied
+            .await
+            .map_err(|_| Status::unavailable("Message loop stopped"))?
+            .map_err(Status::internal)?;
+
+        let failure = replies.iter().find_map(|message| match &message.data {
+            MessageData::Aim(AimCommand::Error { message, .. }) => Some(message.clone()),
+            _ => None,
+        });
+        match failure {
+            Some(message) => Err(Status::failed_precondition(message)),
+            None => Ok(replies),
+        }
+    }
+}
+
+#[cfg(feature = "grpc")]
+#[tonic::async_trait]
+impl proto::aim_server::Aim for AimService {
+    async fn set_pattern(
+        &self,
+        request: Request<proto::SetPatternRequest>,
+    ) -> std::result::Result<Response<proto::Reply>, Status> {
+        let request = request.into_inner();
+        let pattern = serde_json::from_str(&request.pattern).map_err(invalid_json)?;
+        reply(
+            self.execute(
+                AimCommand::SetPattern {
+                    pattern,
+                    raw: false,
+                },
+                request.client,
+            )
+            .await?,
+        )
+    }
+
+    async fn set_fresnel(
+        &self,
+        request: Request<proto::SetFresnelRequest>,
+    ) -> std::result::Result<Response<proto::Reply>, Status> {
+        let request = request.into_inner();
+        reply(
+            self.execute(
+                AimCommand::SetFresnel {
+                    value: request.value,
+                },
+                request.client,
+            )
+            .await?,
+        )
+    }
+
+    async fn get_state(
+        &self,
+        _request: Request<proto::GetStateRequest>,
+    ) -> std::result::Result<Response<proto::State>, Status> {
+        let replies = self.execute(AimCommand::Get, String::new()).await?;
+        replies
+            .iter()
+            .find_map(|message| match &message.data {
+                MessageData::Aim(AimCommand::State(report)) => Some(Response::new(state(report))),
+                _ => None,
+            })
+            .ok_or_else(|| Status::internal("No state in the reply"))
+    }
+
+    type WatchStateStream =
+        Pin<Box<dyn Stream<Item = std::result::Result<proto::State, Status>> + Send>>;
+
+    async fn watch_state(
+        &self,
+        _request: Request<proto::GetStateRequest>,
+    ) -> std::result::Result<Response<Self::WatchStateStream>, Status> {
+        // states missed by slow clients are skipped
+        let states =
+            BroadcastStream::new(self.states.subscribe()).filter_map(|state| state.ok().map(Ok));
+        Ok(Response::new(Box::pin(states)))
+    }
+
+    async fn send(
+        &self,
+        request: Request<proto::Command>,
+    ) -> std::result::Result<Response<proto::Reply>, Status> {
+        let request = request.into_inner();
+        let command = serde_json::from_str(&request.data).map_err(invalid_json)?;
+        reply(self.execute(command, request.client).await?)
+    }
+}
+
+impl<'a> Context<'a> {
+    /// Serve gRPC on a runtime of its own, in a background thread
+    #[cfg(feature = "grpc")]
+    pub fn open_grpc(&mut self) -> Result<()> {
+        let config = match &self.config.grpc {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        let address = config.address.parse().map_err(|err| {
+            SlmError::Config(format!("Invalid gRPC address {}: {}", config.address, err))
+        })?;
+
+        let (requests_sender, requests) = mpsc::unbounded_channel();
+        let (states, _) = broadcast::channel(16);
+        let service = AimService {
+            requests: requests_sender,
+            states: states.clone(),
+        };
+        let runtime = tokio::runtime::Runtime::new()?;
+        std::thread::spawn(move || {
+            let server = Server::builder()
+                .add_service(proto::aim_server::AimServer::new(service))
+                .serve(address);
+            if let Err(err) = runtime.block_on(server) {
+                error!("gRPC server stopped: {}", err);
+            }
+        });
+        info!("Serving gRPC on {}", address);
+
+        self.state.grpc = Some(Grpc { requests, states });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "grpc"))]
+    pub fn open_grpc(&mut self) -> Result<()> {
+        match self.config.grpc {
+            Some(_) => Err(SlmError::Config(
+                "The gRPC server needs the grpc feature".to_owned(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Process a pending request, if there is one
+    #[cfg(feature = "grpc")]
+    pub fn poll_grpc(&mut self) -> Result<()> {
+        let pending = match &mut self.state.grpc {
+            Some(grpc) => match grpc.requests.try_recv() {
+                Ok(pending) => pending,
+                Err(_) => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        let subtopic = self
+            .config
+            .grpc
+            .as_ref()
+            .map_or("grpc/aim".to_owned(), |config| config.subtopic.clone());
+        let result = self
+            .process_local_message(&subtopic, pending.payload)
+            .map_err(|err| err.to_string());
+        // the client may have given up waiting
+        let _ = pending.reply.send(result);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "grpc"))]
+    pub fn poll_grpc(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Hand published states to the `WatchState` streams
+    #[cfg(feature = "grpc")]
+    pub fn publish_grpc(&self, message: &Message) {
+        if let (Some(grpc), MessageData::Aim(AimCommand::State(report))) =
+            (&self.state.grpc, &message.data)
+        {
+            // fails only without watchers
+            let _ = grpc.states.send(state(report));
+        }
+    }
+
+    #[cfg(not(feature = "grpc"))]
+    pub fn publish_grpc(&self, _message: &Message) {}
+}
@@ -0,0 +1,136 @@
+//! Disk, CPU and memory of the controller's computer, published with the status;
+//! a full disk has made uploads and correction saves fail before
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::error;
+
+use crate::{schema::SystemHealth, Context, Result};
+
+/// Which warnings were sent, so each is only sent once per incident
+#[derive(Default)]
+pub struct HealthWarnings {
+    low_disk: bool,
+    high_memory: bool,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Free space of the partition holding `path` for unprivileged users
+#[cfg(unix)]
+fn disk_free_bytes(path: &Path) -> Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| invalid_data(format!("Invalid path: {}", err)))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        Err(io::Error::last_os_error())?
+    }
+    let stat = unsafe { stat.assume_init() };
+    // the fields are u32 on some platforms
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn disk_free_bytes(_path: &Path) -> Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Free disk space is only read on unix",
+    ))?
+}
+
+fn load_average() -> Result<f32> {
+    let loadavg = fs::read_to_string("/proc/loadavg")?;
+    loadavg
+        .split_whitespace()
+        .next()
+        .and_then(|load| load.parse().ok())
+        .ok_or_else(|| invalid_data(format!("Unexpected /proc/loadavg: {}", loadavg)).into())
+}
+
+fn memory_used_percent() -> Result<f32> {
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    let field = |name: &str| -> Option<f32> {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(name))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|kib| kib.parse().ok())
+    };
+    match (field("MemTotal:"), field("MemAvailable:")) {
+        (Some(total), Some(available)) if total > 0.0 => Ok(100.0 * (1.0 - available / total)),
+        _ => Err(invalid_data(
+            "MemTotal or MemAvailable missing from /proc/meminfo".to_owned(),
+        ))?,
+    }
+}
+
+/// The logger writes its rotated `.log` files to the working directory
+fn log_bytes() -> Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(".")? {
+        let entry = entry?;
+        if entry.path().extension().is_some_and(|ext| ext == "log") {
+            total += entry.metadata()?.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Value of a reading, logging why it's missing
+fn reading<T>(what: &str, result: Result<T>) -> Option<T> {
+    result
+        .map_err(|err| error!("Can't read {}: {}", what, err))
+        .ok()
+}
+
+impl<'a> Context<'a> {
+    /// Read the system health, warning once when a threshold is crossed
+    pub fn check_health(&mut self) -> Result<SystemHealth> {
+        let health = SystemHealth {
+            disk_free_bytes: reading(
+                "free disk space",
+                disk_free_bytes(&self.config.dir_path.base_patterns),
+            ),
+            load_average: reading("load average", load_average()),
+            memory_used_percent: reading("memory use", memory_used_percent()),
+            log_bytes: reading("log size", log_bytes()),
+            clock_synchronized: self.state.clock.synchronized,
+            clock_offset_ms: self.state.clock.offset_ms,
+        };
+
+        let (min_disk_free_mb, max_memory_used_percent) = match &self.config.status {
+            Some(status) => (status.min_disk_free_mb, status.max_memory_used_percent),
+            None => return Ok(health),
+        };
+        let low_disk = health
+            .disk_free_bytes
+            .is_some_and(|free| free < min_disk_free_mb * 1_000_000);
+        if low_disk && !self.state.health_warnings.low_disk {
+            self.send_warning(format!(
+                "Only {} MB free for patterns and corrections",
+                health.disk_free_bytes.unwrap_or_default() / 1_000_000
+            ))?;
+        }
+        self.state.health_warnings.low_disk = low_disk;
+
+        let high_memory = health
+            .memory_used_percent
+            .is_some_and(|used| used > max_memory_used_percent);
+        if high_memory && !self.state.health_warnings.high_memory {
+            self.send_warning(format!(
+                "{:.0} % of the memory is in use",
+                health.memory_used_percent.unwrap_or_default()
+            ))?;
+        }
+        self.state.health_warnings.high_memory = high_memory;
+        Ok(health)
+    }
+}
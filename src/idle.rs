@@ -0,0 +1,132 @@
+//! Burn-in protection: liquid crystal panels degrade if a static pattern with
+//! strong DC is shown for days, so the pattern keeps changing while no commands arrive
+
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::{
+    phase::wrap_level,
+    schema::{AimCommand, IdleMode, Message, MessageData, MessageType},
+    Context, Result,
+};
+
+/// How often the drifting pattern is updated
+const DRIFT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Idle {
+    last_activity: Instant,
+    /// When burn-in protection started, `None` while the pattern is shown as is
+    since: Option<Instant>,
+    shown_at: Instant,
+}
+
+impl Idle {
+    pub fn new() -> Self {
+        Idle {
+            last_activity: Instant::now(),
+            since: None,
+            shown_at: Instant::now(),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.since.is_some()
+    }
+}
+
+impl<'a> Context<'a> {
+    fn send_idle(&mut self, idle: bool) -> Result<&mut Self> {
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Idle { idle }),
+        })
+    }
+
+    /// Restart the idle timeout, showing the actual pattern again if protection was on
+    pub fn wake_from_idle(&mut self) -> Result<()> {
+        self.state.idle.last_activity = Instant::now();
+        if self.state.idle.since.take().is_some() {
+            info!("Ending burn-in protection");
+            if let Some(pattern) = &self.state.displayed {
+                self.screen_context.display.show(pattern)?;
+            }
+            self.send_idle(false)?;
+        }
+        Ok(())
+    }
+
+    /// The displayed pattern shifted or inverted as the idle mode asks for
+    fn idle_frame(
+        &self,
+        mode: &IdleMode,
+        pattern: &ndarray::Array2<u8>,
+        elapsed: Duration,
+    ) -> Result<ndarray::Array2<u8>> {
+        // binary patterns can only be inverted
+        let scale = match self.config.compute_pattern.binary {
+            Some(_) => None,
+            None => Some(self.scale_factor(self.state.wavelength)?),
+        };
+        let inverted = |pattern: &ndarray::Array2<u8>| match scale {
+            Some(scale) => pattern.mapv(|e| wrap_level(scale - f32::from(e), scale)),
+            None => pattern.mapv(|e| u8::MAX - e),
+        };
+
+        Ok(match (mode, scale) {
+            (IdleMode::Drift { period_secs }, Some(scale)) => {
+                let cycle = (elapsed.as_secs_f32() / (*period_secs).max(1) as f32).fract();
+                pattern.mapv(|e| wrap_level(f32::from(e) + cycle * scale, scale))
+            }
+            (IdleMode::Drift { period_secs }, None) | (IdleMode::Invert { period_secs }, _) => {
+                if elapsed.as_secs() / (*period_secs).max(1) % 2 == 1 {
+                    inverted(pattern)
+                } else {
+                    pattern.clone()
+                }
+            }
+        })
+    }
+
+    /// Start burn-in protection after the timeout and keep the pattern changing
+    pub fn poll_idle(&mut self) -> Result<()> {
+        let (timeout, mode) = match &self.config.idle {
+            Some(config) => (
+                Duration::from_secs(config.timeout_secs),
+                config.mode.clone(),
+            ),
+            None => return Ok(()),
+        };
+        let interval = match mode {
+            IdleMode::Drift { .. } => DRIFT_INTERVAL,
+            IdleMode::Invert { period_secs } => Duration::from_secs(period_secs.max(1)),
+        };
+
+        let idle = &self.state.idle;
+        let since = match idle.since {
+            Some(_) if idle.shown_at.elapsed() < interval => return Ok(()),
+            Some(since) => since,
+            None if idle.last_activity.elapsed() < timeout => return Ok(()),
+            None => {
+                info!(
+                    "No commands for {} s; starting burn-in protection",
+                    timeout.as_secs()
+                );
+                let since = Instant::now();
+                self.state.idle.since = Some(since);
+                self.send_idle(true)?;
+                since
+            }
+        };
+
+        self.state.idle.shown_at = Instant::now();
+        if let Some(pattern) = &self.state.displayed {
+            let frame = self.idle_frame(&mode, pattern, since.elapsed())?;
+            self.screen_context.display.show(&frame)?;
+        }
+        Ok(())
+    }
+}
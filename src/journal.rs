@@ -0,0 +1,59 @@
+//! Journal of state changes and who made them, so unexpected pattern changes
+//! can be traced to a client
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use chrono::Local;
+use log::error;
+use serde::Serialize;
+
+use crate::{precondition::pattern_hash, Context, Result};
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+/// The journal is moved to `journal.jsonl.1` once it grows past this size
+const MAX_JOURNAL_BYTES: u64 = 10 * 1024 * 1024;
+
+/// One line of the journal: the command and the state it left the controller in
+#[derive(Serialize)]
+struct JournalEntry<'e> {
+    time: String,
+    origin: &'e str,
+    command: &'e str,
+    wavelength: u32,
+    fresnel: u32,
+    pattern_hash: String,
+}
+
+fn append(entry: &JournalEntry) -> Result<()> {
+    if fs::metadata(JOURNAL_FILE).is_ok_and(|metadata| metadata.len() > MAX_JOURNAL_BYTES) {
+        fs::rename(JOURNAL_FILE, format!("{}.1", JOURNAL_FILE))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(JOURNAL_FILE)?;
+    serde_json::to_writer(&mut file, entry)?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
+
+impl<'a> Context<'a> {
+    /// Append the current state to the journal; errors are only logged,
+    /// the command was carried out already
+    pub fn journal(&self, origin: &str, command: &str) {
+        let result = pattern_hash(&self.state.pattern_params).and_then(|pattern_hash| {
+            append(&JournalEntry {
+                time: Local::now().to_rfc3339(),
+                origin,
+                command,
+                wavelength: self.state.wavelength,
+                fresnel: self.state.fresnel,
+                pattern_hash,
+            })
+        });
+        if let Err(err) = result {
+            error!("Error {} while writing the journal; continuing", err);
+        }
+    }
+}
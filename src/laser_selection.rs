@@ -0,0 +1,47 @@
+//! Wavelength the pattern is computed for, selected from the laser states the
+//! embedded controller reports
+
+use std::time::Instant;
+
+use log::info;
+
+use crate::{schema::LaserState, Context};
+
+impl<'a> Context<'a> {
+    /// Wavelength of the strongest enabled laser, or `None` if none is on;
+    /// the selected laser is kept within the hysteresis margin and dwell time,
+    /// so lasers of similar intensity don't cause a recompute on every update
+    pub fn select_wavelength(&mut self, lasers: &[LaserState]) -> Option<u32> {
+        let selection = &self.config.laser_selection;
+        let enabled: Vec<_> = lasers
+            .iter()
+            .filter(|laser| laser.state && selection.is_selectable(&laser.name))
+            .collect();
+        let strongest = enabled
+            .iter()
+            .max_by(|a, b| a.intensity.total_cmp(&b.intensity))?;
+
+        let current = enabled
+            .iter()
+            .find(|laser| laser.wavelength == self.state.wavelength);
+        if let Some(current) = current {
+            if current.wavelength != strongest.wavelength {
+                let dwelling = self.state.wavelength_selected.elapsed().as_secs_f32()
+                    < selection.min_dwell_secs;
+                let margin = strongest.intensity - current.intensity;
+                if dwelling || margin <= selection.hysteresis_percent {
+                    info!(
+                        "Keeping wavelength {} ({} points below {})",
+                        current.wavelength, margin, strongest.wavelength
+                    );
+                    return Some(current.wavelength);
+                }
+            }
+        }
+
+        if strongest.wavelength != self.state.wavelength {
+            self.state.wavelength_selected = Instant::now();
+        }
+        Some(strongest.wavelength)
+    }
+}
@@ -0,0 +1,92 @@
+//! Synthetic laser messages from a scenario file (`--simulate-lasers`), published
+//! on the embedded lasers topic, to test the wavelength selection end to end
+//! without the embedded controller
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use log::info;
+use mqtt::Message as MqttMessage;
+use serde::Deserialize;
+
+use crate::{
+    schema::{LaserCommand, LaserState, Message, MessageData, MessageType},
+    util::Subtopic,
+    Context, Result,
+};
+
+#[derive(Deserialize)]
+struct ScenarioStep {
+    /// Delay after the previous step
+    after_ms: u64,
+    lasers: Vec<LaserState>,
+}
+
+#[derive(Deserialize)]
+struct Scenario {
+    steps: Vec<ScenarioStep>,
+    /// Start over after the last step
+    #[serde(default)]
+    repeat: bool,
+}
+
+pub struct LaserSimulator {
+    scenario: Scenario,
+    index: usize,
+    last_step: Instant,
+}
+
+impl LaserSimulator {
+    pub fn load(path: &Path) -> Result<Self> {
+        let scenario: Scenario = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        info!(
+            "Simulating lasers with {} steps from {}",
+            scenario.steps.len(),
+            path.display()
+        );
+        Ok(LaserSimulator {
+            scenario,
+            index: 0,
+            last_step: Instant::now(),
+        })
+    }
+}
+
+impl<'a> Context<'a> {
+    /// Publish the next laser state of the scenario once it's due
+    pub fn poll_laser_simulator(&mut self) -> Result<()> {
+        let simulator = match &mut self.state.laser_simulator {
+            Some(simulator) => simulator,
+            None => return Ok(()),
+        };
+        if simulator.index >= simulator.scenario.steps.len() {
+            if !simulator.scenario.repeat || simulator.scenario.steps.is_empty() {
+                return Ok(());
+            }
+            simulator.index = 0;
+        }
+        let step = &simulator.scenario.steps[simulator.index];
+        if simulator.last_step.elapsed() < Duration::from_millis(step.after_ms) {
+            return Ok(());
+        }
+        simulator.last_step = Instant::now();
+        simulator.index += 1;
+
+        let message = Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Lasers(LaserCommand::Set {
+                lasers: step.lasers.clone(),
+            }),
+        };
+        let topic = self.config.main_topic().subtopic("embedded/lasers");
+        info!("Simulating laser message on {}", topic);
+        self.client
+            .publish(MqttMessage::new(topic, serde_json::to_vec(&message)?, 0))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+//! Latency measurement of the whole update chain, from decoding a command to the
+//! pattern being on the panel (optionally confirmed by a photodiode)
+
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use serialport::{ClearBuffer, SerialPortSettings};
+
+use crate::{
+    schema::{
+        AimCommand, AimState, HistogramBin, LatencyReport, Message, MessageData, MessageType,
+        Space, StageLatency,
+    },
+    Context, Result, SlmError,
+};
+
+/// Upper bounds of the histogram bins, in milliseconds
+const BIN_EDGES_MS: [f64; 9] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0];
+
+fn stage_latency(stage: &str, samples: &[Duration]) -> StageLatency {
+    let samples_ms: Vec<f64> = samples.iter().map(|d| d.as_secs_f64() * 1e3).collect();
+
+    let mut histogram: Vec<HistogramBin> = BIN_EDGES_MS
+        .iter()
+        .map(|&upper| HistogramBin {
+            upper_ms: Some(upper),
+            count: 0,
+        })
+        .chain(std::iter::once(HistogramBin {
+            upper_ms: None,
+            count: 0,
+        }))
+        .collect();
+    for &sample in &samples_ms {
+        let bin = BIN_EDGES_MS
+            .iter()
+            .position(|&upper| sample <= upper)
+            .unwrap_or(BIN_EDGES_MS.len());
+        histogram[bin].count += 1;
+    }
+
+    StageLatency {
+        stage: stage.to_owned(),
+        min_ms: samples_ms.iter().cloned().fold(f64::INFINITY, f64::min),
+        mean_ms: samples_ms.iter().sum::<f64>() / samples_ms.len().max(1) as f64,
+        max_ms: samples_ms.iter().cloned().fold(0.0, f64::max),
+        histogram,
+    }
+}
+
+impl<'a> Context<'a> {
+    pub fn measure_latency(&mut self, iterations: u32, use_photodiode: bool) -> Result<&mut Self> {
+        if iterations == 0 {
+            Err(SlmError::Request(
+                "Latency measurement needs at least one iteration".to_owned(),
+            ))?
+        }
+        let mut photodiode = match (&self.config.photodiode, use_photodiode) {
+            (Some(config), true) => {
+                let settings = SerialPortSettings {
+                    baud_rate: config.baud_rate,
+                    timeout: Duration::from_millis(config.timeout_ms),
+                    ..Default::default()
+                };
+                Some(serialport::open_with_settings(&config.port, &settings)?)
+            }
+            (None, true) => Err(SlmError::Config("No photodiode configured".to_owned()))?,
+            (_, false) => None,
+        };
+
+        // Decoding is measured on the message that would set the current state
+        let payload = serde_json::to_vec(&AimState {
+            pattern: self.state.pattern_params.clone(),
+            fresnel: self.state.fresnel,
+            space: Space::Slm,
+            raw: self.state.raw,
+        })?;
+
+        let (mut decode, mut compute, mut present, mut optical) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+
+        for iteration in 0..iterations {
+            let start = Instant::now();
+            let _: AimState = serde_json::from_slice(&payload)?;
+            decode.push(start.elapsed());
+
+            let start = Instant::now();
+            let mut pattern = self.compute_pattern()?;
+            compute.push(start.elapsed());
+
+            // Alternate with the inverted pattern, so every update is visible to the photodiode
+            if iteration % 2 == 1 {
+                pattern.mapv_inplace(|e| 255 - e);
+            }
+
+            if let Some(port) = &mut photodiode {
+                port.clear(ClearBuffer::Input)?;
+            }
+
+            let start = Instant::now();
+            self.put_pattern(&pattern)?;
+            present.push(start.elapsed());
+
+            if let Some(port) = &mut photodiode {
+                port.read_exact(&mut [0; 1])?;
+                optical.push(start.elapsed());
+            }
+        }
+
+        // Leave the panel showing the actual state
+        let pattern = self.compute_pattern()?;
+        self.put_pattern(&pattern)?;
+
+        let mut stages = vec![
+            stage_latency("decode", &decode),
+            stage_latency("compute", &compute),
+            stage_latency("present", &present),
+        ];
+        if photodiode.is_some() {
+            stages.push(stage_latency("photodiode", &optical));
+        }
+
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Latency(LatencyReport { iterations, stages })),
+        })
+    }
+}
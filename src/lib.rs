@@ -0,0 +1,534 @@
+//! SLM controller: computes patterns from MQTT commands and shows them on the
+//! SLM. The binary runs it; the library is also used by the Python bindings.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    Local, Utc,
+};
+use flexi_logger::{DeferredNow, LogSpecification, Logger};
+use log::{info, Record as LogRecord};
+use mqtt::{Client, ConnectOptionsBuilder, Message as MqttMessage};
+use sdl2::Sdl;
+
+pub type Array = ndarray::Array2<f32>;
+pub type Array64 = ndarray::Array2<f64>;
+pub type Dim = ndarray::Dim<[usize; 2]>;
+
+pub const TWO_PI: f32 = std::f32::consts::PI * 2.0;
+
+mod backup;
+mod batch;
+mod build_info;
+mod calibration;
+mod capture;
+mod checkpoint;
+mod clock;
+mod compression;
+mod control;
+pub mod coordinates;
+mod data_uri;
+pub mod display;
+mod drop_dir;
+mod encoding;
+mod error;
+mod events;
+mod far_field;
+mod fiducials;
+mod gamepad;
+mod gray_levels;
+mod grpc;
+mod health;
+mod idle;
+mod journal;
+mod laser_selection;
+mod laser_simulator;
+mod latency;
+mod maintenance;
+pub mod manifest;
+mod message_loop;
+mod metrics;
+mod morph;
+mod overdrive;
+mod parameters;
+mod pattern_algebra;
+mod pattern_names;
+pub mod patterns;
+mod phase;
+mod precondition;
+mod prestack;
+mod probe;
+mod profile;
+mod rate_limit;
+mod raw_frames;
+mod registration;
+pub mod render;
+mod scan;
+mod scheduler;
+pub mod schema;
+pub mod send;
+mod sensors;
+mod session_log;
+mod shared_memory;
+mod stage_dumps;
+mod startup;
+mod status;
+pub mod storage;
+mod sweep;
+mod update;
+mod util;
+mod video_stream;
+mod zeromq;
+
+use calibration::CalibrationStore;
+use clock::ClockCheck;
+use display::{Display, SerialDisplay, VideoDisplay};
+use fiducials::FiducialRun;
+use gray_levels::GrayLevelRun;
+use health::HealthWarnings;
+use idle::Idle;
+use laser_simulator::LaserSimulator;
+use manifest::Manifest;
+use metrics::Metrics;
+use morph::MorphRun;
+use prestack::Prestack;
+use probe::ProbeRun;
+use rate_limit::RateLimiter;
+use scan::ScanRun;
+use scheduler::Schedule;
+use schema::{
+    AimCommand, AstigmaticFresnel, Config, DiffractionOrder, DisplayBackend, Message, MessageData,
+    MessageType, PatternParams, Registration, ScreenMode, SequenceCheckpoint,
+    DEFAULT_TIMESTAMP_FORMAT,
+};
+use shared_memory::SharedFrames;
+use sweep::FresnelSweepRun;
+use update::PendingUpdate;
+use util::Subtopic;
+use video_stream::VideoStream;
+
+pub use error::SlmError;
+
+pub type Result<T> = std::result::Result<T, SlmError>;
+
+pub struct ScreenContext<'a> {
+    pub display: Box<dyn Display + 'a>,
+    pub sdl_context: &'a Sdl,
+    /// Display mode the video backend got, for the startup summary
+    pub mode: Option<ScreenMode>,
+}
+
+pub struct State {
+    pub wavelength: u32,
+    pub fresnel: u32,
+    pub pattern_params: PatternParams,
+    /// Active config profile
+    pub profile: Option<String>,
+    /// Additional phase gradient in radians per pixel, adjusted live from a gamepad
+    pub tilt_xy: (f32, f32),
+    /// Added to the fresnel, for astigmatic beams
+    pub astigmatic_fresnel: Option<AstigmaticFresnel>,
+    /// Percentage by which the phase modulation depth is reduced
+    pub attenuation: f32,
+    /// Sign of the blaze slope
+    pub diffraction_order: DiffractionOrder,
+    pub calibration: CalibrationStore,
+    /// Corrections applied to the currently displayed pattern
+    pub applied_corrections: Vec<String>,
+    /// Wavelength last warned about for a missing flatness correction, so the
+    /// warning isn't repeated for every frame
+    pub missing_correction_warned: Option<u32>,
+    /// Latest successful sensor readings, by sensor name
+    pub temperatures: HashMap<String, f32>,
+    pub overdrive_lut: Option<ndarray::Array2<u8>>,
+    /// Pattern currently on the SLM
+    pub displayed: Option<ndarray::Array2<u8>>,
+    /// Phase range of the last computed pattern, before wrapping
+    pub phase_range: (f32, f32),
+    /// Fraction of pixels of the last computed pattern that had to be wrapped
+    pub wrapped_fraction: f32,
+    /// Sequence numbers of the recently processed commands, oldest first, by
+    /// topic and client id, since several clients may share a topic
+    pub processed_seqs: HashMap<(String, Option<String>), VecDeque<u64>>,
+    pub rate_limiter: RateLimiter,
+    pub metrics: Metrics,
+    /// Encoded state report that was published last
+    pub last_published_state: Option<String>,
+    /// Set while a command runs, so the state it publishes is sent even if unchanged
+    pub handling_request: bool,
+    /// Id and encoded state of the last event on the events topic
+    pub last_event_id: u64,
+    pub last_event_state: Option<String>,
+    /// Custom patterns were added or removed since they were last announced
+    pub available_patterns_changed: bool,
+    pub probe_run: Option<ProbeRun>,
+    pub fresnel_sweep: Option<FresnelSweepRun>,
+    pub morph: Option<MorphRun>,
+    /// Of the running sequence
+    pub checkpoint: Option<SequenceCheckpoint>,
+    /// Of a sequence interrupted by a restart, until resumed or discarded
+    pub resumable: Option<SequenceCheckpoint>,
+    pub scan: Option<ScanRun>,
+    /// Scheduled tasks added over MQTT
+    pub schedule: Schedule,
+    /// Camera to SLM mapping for positions given in camera space
+    pub registration: Option<Registration>,
+    pub fiducial_run: Option<FiducialRun>,
+    pub idle: Idle,
+    pub gray_levels: Option<GrayLevelRun>,
+    /// Set while the commands of a batch run, which defers computing the pattern
+    /// and publishing the state
+    pub batching: bool,
+    /// Client holding control
+    pub controlled_by: Option<String>,
+    /// Origin of the last command that changed state
+    pub last_modified_by: Option<String>,
+    pub prestack: Option<Prestack>,
+    pub health_warnings: HealthWarnings,
+    pub clock: ClockCheck,
+    /// Update downloading in the background
+    pub update: Option<PendingUpdate>,
+    /// Laser and GUI messages aren't applied while service engineers work
+    pub maintenance: bool,
+    /// The pattern image is shown without blaze, fresnel or corrections
+    pub raw: bool,
+    /// The panel is blanked by the schedule until the next pattern is set
+    pub blanked: bool,
+    /// Messages sent while set, as replies to a command file
+    pub captured: Option<Vec<Message>>,
+    pub drop_dir_checked: Instant,
+    pub laser_simulator: Option<LaserSimulator>,
+    /// When the wavelength was last switched to another laser
+    pub wavelength_selected: Instant,
+    pub shared_frames: Option<SharedFrames>,
+    pub video_stream: Option<VideoStream>,
+    /// Start of this run, naming its capture directory and session log
+    pub capture_session: String,
+    pub frames_captured: u32,
+    #[cfg(feature = "hdf5")]
+    pub session_log: Option<session_log::SessionLog>,
+    #[cfg(feature = "zeromq")]
+    pub zeromq: Option<zeromq::ZeroMq>,
+    #[cfg(feature = "grpc")]
+    pub grpc: Option<grpc::Grpc>,
+    pub cache: HashMap<PathBuf, Array>,
+    /// Base patterns are listed and looked up by file name without one
+    pub manifest: Option<Manifest>,
+}
+pub struct Context<'a> {
+    pub config: Config,
+    pub client: Client,
+    pub screen_context: ScreenContext<'a>,
+    pub state: State,
+    pub main_topic_aim: String, // We need this a lot, might as well precalucalate it
+    /// Messages are only logged instead of published, for the command-line tools
+    pub offline: bool,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(
+        config: Config,
+        client: Client,
+        screen_context: ScreenContext<'a>,
+        state: State,
+    ) -> Self {
+        Context {
+            main_topic_aim: config.main_topic().subtopic("aim"),
+            config,
+            screen_context,
+            client,
+            state,
+            offline: false,
+        }
+    }
+}
+
+fn last_will_message(config: &Config) -> MqttMessage {
+    let message = Message {
+        m_type: MessageType::Device,
+        seq: None,
+        expect: None,
+        client: None,
+        data: MessageData::Aim(AimCommand::Disconnect),
+    };
+    let topic = config.main_topic().subtopic("aim");
+
+    info!(
+        "Set last will message: Topic: {}, Contents: {}",
+        topic,
+        serde_json::to_string_pretty(&message).unwrap()
+    );
+
+    MqttMessage::new(&topic, serde_json::to_vec(&message).unwrap(), 0)
+}
+
+/// Format and time zone of the log timestamps, from the config
+static LOG_TIMESTAMPS: OnceLock<(String, bool)> = OnceLock::new();
+
+/// Format function for printing log entries
+fn logger_format(
+    write: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &LogRecord,
+) -> std::result::Result<(), std::io::Error> {
+    // the config sets them before the logger starts
+    let (format, utc) = LOG_TIMESTAMPS
+        .get()
+        .map_or((DEFAULT_TIMESTAMP_FORMAT, false), |(format, utc)| {
+            (format.as_str(), *utc)
+        });
+    let timestamp = if utc {
+        now.now().with_timezone(&Utc).format(format)
+    } else {
+        now.now().format(format)
+    };
+    // timestamp - caller - level - message
+    write!(
+        write,
+        "{} - {} - {} - {}",
+        timestamp,
+        record.target(),
+        record.level(),
+        record.args()
+    )
+}
+
+fn initialize_logger(config: &Config) -> Result<()> {
+    let _ = LOG_TIMESTAMPS.set((config.logging.timestamp_format.clone(), config.logging.utc));
+    // Set level filter to the config value
+    Logger::with(
+        LogSpecification::default(config.logging.log_level.into_level_filter()).finalize(),
+    )
+    .log_to_file()
+    .format(logger_format) // set format function for log entries
+    .rotate(
+        // rotation logger settings
+        flexi_logger::Criterion::Size(500000), // Maximum size of each log file
+        flexi_logger::Naming::Numbers,
+        flexi_logger::Cleanup::KeepLogFiles(2), // Number of log files to keep
+    )
+    .start()?;
+    Ok(())
+}
+
+pub fn initialize_state(config: &Config) -> Result<State> {
+    let defaults = config.profile_defaults(config.profile.as_deref())?;
+    Ok(State {
+        wavelength: defaults.wavelength,
+        fresnel: defaults.fresnel,
+        pattern_params: defaults.pattern.clone(),
+        profile: config.profile.clone(),
+        tilt_xy: (0.0, 0.0),
+        astigmatic_fresnel: None,
+        attenuation: 0.0,
+        diffraction_order: Default::default(),
+        calibration: CalibrationStore::load(&config.dir_path.calibration_store())?,
+        applied_corrections: Vec::new(),
+        missing_correction_warned: None,
+        temperatures: Default::default(),
+        overdrive_lut: match &config.overdrive {
+            Some(overdrive) => Some(overdrive::load_lut(&overdrive.lut_file)?),
+            None => None,
+        },
+        displayed: None,
+        phase_range: (0.0, 0.0),
+        wrapped_fraction: 0.0,
+        processed_seqs: Default::default(),
+        rate_limiter: RateLimiter::new(config.rate_limits.clone()),
+        // without a status message, only the latest sample is kept
+        metrics: Metrics::new(Duration::from_secs(
+            config
+                .status
+                .as_ref()
+                .map_or(0, |status| status.metrics_window_secs),
+        )),
+        last_published_state: None,
+        handling_request: false,
+        last_event_id: 0,
+        last_event_state: None,
+        available_patterns_changed: false,
+        probe_run: None,
+        fresnel_sweep: None,
+        morph: None,
+        checkpoint: None,
+        resumable: None,
+        scan: None,
+        schedule: Schedule::load()?,
+        registration: registration::load()?,
+        fiducial_run: None,
+        idle: Idle::new(),
+        gray_levels: None,
+        batching: false,
+        controlled_by: None,
+        last_modified_by: None,
+        prestack: None,
+        health_warnings: Default::default(),
+        clock: Default::default(),
+        update: None,
+        maintenance: false,
+        raw: false,
+        blanked: false,
+        captured: None,
+        drop_dir_checked: Instant::now(),
+        laser_simulator: None,
+        wavelength_selected: Instant::now(),
+        shared_frames: None,
+        video_stream: None,
+        capture_session: Local::now().format("%Y%m%d-%H%M%S").to_string(),
+        frames_captured: 0,
+        #[cfg(feature = "hdf5")]
+        session_log: None,
+        #[cfg(feature = "zeromq")]
+        zeromq: None,
+        #[cfg(feature = "grpc")]
+        grpc: None,
+        cache: Default::default(),
+        manifest: Manifest::load(&config.dir_path.base_patterns)?,
+    })
+}
+
+/// Parse config from `config.json`
+pub fn read_config() -> Result<Config> {
+    read_config_file(Path::new("config.json"))
+}
+
+pub fn read_config_file(path: &Path) -> Result<Config> {
+    let mut config: Config = serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|err| SlmError::Config(format!("can't parse {}: {}", path.display(), err)))?;
+    let (pitch_x, pitch_y) = config.slm_geometry.pixel_pitch_um;
+    if !(pitch_x.is_finite() && pitch_y.is_finite() && pitch_x > 0.0 && pitch_y > 0.0) {
+        Err(SlmError::Config(format!(
+            "Invalid pixel pitch ({}, {})",
+            pitch_x, pitch_y
+        )))?
+    }
+    if let Some(scaling) = &mut config.temperature_scaling {
+        if scaling
+            .table
+            .iter()
+            .any(|(temperature, _)| temperature.is_nan())
+        {
+            Err(SlmError::Config(
+                "Temperature scaling table has a NaN temperature".to_owned(),
+            ))?
+        }
+        scaling.table.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+    if StrftimeItems::new(&config.logging.timestamp_format).any(|item| item == Item::Error) {
+        Err(SlmError::Config(format!(
+            "Invalid log timestamp format {}",
+            config.logging.timestamp_format
+        )))?
+    }
+    Ok(config)
+}
+
+fn initialize() -> Result<(Config, Client)> {
+    let config = read_config()?;
+    initialize_logger(&config)?;
+    info!("Parsed config; initialized logger");
+    let build = build_info::build_info();
+    info!(
+        "rasp_pi {} ({}, built {}), protocol version {}",
+        build.version, build.git, build.build_date, build.protocol_version
+    );
+
+    // Create a client instance with the address given in config
+    let client = Client::new(config.mqtt.server_uri())?;
+
+    let connect_options = ConnectOptionsBuilder::new()
+        .clean_session(true)
+        .retry_interval(Duration::from_secs(10))
+        .automatic_reconnect(Duration::from_secs(1), Duration::from_secs(120))
+        .will_message(last_will_message(&config))
+        .finalize();
+
+    info!(
+        "Connecting to the server on {}...",
+        config.mqtt.server_uri()
+    );
+    let response = client.connect(connect_options)?;
+    info!("Connected with result code {}", response.1);
+
+    Ok((config, client))
+}
+
+/// Run the controller; a convenience function to propagate all errors to one place
+pub fn run(args: &[String]) -> Result<()> {
+    let simulate_lasers = match args {
+        [] => None,
+        [option, path] if option == "--simulate-lasers" => Some(PathBuf::from(path)),
+        _ => Err(SlmError::Request(
+            "Usage: rasp_pi [--simulate-lasers <scenario JSON file>] | render ... | send ..."
+                .to_owned(),
+        ))?,
+    };
+
+    let (config, client) = initialize()?;
+
+    // Initialize SDL structures
+    let sdl_context = sdl2::init().map_err(SlmError::Display)?;
+    let video_subsystem = sdl_context.video().map_err(SlmError::Display)?;
+
+    let (width, height) = config.screen.size;
+
+    // Only needed for the video backend, but have to outlive the display
+    let mut canvas;
+    let creator;
+    let mut screen_mode = None;
+
+    let display: Box<dyn Display> = match &config.display {
+        DisplayBackend::Video => {
+            // create window
+            let mut window = video_subsystem.window("pew-pew", width, height);
+            if config.screen.fullscreen {
+                window.fullscreen_desktop().borderless();
+            };
+            let window = window.position_centered().opengl().build()?;
+            let display_mode = video_subsystem
+                .current_display_mode(window.display_index().map_err(SlmError::Display)?)
+                .map_err(SlmError::Display)?;
+            screen_mode = Some(ScreenMode {
+                size: window.size(),
+                refresh_hz: display_mode.refresh_rate,
+            });
+
+            // create handles for drawing to the window
+            canvas = window.into_canvas().build()?;
+            creator = canvas.texture_creator();
+
+            Box::new(VideoDisplay::new(&mut canvas, &creator, (width, height))?)
+        }
+        DisplayBackend::Serial(serial_config) => {
+            info!("Sending patterns to serial SLM on {}", serial_config.port);
+            Box::new(SerialDisplay::open(serial_config)?)
+        }
+    };
+
+    let screen_context = ScreenContext {
+        display,
+        sdl_context: &sdl_context,
+        mode: screen_mode,
+    };
+
+    let state = initialize_state(&config)?;
+
+    let mut context = Context::new(config, client, screen_context, state);
+    if let Some(path) = simulate_lasers {
+        context.state.laser_simulator = Some(LaserSimulator::load(&path)?);
+    }
+
+    // Update state from the defaults
+    context.update_state(None, None, None)?;
+
+    // Start dispatching messages
+    context.message_loop()?;
+
+    Ok(())
+}
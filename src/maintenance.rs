@@ -0,0 +1,47 @@
+//! Maintenance mode, so service engineers can show test patterns without
+//! the lasers and the acquisition changing them
+
+use log::info;
+
+use crate::{
+    schema::{AimCommand, EmbeddedCommand, LaserCommand, Message, MessageData, MessageType},
+    Context, Result,
+};
+
+impl<'a> Context<'a> {
+    /// Whether the message is only answered, because it would change the
+    /// pattern during maintenance; clients holding control and override
+    /// subtopics can still set patterns
+    pub fn suppressed_by_maintenance(&self, message: &Message, topic: &str) -> bool {
+        if !self.state.maintenance {
+            return false;
+        }
+        match (&message.m_type, &message.data) {
+            (MessageType::Status, MessageData::Embedded(EmbeddedCommand::InitDone)) => true,
+            (_, MessageData::Lasers(LaserCommand::Set { .. })) => true,
+            (MessageType::Device, MessageData::Aim(AimCommand::Set(_)))
+            | (MessageType::Device, MessageData::Aim(AimCommand::PreStack(_))) => {
+                let holds_control =
+                    message.client.is_some() && message.client == self.state.controlled_by;
+                !holds_control && !self.config.overrides_control(topic)
+            }
+            _ => false,
+        }
+    }
+
+    /// Leaving maintenance asks for the laser state again, so the pattern
+    /// follows the lasers that were switched in the meantime
+    pub fn set_maintenance_mode(&mut self, on: bool) -> Result<&mut Self> {
+        if on == self.state.maintenance {
+            return Ok(self);
+        }
+        self.state.maintenance = on;
+        if on {
+            info!("Entering maintenance mode");
+            Ok(self)
+        } else {
+            info!("Leaving maintenance mode");
+            self.send_get_lasers()
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Manifest of the base patterns, naming the pattern and properties of every
+//! file explicitly instead of parsing them from legacy `name_property_value`
+//! file names, and the `migrate-patterns` subcommand generating it; also the
+//! metadata of pattern files, in the manifest or in a sidecar file
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{pattern_names, read_config, Result, SlmError};
+
+/// Kept in the base patterns directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Sidecars are named after their pattern file with this appended,
+/// e.g. `hologram.png.json`
+pub const METADATA_SUFFIX: &str = ".json";
+
+const USAGE: &str = "Usage: rasp_pi migrate-patterns [--apply]";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Manifest {
+    pub patterns: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    /// Relative to the base patterns directory
+    pub file: String,
+    pub name: String,
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+    /// Takes precedence over a sidecar
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<PatternMetadata>,
+}
+
+/// How the phase of a pattern file goes into the displayed pattern, e.g. for
+/// uploaded holograms that already include a tilt and shouldn't get the blaze
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PatternMetadata {
+    /// The phase of the file is multiplied by this,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// then this is added, in radians
+    #[serde(default)]
+    pub offset: f32,
+    #[serde(default = "default_true")]
+    pub blaze: bool,
+    #[serde(default = "default_true")]
+    pub fresnel: bool,
+    /// The flatness correction
+    #[serde(default = "default_true")]
+    pub corrections: bool,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for PatternMetadata {
+    fn default() -> Self {
+        Self {
+            scale: default_scale(),
+            offset: 0.0,
+            blaze: true,
+            fresnel: true,
+            corrections: true,
+        }
+    }
+}
+
+pub fn metadata_path(pattern: &Path) -> PathBuf {
+    let mut path = pattern.as_os_str().to_owned();
+    path.push(METADATA_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Sidecars and the manifest aren't patterns
+pub fn is_metadata_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+/// The metadata in the sidecar of `pattern`, the defaults if it has none
+pub fn load_metadata(pattern: &Path) -> Result<PatternMetadata> {
+    let path = metadata_path(pattern);
+    if !path.is_file() {
+        return Ok(Default::default());
+    }
+    serde_json::from_reader(BufReader::new(File::open(&path)?))
+        .map_err(|err| SlmError::Config(format!("can't parse {}: {}", path.display(), err)))
+}
+
+impl Manifest {
+    /// The manifest of a base patterns directory, if it has one
+    pub fn load(base_patterns: &Path) -> Result<Option<Self>> {
+        let path = base_patterns.join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let manifest = serde_json::from_reader(BufReader::new(File::open(&path)?))
+            .map_err(|err| SlmError::Config(format!("can't parse {}: {}", path.display(), err)))?;
+        Ok(Some(manifest))
+    }
+
+    pub fn save(&self, base_patterns: &Path) -> Result<()> {
+        let file = File::create(base_patterns.join(MANIFEST_FILE))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// The file of a pattern with exactly these properties, in any order
+    pub fn find(&self, name: &str, properties: &HashMap<String, String>) -> Option<&ManifestEntry> {
+        self.patterns.iter().find(|entry| {
+            entry.name == name
+                && entry.properties.len() == properties.len()
+                && properties
+                    .iter()
+                    .all(|(property, value)| entry.properties.get(property) == Some(value))
+        })
+    }
+}
+
+/// Pattern name and properties of a legacy file stem, `None` if the
+/// properties and values don't pair up
+fn parse_legacy_stem(stem: &str) -> Option<(String, BTreeMap<String, String>)> {
+    let (name, properties) = pattern_names::parse_file_stem(stem)?;
+    Some((name, properties.into_iter().collect()))
+}
+
+/// Scan the base patterns, print what would change, and with `--apply`
+/// rename the inconsistently named files and write the manifest
+pub fn migrate_patterns(args: &[String]) -> Result<()> {
+    let apply = match args {
+        [] => false,
+        [flag] if flag == "--apply" => true,
+        _ => Err(SlmError::Request(USAGE.to_owned()))?,
+    };
+    let config = read_config()?;
+    let base_patterns = &config.dir_path.base_patterns;
+    let extensions: Vec<_> = config
+        .image_file_extensions
+        .iter()
+        .map(|ext| ext.trim_start_matches('.'))
+        .collect();
+
+    let mut manifest = Manifest::default();
+    let mut renames = Vec::new();
+    for entry in WalkDir::new(base_patterns)
+        .min_depth(1)
+        .max_depth(1)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+    {
+        let entry = entry.map_err(|err| SlmError::Io(err.into()))?;
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        if !entry.file_type().is_file() || is_metadata_file(path) {
+            continue;
+        }
+        let (stem, extension) = match (path.file_stem().and_then(|stem| stem.to_str()), extension) {
+            (Some(stem), Some(extension)) if extensions.contains(&extension) => (stem, extension),
+            _ => {
+                println!("{}: skipped, not a pattern image", file_name);
+                continue;
+            }
+        };
+        let (name, properties) = match parse_legacy_stem(stem) {
+            Some(parsed) => parsed,
+            None => {
+                println!(
+                    "{}: skipped, its properties and values don't pair up",
+                    file_name
+                );
+                continue;
+            }
+        };
+
+        // properties in alphabetical order
+        let canonical = format!(
+            "{}.{}",
+            pattern_names::file_stem(&name, &properties),
+            extension
+        );
+        let taken = base_patterns.join(&canonical).exists()
+            || renames.iter().any(|(_, to)| *to == canonical);
+        let file = if canonical != file_name && !taken {
+            println!("{}: rename to {}", file_name, canonical);
+            renames.push((file_name, canonical.clone()));
+            canonical
+        } else {
+            if canonical != file_name {
+                println!("{}: not renamed, {} is taken", file_name, canonical);
+            }
+            file_name
+        };
+        println!("  {} {:?}", name, properties);
+        // sidecars aren't renamed along with their pattern
+        let metadata = if metadata_path(path).is_file() {
+            Some(load_metadata(path)?)
+        } else {
+            None
+        };
+        manifest.patterns.push(ManifestEntry {
+            file,
+            name,
+            properties,
+            metadata,
+        });
+    }
+
+    if !apply {
+        println!(
+            "Dry run: {} patterns, {} renames; pass --apply to carry them out",
+            manifest.patterns.len(),
+            renames.len()
+        );
+        return Ok(());
+    }
+    for (from, to) in &renames {
+        fs::rename(base_patterns.join(from), base_patterns.join(to))?;
+    }
+    manifest.save(base_patterns)?;
+    println!(
+        "Renamed {} files and wrote {} with {} patterns; restart the controller to use it",
+        renames.len(),
+        base_patterns.join(MANIFEST_FILE).display(),
+        manifest.patterns.len()
+    );
+    Ok(())
+}
@@ -1,103 +1,205 @@
+use std::cmp::Ordering;
+use std::collections::{HashSet, VecDeque};
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::string::ToString;
+use std::time::{Duration, Instant};
 
-use log::{error, info};
+use base64::DecodeError;
+use log::{error, info, warn};
 use mqtt::{Client, Message as MqttMessage};
-use sdl2::{event::Event, keyboard::Keycode};
+use sdl2::{
+    event::{Event, WindowEvent},
+    keyboard::Keycode,
+};
 use walkdir::WalkDir;
 
-type Dim = ndarray::Dim<[usize; 2]>;
-
 use crate::{
+    build_info::build_info,
+    calibration::CalibrationStore,
+    compression, coordinates,
+    data_uri::{parse_image_data, parse_phase_samples},
+    encoding::{self, BINARY_ENCODINGS},
+    manifest::{is_metadata_file, load_metadata, metadata_path, PatternMetadata},
+    overdrive::overdrive_frame,
+    pattern_names, patterns,
+    phase::{quantize, Phase},
+    precondition::pattern_hash,
+    rate_limit::Admission,
+    read_config,
+    scan::trajectory_points,
     schema::{
-        APattern, AimCommand, AvailablePatterns, CorrectionPatternDeltas, EmbeddedCommand,
-        LaserCommand, Message, MessageData, MessageType, PatternParams,
+        APattern, AimCommand, AimState, AstigmaticFresnel, AvailablePatterns, BinaryData,
+        Capabilities, CorrectionDeltaResult, CorrectionPatternDeltas, DiffractionOrder,
+        EmbeddedCommand, Encoding, LaserCommand, Message, MessageData, MessageType,
+        MissingCorrectionPolicy, MqttConfig, PatternPage, PatternParams, PatternStats,
+        PhaseSamples, Precision, ResponseCode, StateReport, WarningCode, YAxis,
+    },
+    sensors::{interpolate, SensorPoller},
+    stage_dumps::StageDumps,
+    storage::{
+        self, check_not_factory, is_factory_file, is_npy, read_image_from_file, read_npy_phase,
+        save_image, save_npy, COMPRESSED_NPY_EXTENSION, NPY_EXTENSION,
     },
     util::Subtopic,
-    Array, Context, Result, State,
+    Array, Array64, Context, Dim, Result, SlmError, State, TWO_PI,
 };
 
-const TWO_PI: f32 = std::f32::consts::PI * 2.0;
-
-fn base64_to_ndarray(s: &str, dim: Dim) -> Result<Array> {
+/// Sequence numbers remembered per sender, so redelivered ones are recognized
+const RECENT_SEQS: usize = 64;
+
+/// Little-endian `f32`s of the shape `dim`; errors tell where base64 data is
+/// corrupted, since the payloads are too large to inspect by hand
+fn binary_to_ndarray(data: &BinaryData, dim: Dim, what: &str) -> Result<Array> {
+    let decoded;
+    let bytes = match data {
+        BinaryData::Bytes(bytes) => bytes,
+        BinaryData::Base64(s) => {
+            decoded = base64::decode(s).map_err(|err| {
+                let offset = match err {
+                    DecodeError::InvalidByte(offset, _)
+                    | DecodeError::InvalidLastSymbol(offset, _) => Some(offset),
+                    DecodeError::InvalidLength => None,
+                };
+                let context = match offset {
+                    Some(offset) => format!(
+                        " near {:?} at character {}",
+                        String::from_utf8_lossy(
+                            &s.as_bytes()[offset.saturating_sub(8)..(offset + 8).min(s.len())]
+                        ),
+                        offset
+                    ),
+                    None => String::new(),
+                };
+                SlmError::Request(format!(
+                    "invalid base64 in {} ({} characters): {}{}",
+                    what,
+                    s.len(),
+                    err,
+                    context
+                ))
+            })?;
+            &decoded
+        }
+    };
+
+    let (size_x, size_y) = (dim[0], dim[1]);
+    let expected = size_x * size_y;
+    if bytes.len() != expected * 4 {
+        Err(SlmError::Request(format!(
+            "{} of shape {}x{} needs {} floats ({} bytes), but decodes to {} bytes{}",
+            what,
+            size_x,
+            size_y,
+            expected,
+            expected * 4,
+            bytes.len(),
+            match bytes.len() % 4 {
+                0 => format!(" ({} floats)", bytes.len() / 4),
+                _ => ", not a whole number of floats".to_owned(),
+            }
+        )))?
+    }
     Ok(ndarray::Array2::from_shape_vec(
         dim,
-        base64::decode(s)?
+        bytes
             .chunks_exact(4)
             .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
             .collect(),
     )?)
 }
 
-fn read_image_from_file(path: &Path, dim: Option<Dim>) -> Result<Array> {
-    let factor = TWO_PI / 256.0;
-    // a 2d array with !u8! elements
-    let array = ndarray_image::open_gray_image(path)?;
-
-    let normalize = |e: &u8| *e as f32 * factor;
-
-    if let Some(dim) = dim {
-        Ok(Array::from_shape_fn(dim, |id| {
-            array.get(id).map(normalize).unwrap_or(0.0)
-        }))
+/// The path with its file name escaped, unless only a legacy file with the
+/// name as it is exists
+fn escaped_file_name(path: PathBuf) -> PathBuf {
+    let escaped = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => path.with_file_name(pattern_names::escape(name)),
+        None => return path,
+    };
+    if !escaped.exists() && path.exists() {
+        path
     } else {
-        Ok(array.map(normalize))
+        escaped
     }
 }
 
-fn save_image(path: &Path, array: &Array) -> Result<()> {
-    Ok(ndarray_image::save_gray_image(
-        path,
-        array
-            .mapv(|e| (e.rem_euclid(TWO_PI) * 255.0 / TWO_PI) as u8)
-            .view(),
-    )?)
+/// Numbers in numeric order before other values in alphabetical order
+fn compare_property_values(a: &str, b: &str) -> Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(x), Ok(y)) => x.total_cmp(&y).then_with(|| a.cmp(b)),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
 }
 
-fn save_image_data(mut path: PathBuf, b64_data: String) -> Result<()> {
-    let mut parts = b64_data.split(";base64,");
-    let header = parts
-        .next()
-        .ok_or_else(|| format!("image data {} doesn't have a header", b64_data))?;
-    let body = parts
-        .next()
-        .ok_or_else(|| format!("image data {} doesn't have a body", b64_data))?;
-
-    let extension = header
-        .split('/')
-        .nth(1)
-        .ok_or_else(|| format!("image header {} doesn't contain an extenstion", header))?;
-
-    path.set_extension(extension);
+/// Save an uploaded image, returning the path it's saved to
+fn save_image_data(path: PathBuf, b64_data: &str, extension: Option<&str>) -> Result<PathBuf> {
+    let image = parse_image_data(b64_data, extension)?;
+    let path = pattern_names::with_extension(&path, &image.extension);
 
+    check_not_factory(&path)?;
     info!("Saving image to {:?}", path);
-    File::create(path)?.write_all(&base64::decode(body)?)?;
+    File::create(&path)?.write_all(&image.bytes)?;
 
-    Ok(())
+    Ok(path)
 }
 
-fn send_message(client: &mut Client, topic: &str, message: &Message) -> Result<()> {
+pub fn send_message(
+    client: &mut Client,
+    topic: &str,
+    message: &Message,
+    mqtt: &MqttConfig,
+) -> Result<()> {
     info!(
         "Sent message: Topic: {}, Contents:\n{}",
         topic,
         serde_json::to_string_pretty(&message).unwrap(),
     );
 
-    client.publish(MqttMessage::new(topic, serde_json::to_vec(message)?, 0))?;
+    let compression = mqtt.compression.as_ref();
+    let payload = compression::compress(serde_json::to_vec(message)?, compression)?;
+    client.publish(MqttMessage::new(topic, payload, 0))?;
+    for encoding in &mqtt.publish_encodings {
+        if *encoding == Encoding::Json {
+            continue;
+        }
+        let payload = compression::compress(encoding.encode(message)?, compression)?;
+        client.publish(MqttMessage::new(
+            topic.subtopic(encoding.suffix()),
+            payload,
+            0,
+        ))?;
+    }
 
     Ok(())
 }
 
-impl<'a, 'b> Context<'a, 'b> {
-    fn send_aim_message(&mut self, message: &Message) -> Result<&mut Self> {
-        send_message(&mut self.client, &self.main_topic_aim, message)?;
+impl<'a> Context<'a> {
+    pub fn send_aim_message(&mut self, message: &Message) -> Result<&mut Self> {
+        if let Some(captured) = &mut self.state.captured {
+            captured.push(message.clone());
+        }
+        if let Err(err) = self.publish_zeromq(message) {
+            error!("Error {} while publishing on ZeroMQ; continuing", err);
+        }
+        self.publish_grpc(message);
+        if self.offline {
+            info!("Not sending message: {}", serde_json::to_string(message)?);
+            return Ok(self);
+        }
+        send_message(
+            &mut self.client,
+            &self.main_topic_aim,
+            message,
+            &self.config.mqtt,
+        )?;
         Ok(self)
     }
 
-    fn available_patterns(&self) -> AvailablePatterns {
+    pub fn available_patterns(&self) -> AvailablePatterns {
         let mut path = self.config.dir_path.base_patterns.clone();
         let mut patterns = AvailablePatterns::default();
 
@@ -116,35 +218,39 @@ impl<'a, 'b> Context<'a, 'b> {
                 .push(value.to_owned());
         };
 
-        for entry in WalkDir::new(&path).min_depth(1).max_depth(1) {
-            // A wrapper for '?' operations
-            let process_entry = || -> Option<()> {
-                let entry = entry.ok()?;
-
-                if !entry.file_type().is_file() {
-                    return None;
+        match &self.state.manifest {
+            Some(manifest) => {
+                for entry in &manifest.patterns {
+                    let map_entry = patterns.patterns.entry(entry.name.clone()).or_default();
+                    for (property, value) in &entry.properties {
+                        process_value(map_entry, property.clone(), value.clone());
+                    }
                 }
+            }
+            None => {
+                for entry in WalkDir::new(&path).min_depth(1).max_depth(1) {
+                    // A wrapper for '?' operations
+                    let process_entry = || -> Option<()> {
+                        let entry = entry.ok()?;
 
-                let file_name = entry.path().file_stem()?.to_str()?;
-                let mut parts_iter = file_name.split('_');
-                let name = parts_iter.next()?;
+                        if !entry.file_type().is_file() || is_metadata_file(entry.path()) {
+                            return None;
+                        }
 
-                let map_entry = patterns.patterns.entry(name.to_owned()).or_default();
+                        let file_stem = entry.path().file_stem()?.to_str()?;
+                        let (name, properties) = pattern_names::parse_file_stem(file_stem)?;
 
-                loop {
-                    let property = match parts_iter.next() {
-                        Some(property) => property.to_owned(),
-                        None => break,
+                        let map_entry = patterns.patterns.entry(name).or_default();
+                        for (property, value) in properties {
+                            process_value(map_entry, property, value);
+                        }
+
+                        Some(())
                     };
-                    let value = parts_iter.next()?.to_owned();
 
-                    process_value(map_entry, property, value);
+                    process_entry();
                 }
-
-                Some(())
-            };
-
-            process_entry();
+            }
         }
 
         path.push("custom_patterns");
@@ -154,11 +260,11 @@ impl<'a, 'b> Context<'a, 'b> {
             let process_entry = || -> Option<()> {
                 let entry = entry.ok()?;
 
-                if !entry.file_type().is_file() {
+                if !entry.file_type().is_file() || is_metadata_file(entry.path()) {
                     return None;
                 }
 
-                let file_name = entry.file_name().to_str()?.to_owned();
+                let file_name = pattern_names::unescape(entry.file_name().to_str()?);
 
                 let map_entry = patterns.patterns.entry("custom".into()).or_default();
                 process_value(map_entry, "filename".into(), file_name);
@@ -168,6 +274,14 @@ impl<'a, 'b> Context<'a, 'b> {
 
             process_entry();
         }
+        // one entry per value instead of per file, in the order of a dropdown
+        for pattern in patterns.patterns.values_mut() {
+            for prop in pattern.property_values.values_mut() {
+                prop.values.sort_by(|a, b| compare_property_values(a, b));
+                prop.values.dedup();
+                prop.infer_kind();
+            }
+        }
         patterns.pattern_names = patterns.patterns.keys().cloned().collect();
         patterns.pattern_names.sort();
 
@@ -175,33 +289,204 @@ impl<'a, 'b> Context<'a, 'b> {
     }
 
     fn send_available_patterns(&mut self) -> Result<&mut Self> {
+        self.state.available_patterns_changed = false;
         self.send_aim_message(&Message {
             m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
             data: MessageData::Aim(AimCommand::AvailablePatterns {
                 patterns: self.available_patterns(),
+                page: None,
             }),
         })
     }
 
-    fn send_current_state(&mut self) -> Result<&mut Self> {
-        //FIXME
-        Ok(self)
+    /// The patterns whose name starts with `prefix`, with the custom patterns
+    /// from `offset` on; for libraries too large for a single message
+    fn send_pattern_page(
+        &mut self,
+        prefix: &str,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<&mut Self> {
+        let mut patterns = self.available_patterns();
+        patterns
+            .patterns
+            .retain(|name, _| name == "custom" || name.starts_with(prefix));
+
+        let mut total = 0;
+        if let Some(files) = patterns
+            .patterns
+            .get_mut("custom")
+            .and_then(|custom| custom.property_values.get_mut("filename"))
+        {
+            files.values.retain(|file| file.starts_with(prefix));
+            total = files.values.len();
+            files.values = files
+                .values
+                .drain(..)
+                .skip(offset)
+                .take(limit.unwrap_or(usize::MAX))
+                .collect();
+        }
+        patterns.pattern_names = patterns.patterns.keys().cloned().collect();
+        patterns.pattern_names.sort();
+
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::AvailablePatterns {
+                patterns,
+                page: Some(PatternPage { offset, total }),
+            }),
+        })
     }
 
-    fn send_prestack_done(&mut self) -> Result<&mut Self> {
+    fn send_available_wavelengths(&mut self) -> Result<&mut Self> {
         self.send_aim_message(&Message {
             m_type: MessageType::Device,
-            data: MessageData::Aim(AimCommand::Response {
-                reply: "PreStack done".to_string(),
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::AvailableWavelengths {
+                wavelengths: self.available_wavelengths(),
             }),
+        })
+    }
+
+    pub fn state_report(&self) -> Result<StateReport> {
+        Ok(StateReport {
+            pattern: self.state.pattern_params.clone(),
+            fresnel: self.state.fresnel,
+            wavelength: self.state.wavelength,
+            tilt_xy: self.state.tilt_xy,
+            attenuation: self.state.attenuation,
+            defect_masks: self.state.calibration.defect_masks.clone(),
+            applied_corrections: self.state.applied_corrections.clone(),
+            profile: self.state.profile.clone(),
+            astigmatic_fresnel: self.state.astigmatic_fresnel.clone(),
+            pattern_hash: pattern_hash(&self.state.pattern_params)?,
+            controlled_by: self.state.controlled_by.clone(),
+            last_modified_by: self.state.last_modified_by.clone(),
+            maintenance: self.state.maintenance,
+            raw: self.state.raw,
+            blanked: self.state.blanked,
+            diffraction_order: self.state.diffraction_order,
+        })
+    }
+
+    /// Publish the state; unsolicited publishes are skipped if it's identical
+    /// to the one published last, requests are always answered
+    pub fn send_current_state(&mut self) -> Result<&mut Self> {
+        if self.state.batching {
+            return Ok(self);
+        }
+        self.state_changed();
+        let report = self.state_report()?;
+        let encoded = serde_json::to_string(&report)?;
+        if !self.state.handling_request
+            && self.state.last_published_state.as_ref() == Some(&encoded)
+        {
+            return Ok(self);
+        }
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::State(Box::new(report))),
         })?;
+        self.state.last_published_state = Some(encoded);
         Ok(self)
     }
 
-    fn send_get_lasers(&mut self) -> Result<&mut Self> {
+    /// Publish the state even if it didn't change, for clients asking for it
+    fn resend_current_state(&mut self) -> Result<&mut Self> {
+        self.state.last_published_state = None;
+        self.send_current_state()
+    }
+
+    /// Announce the available patterns if they changed; called once a burst
+    /// of messages is processed, so e.g. several uploads are announced once
+    pub fn flush_available_patterns(&mut self) -> Result<&mut Self> {
+        if self.state.available_patterns_changed {
+            self.state.available_patterns_changed = false;
+            self.send_available_patterns()?;
+        }
+        Ok(self)
+    }
+
+    pub fn send_warning(&mut self, warning: String) -> Result<&mut Self> {
+        self.send_coded_warning(None, warning)
+    }
+
+    fn send_coded_warning(
+        &mut self,
+        code: Option<WarningCode>,
+        warning: String,
+    ) -> Result<&mut Self> {
+        warn!("{}", warning);
+        self.send_aim_message(&Message {
+            m_type: MessageType::Log,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Warning {
+                code,
+                message: warning,
+            }),
+        })
+    }
+
+    /// Tell clients why their request failed
+    pub fn send_error(&mut self, err: &SlmError, seq: Option<u64>) -> Result<&mut Self> {
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Error {
+                code: err.code(),
+                category: err.category().to_owned(),
+                message: err.to_string(),
+                seq,
+            }),
+        })
+    }
+
+    fn send_ack(&mut self, seq: u64, duplicate: bool) -> Result<&mut Self> {
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Ack { seq, duplicate }),
+        })
+    }
+
+    pub fn send_response(&mut self, code: ResponseCode) -> Result<&mut Self> {
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Response {
+                code,
+                reply: Some(code.text().to_owned()),
+            }),
+        })
+    }
+
+    pub fn send_get_lasers(&mut self) -> Result<&mut Self> {
         // Possible improvement: cache this?
         self.send_aim_message(&Message {
             m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
             data: MessageData::Lasers(LaserCommand::Get),
         })
     }
@@ -209,6 +494,9 @@ impl<'a, 'b> Context<'a, 'b> {
     fn send_set_correction_pattern_deltas(&mut self, wavelength: u32) -> Result<&mut Self> {
         self.send_aim_message(&Message {
             m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
             data: MessageData::Aim(AimCommand::SetCorrectionPatternDeltasResponse {
                 wavelength,
                 success: true,
@@ -227,110 +515,413 @@ impl<'a, 'b> Context<'a, 'b> {
         Ok(&self.state.cache[path])
     }
 
-    fn put_pattern(&mut self, pattern: &ndarray::Array2<u8>) -> Result<()> {
-        let size = self.config.screen.size;
-        let pixels = &mut self.screen_context.pixels;
+    pub fn put_pattern(&mut self, pattern: &ndarray::Array2<u8>) -> Result<()> {
+        self.wake_from_idle()?;
+        if let (Some(overdrive), Some(lut), Some(previous)) = (
+            &self.config.overdrive,
+            &self.state.overdrive_lut,
+            &self.state.displayed,
+        ) {
+            if previous.dim() == pattern.dim() {
+                let frame = overdrive_frame(lut, previous, pattern);
+                for _ in 0..overdrive.frames {
+                    self.screen_context.display.show(&frame)?;
+                    std::thread::sleep(Duration::from_millis(overdrive.frame_ms));
+                }
+            }
+        }
 
-        for (id, value) in pattern.indexed_iter() {
-            let idd = (id.1 * size.0 as usize + id.0) * 4;
-            pixels[idd + 0] = *value;
-            pixels[idd + 1] = *value;
-            pixels[idd + 2] = *value;
+        self.screen_context.display.show(pattern)?;
+        self.export_shared_memory(pattern)?;
+        if let Err(err) = self.log_session_frame(pattern) {
+            error!("Error {} while logging the pattern; continuing", err);
         }
-        let pitch = sdl2::pixels::PixelFormatEnum::ARGB8888.byte_size_of_pixels(size.0 as usize);
-        self.screen_context.texture.update(None, &pixels, pitch)?;
-        self.screen_context
-            .canvas
-            .copy(self.screen_context.texture, None, None)?;
-        self.screen_context.canvas.present();
+        self.state.displayed = Some(pattern.clone());
         Ok(())
     }
 
-    fn get_file_path_for_flatness_corr_pattern(&self, wavelength: u32) -> Result<PathBuf> {
+    /// Every file the flatness correction for `wavelength` may be stored in,
+    /// in the order they are looked up
+    fn flatness_corr_pattern_paths(&self, wavelength: u32) -> Vec<PathBuf> {
         let filename = "flatness_wavelength_".to_owned() + &wavelength.to_string();
 
+        let extensions = [COMPRESSED_NPY_EXTENSION, NPY_EXTENSION]
+            .iter()
+            .copied()
+            .chain(self.config.image_file_extensions.iter().map(String::as_str));
+
+        let mut paths = Vec::new();
         for factory in &["", "_factory"] {
-            for ext in &self.config.image_file_extensions {
-                let path = self
-                    .config
-                    .dir_path
-                    .flatness_corr_patterns
-                    .clone()
-                    .join(filename.clone() + factory + ext);
-                if path.is_file() {
-                    return Ok(path);
-                }
+            for ext in extensions.clone() {
+                paths.push(
+                    self.config
+                        .dir_path
+                        .flatness_corr_patterns
+                        .join(filename.clone() + factory + ext),
+                );
             }
         }
-        Err(format!(
+        paths
+    }
+
+    pub fn get_file_path_for_flatness_corr_pattern(&self, wavelength: u32) -> Result<PathBuf> {
+        if let Some(path) = self
+            .flatness_corr_pattern_paths(wavelength)
+            .into_iter()
+            .find(|path| path.is_file())
+        {
+            return Ok(path);
+        }
+        Err(SlmError::Calibration(format!(
             "No flatness correction pattern for wavelength {}",
             wavelength
-        ))?
+        )))?
+    }
+
+    /// Wavelengths that have a calibration scale factor and,
+    /// if flatness correction is required, a flatness correction pattern
+    pub fn available_wavelengths(&self) -> Vec<u32> {
+        let compute_config = &self.config.compute_pattern;
+        let flatness_required = compute_config.add_flatness_correction
+            && compute_config.missing_correction == MissingCorrectionPolicy::Fail;
+
+        let mut wavelengths: Vec<u32> = self
+            .config
+            .compute_pattern
+            .slm_calib_scaling
+            .known_wavelengths
+            .iter()
+            .copied()
+            .filter(|&wavelength| {
+                !flatness_required
+                    || self
+                        .get_file_path_for_flatness_corr_pattern(wavelength)
+                        .is_ok()
+            })
+            .collect();
+        wavelengths.sort();
+        wavelengths.dedup();
+        wavelengths
+    }
+
+    /// Metadata of a pattern file from the manifest or its sidecar;
+    /// the defaults for computed patterns
+    fn pattern_metadata(&self, pattern: &PatternParams) -> Result<PatternMetadata> {
+        if let (PatternParams::Base { base }, Some(manifest)) = (pattern, &self.state.manifest) {
+            let entry = manifest.find(&base.filename, &base.properties);
+            if let Some(metadata) = entry.and_then(|entry| entry.metadata.clone()) {
+                return Ok(metadata);
+            }
+        }
+        match pattern {
+            PatternParams::Base { .. } | PatternParams::Custom { .. } => {
+                load_metadata(&self.get_file_path_for_base_corr_pattern(pattern)?)
+            }
+            _ => Ok(Default::default()),
+        }
     }
 
     fn get_file_path_for_base_corr_pattern(&self, pattern: &PatternParams) -> Result<PathBuf> {
         match pattern {
-            PatternParams::Spot { .. } => Err("Cannot get file path for the spot pattern")?,
+            PatternParams::Spot { .. }
+            | PatternParams::Annulus { .. }
+            | PatternParams::KnifeEdge { .. }
+            | PatternParams::Complex { .. } => Err(SlmError::Pattern(
+                "Cannot get file path for a computed pattern".to_owned(),
+            ))?,
             PatternParams::Custom { custom } => {
-                Ok(self.config.dir_path.base_patterns.join(&custom.filename))
+                let path = self.config.dir_path.base_patterns.join(&custom.filename);
+                Ok(escaped_file_name(path))
             }
             PatternParams::Base { base } => {
-                let mut filename = base.filename.clone();
-
-                for (property, value) in &base.properties {
-                    filename = filename + "_" + property + "_" + value;
+                if let Some(manifest) = &self.state.manifest {
+                    return match manifest.find(&base.filename, &base.properties) {
+                        Some(entry) => Ok(self.config.dir_path.base_patterns.join(&entry.file)),
+                        None => Err(SlmError::Pattern(format!(
+                            "Base pattern {:?} is not in the manifest",
+                            pattern
+                        )))?,
+                    };
                 }
-                let mut path = self.config.dir_path.base_patterns.join(filename);
-                for ext in &self.config.image_file_extensions {
-                    path.set_extension(ext);
-                    if path.is_file() {
-                        return Ok(path);
+                // files named before escaping have the parts as they are
+                let legacy = base
+                    .properties
+                    .iter()
+                    .fold(base.filename.clone(), |filename, (property, value)| {
+                        filename + "_" + property + "_" + value
+                    });
+                let filenames = [
+                    pattern_names::file_stem(&base.filename, &base.properties),
+                    legacy,
+                ];
+                for filename in filenames.iter() {
+                    let stem = self.config.dir_path.base_patterns.join(filename);
+                    for ext in &self.config.image_file_extensions {
+                        let path = pattern_names::with_extension(&stem, ext);
+                        if path.is_file() {
+                            return Ok(path);
+                        }
                     }
                 }
 
-                Err(format!("Can't find file for base pattern {:?}", pattern))?
+                Err(SlmError::Pattern(format!(
+                    "Can't find file for base pattern {:?}",
+                    pattern
+                )))?
             }
         }
     }
 
-    fn add_correction_pattern_deltas(
+    /// Apply the deltas to the current flatness correction, returning the path
+    /// the corrected pattern should be saved to along with the pattern itself.
+    /// The sum is always done in f64, so precision is only lost when storing.
+    fn corrected_flatness_pattern(
         &mut self,
         pattern_deltas: &CorrectionPatternDeltas,
-    ) -> Result<&mut Self> {
+    ) -> Result<(PathBuf, Array64)> {
         let mut fp = self.get_file_path_for_flatness_corr_pattern(pattern_deltas.wavelength)?;
-        let old_pattern = self.load_data(&fp, None)?;
-        let delta = base64_to_ndarray(
+        let old_pattern = if is_npy(&fp) {
+            read_npy_phase(&fp)?
+        } else {
+            self.load_data(&fp, None)?.mapv(f64::from)
+        };
+        let delta = binary_to_ndarray(
             &pattern_deltas.imagedata,
             ndarray::Dim(pattern_deltas.shape_xy),
+            &format!(
+                "correction deltas for wavelength {}",
+                pattern_deltas.wavelength
+            ),
         )?;
-        let new_pattern = old_pattern + &delta;
-
-        let filename = fp
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .replace("_factory", "");
+        // adding would broadcast, and panic on a shape the correction can't take
+        if delta.dim() != old_pattern.dim() {
+            Err(SlmError::Request(format!(
+                "Deltas for wavelength {} are {:?} pixels, the correction {:?}",
+                pattern_deltas.wavelength,
+                delta.dim(),
+                old_pattern.dim()
+            )))?
+        }
+        let new_pattern = old_pattern + &delta.mapv(f64::from);
+
+        let compute_config = &self.config.compute_pattern;
+        let extension = if compute_config.compress_corrections {
+            Some(COMPRESSED_NPY_EXTENSION)
+        } else if compute_config.f64_corrections {
+            Some(NPY_EXTENSION)
+        } else {
+            None
+        };
+
+        let filename = match extension {
+            Some(extension) => format!(
+                "flatness_wavelength_{}{}",
+                pattern_deltas.wavelength, extension
+            ),
+            None => fp
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .replace("_factory", ""),
+        };
         fp.set_file_name(filename);
-        save_image(&fp, &new_pattern)?;
 
-        self.state.cache.insert(fp, new_pattern);
+        Ok((fp, new_pattern))
+    }
 
+    fn store_flatness_pattern(
+        &mut self,
+        wavelength: u32,
+        path: PathBuf,
+        pattern: Array64,
+    ) -> Result<&mut Self> {
+        // Conversion to f32 happens only here, when the pattern enters the computation cache
+        let pattern_f32 = pattern.mapv(|e| e as f32);
+
+        if !is_npy(&path) {
+            save_image(&path, &pattern_f32)?;
+        } else if self.config.compute_pattern.f64_corrections {
+            save_npy(&path, &pattern)?;
+        } else {
+            save_npy(&path, &pattern_f32)?;
+        }
+        // the saved pattern includes them, and the lookup could prefer a stale one
+        for other in self.flatness_corr_pattern_paths(wavelength) {
+            if other != path && other.is_file() && !is_factory_file(&other) {
+                info!("Removing superseded flatness correction {:?}", other);
+                storage::remove_file(&other)?;
+                self.state.cache.remove(&other);
+            }
+        }
+        self.state.cache.insert(path, pattern_f32);
         Ok(self)
     }
 
-    fn compute_pattern(&mut self) -> Result<ndarray::Array2<u8>> {
-        let (size_x, size_y) = self.config.screen.size;
-        let (size_x, size_y) = (size_x as usize, size_y as usize);
+    fn add_correction_pattern_deltas(
+        &mut self,
+        pattern_deltas: &CorrectionPatternDeltas,
+    ) -> Result<&mut Self> {
+        let (path, pattern) = self.corrected_flatness_pattern(pattern_deltas)?;
+        self.store_flatness_pattern(pattern_deltas.wavelength, path, pattern)
+    }
 
-        let State {
-            fresnel,
-            wavelength,
-            ref pattern_params,
-            ..
-        } = self.state;
+    /// Apply deltas for several wavelengths; nothing is saved unless every delta is valid
+    /// and each wavelength appears only once
+    fn add_correction_pattern_deltas_batch(
+        &mut self,
+        deltas: &[CorrectionPatternDeltas],
+    ) -> Result<&mut Self> {
+        let mut corrected = Vec::new();
+        let mut results = Vec::new();
+        let mut wavelengths = HashSet::new();
+
+        for pattern_deltas in deltas {
+            let result = if wavelengths.insert(pattern_deltas.wavelength) {
+                self.corrected_flatness_pattern(pattern_deltas)
+            } else {
+                // both would be added to the same saved correction, and only the last kept
+                Err(SlmError::Request(format!(
+                    "Duplicate correction deltas for wavelength {}",
+                    pattern_deltas.wavelength
+                )))
+            };
+            if let Err(err) = &result {
+                error!(
+                    "Invalid correction deltas for wavelength {}: {}",
+                    pattern_deltas.wavelength, err
+                );
+            }
+            results.push(CorrectionDeltaResult {
+                wavelength: pattern_deltas.wavelength,
+                success: result.is_ok(),
+            });
+            corrected.push(result);
+        }
 
-        let dim = ndarray::Dim([size_x, size_y]);
+        let applied = corrected.iter().all(|result| result.is_ok());
+        if applied {
+            for (pattern_deltas, result) in deltas.iter().zip(corrected) {
+                let (path, pattern) = result?;
+                self.store_flatness_pattern(pattern_deltas.wavelength, path, pattern)?;
+            }
+        }
+
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::SetCorrectionPatternDeltasBatchResponse {
+                results,
+                applied,
+            }),
+        })
+    }
+
+    /// Re-read the scale table and the calibration store, dropping cached corrections,
+    /// and recompute the displayed pattern with them
+    pub fn reload_calibration(&mut self) -> Result<&mut Self> {
+        info!("Reloading calibration");
+        let config = read_config()?;
+        self.config.compute_pattern.slm_calib_scaling = config.compute_pattern.slm_calib_scaling;
+        self.config.temperature_scaling = config.temperature_scaling;
+        self.state.calibration = CalibrationStore::load(&self.config.dir_path.calibration_store())?;
+        self.state.cache.clear();
+        self.state.missing_correction_warned = None;
+        self.update_state(None, None, None)
+    }
+
+    fn save_calibration(&mut self) -> Result<&mut Self> {
+        let path = self.config.dir_path.calibration_store();
+        info!("Saving calibration to {:?}", path);
+        self.state.calibration.save(&path)?;
+        Ok(self)
+    }
+
+    /// Apply `change` to a copy of the calibration, which replaces it only once
+    /// the pattern is recomputed with it and it's saved
+    fn change_calibration(
+        &mut self,
+        change: impl FnOnce(&mut CalibrationStore) -> Result<()>,
+    ) -> Result<&mut Self> {
+        let mut calibration = self.state.calibration.clone();
+        change(&mut calibration)?;
+        let previous = std::mem::replace(&mut self.state.calibration, calibration);
+        let result = self
+            .update_state(None, None, None)
+            .map(|_| ())
+            .and_then(|()| self.save_calibration().map(|_| ()));
+        if let Err(err) = result {
+            self.state.calibration = previous;
+            // the panel may already show the change
+            if let Err(restore_err) = self.update_state(None, None, None) {
+                error!(
+                    "Error {} while restoring the calibration; continuing",
+                    restore_err
+                );
+            }
+            Err(err)?
+        }
+        Ok(self)
+    }
+
+    fn send_pattern_stats(&mut self) -> Result<&mut Self> {
+        let displayed = match &self.state.displayed {
+            Some(displayed) => displayed,
+            None => Err(SlmError::Request("No pattern displayed yet".to_owned()))?,
+        };
+        let mut histogram = vec![0; 256];
+        for &value in displayed {
+            histogram[value as usize] += 1;
+        }
+        let (phase_min, phase_max) = self.state.phase_range;
+        let stats = PatternStats {
+            histogram,
+            wrapped_fraction: self.state.wrapped_fraction,
+            phase_min,
+            phase_max,
+        };
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::PatternStats(stats)),
+        })
+    }
+
+    /// Gray level corresponding to a phase of 2 pi for the given wavelength
+    pub fn scale_factor(&self, wavelength: u32) -> Result<f32> {
+        let scaling = &self.config.compute_pattern.slm_calib_scaling;
+        let scale_id = scaling
+            .known_wavelengths
+            .iter()
+            .position(|&e| e == wavelength)
+            .ok_or_else(|| {
+                SlmError::Calibration(format!(
+                    "No calibration scale factor for wavelength {}",
+                    wavelength
+                ))
+            })?;
+        let mut scale = scaling.scale_factors[scale_id];
+
+        // The panel's phase response drifts with temperature
+        if let Some(temperature_scaling) = &self.config.temperature_scaling {
+            if let Some(&temperature) = self.state.temperatures.get(&temperature_scaling.sensor) {
+                if let Some(factor) = interpolate(&temperature_scaling.table, temperature) {
+                    scale *= factor;
+                }
+            }
+        }
+
+        Ok(scale)
+    }
+
+    /// Panel pixel grids, x along the first axis
+    pub fn panel_grids(&self) -> (Array, Array) {
+        let (size_x, size_y) = self.config.screen.size;
+        let (size_x, size_y) = (size_x as usize, size_y as usize);
 
         let lx = ndarray::Array1::linspace(0 as f32, size_x as f32, size_x);
         let ly = ndarray::Array1::linspace(0 as f32, size_y as f32, size_y);
@@ -343,78 +934,236 @@ impl<'a, 'b> Context<'a, 'b> {
         for mut row in yy.genrows_mut() {
             row.assign(&ly);
         }
+        (xx, yy)
+    }
+
+    /// Phase of a pattern before corrections, computed or loaded from its file
+    pub fn base_pattern(&mut self, pattern_params: &PatternParams) -> Result<Array> {
+        let (xx, yy) = self.panel_grids();
+        let dim = xx.raw_dim();
 
-        let mut pattern = match &pattern_params {
+        // computed patterns are positioned in the client coordinate system
+        let (cx, cy) = coordinates::grids(&self.config, &xx, &yy);
+        Ok(match pattern_params {
             PatternParams::Spot { spot } => {
-                let r2 = (spot.diameter / 2.0).powf(2.0);
-                Array::from_shape_fn(dim, |id| {
-                    if (xx[id] - spot.position_xy.0).powf(2.0)
-                        + (yy[id] - spot.position_xy.1).powf(2.0)
-                        < r2
-                    {
-                        spot.gradient_xy.0 * xx[id] + spot.gradient_xy.1 * yy[id]
-                    } else {
-                        spot.background_gradient_xy.0 * xx[id]
-                            + spot.background_gradient_xy.1 * yy[id]
-                    }
-                })
+                patterns::spot(spot, &cx, &cy, coordinates::pixel_size(&self.config))
+            }
+            PatternParams::Annulus { annulus } => patterns::annulus(annulus, &cx, &cy),
+            PatternParams::KnifeEdge { knife_edge } => patterns::knife_edge(knife_edge, &cx, &cy),
+            PatternParams::Complex { complex } => {
+                let encoding = self.config.compute_pattern.complex_encoding;
+                let amplitude_path = self.config.dir_path.base_patterns.join(&complex.amplitude);
+                let phase_path = self.config.dir_path.base_patterns.join(&complex.phase);
+                let amplitude = self.load_data(&amplitude_path, Some(dim))?.clone();
+                let phase = self.load_data(&phase_path, Some(dim))?;
+                patterns::encode_complex(&amplitude, phase, encoding)
             }
             PatternParams::Base { .. } | PatternParams::Custom { .. } => {
-                let path = self.get_file_path_for_base_corr_pattern(&pattern_params)?;
+                let path = self.get_file_path_for_base_corr_pattern(pattern_params)?;
                 self.load_data(&path, Some(dim))?.clone()
             }
-        };
+        })
+    }
 
-        if self.config.compute_pattern.add_flatness_correction {
-            let path = self.get_file_path_for_flatness_corr_pattern(wavelength)?;
-            let flat_corr = self.load_data(&path, Some(dim))?;
-            pattern += flat_corr;
+    /// Gray levels of a pattern image as they are, padded with 0 or cropped
+    /// to the panel
+    fn raw_frame(&mut self, pattern: &PatternParams) -> Result<ndarray::Array2<u8>> {
+        let path = self.get_file_path_for_base_corr_pattern(pattern)?;
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        if !self
+            .config
+            .image_file_extensions
+            .iter()
+            .any(|image| image.eq_ignore_ascii_case(extension))
+        {
+            Err(SlmError::Request(format!(
+                "{} can't be shown raw, only image files can",
+                path.display()
+            )))?
         }
+        let image = ndarray_image::open_gray_image(&path)?;
+        let (size_x, size_y) = self.config.screen.size;
+        self.state.applied_corrections = Vec::new();
+        Ok(ndarray::Array2::from_shape_fn(
+            (size_x as usize, size_y as usize),
+            |(x, y)| image.get((x, y)).copied().unwrap_or(0),
+        ))
+    }
 
-        let wvlen_fact = TWO_PI * 488.0 / wavelength as f32;
-        let phi_max_x = 80.0; // Change for 12-bit mode
-        let slope_x = -phi_max_x * wvlen_fact / size_x as f32;
-        let gradient = slope_x * &xx + phi_max_x * wvlen_fact * 1.1;
+    pub fn compute_pattern(&mut self) -> Result<ndarray::Array2<u8>> {
+        if self.state.blanked {
+            let (size_x, size_y) = self.config.screen.size;
+            return Ok(ndarray::Array2::zeros((size_x as usize, size_y as usize)));
+        }
+        if self.state.raw {
+            let pattern_params = self.state.pattern_params.clone();
+            return self.raw_frame(&pattern_params);
+        }
+        match self.config.compute_pattern.precision {
+            Precision::F32 => self.compute_pattern_in::<f32>(),
+            Precision::F64 => self.compute_pattern_in::<f64>(),
+        }
+    }
 
-        // TODO: add masking
-        pattern += &gradient;
+    /// The layers of the pattern summed in `T`
+    fn compute_pattern_in<T: Phase>(&mut self) -> Result<ndarray::Array2<u8>> {
+        let (size_x, size_y) = self.config.screen.size;
+        let (size_x, size_y) = (size_x as usize, size_y as usize);
 
-        if fresnel != 0 {
-            let (xc, yc) = (size_x as f32 / 2.0, size_y as f32 / 2.0); // center of the SLM
-            let pixel_size_nm = 12500_f32;
-            let fresnel_in_1_over_nm = fresnel as f32 * 1e-9;
-            let pre_factor = pixel_size_nm.powf(2.0) * std::f32::consts::PI * fresnel_in_1_over_nm
-                / wavelength as f32;
+        let State {
+            fresnel,
+            wavelength,
+            tilt_xy,
+            attenuation,
+            ..
+        } = self.state;
 
-            pattern += &(pre_factor
-                * ((xx - xc).mapv_into(|e| e.powf(2.0)) + (yy - yc).mapv_into(|e| e.powf(2.0))));
+        let pattern_params = self.state.pattern_params.clone();
+        let mut pattern = match &self.state.morph {
+            Some(run) => run.phase(),
+            None => self.base_pattern(&pattern_params)?,
+        }
+        .mapv(T::from_f32);
+        let metadata = self.pattern_metadata(&pattern_params)?;
+        if metadata.scale != 1.0 || metadata.offset != 0.0 {
+            let (scale, offset) = (T::from_f32(metadata.scale), T::from_f32(metadata.offset));
+            pattern.mapv_inplace(|e| e * scale + offset);
+        }
+        let debug = self.config.compute_pattern.debug.clone();
+        let mut stages = StageDumps::new(debug.as_ref());
+        stages.add("base", &pattern);
+        let (xx, yy) = self.panel_grids();
+        let dim = xx.raw_dim();
+        let (xx, yy) = (xx.mapv(T::from_f32), yy.mapv(T::from_f32));
+
+        let mut applied_corrections = Vec::new();
+
+        if self.config.compute_pattern.add_flatness_correction && metadata.corrections {
+            let flat_corr = self
+                .get_file_path_for_flatness_corr_pattern(wavelength)
+                .and_then(|path| Ok(self.load_data(&path, Some(dim))?.mapv(T::from_f32)));
+
+            match (flat_corr, self.config.compute_pattern.missing_correction) {
+                (Ok(flat_corr), _) => {
+                    self.state.missing_correction_warned = None;
+                    pattern += &flat_corr;
+                    applied_corrections.push("flatness".to_owned());
+                    stages.add("flatness", &pattern);
+                }
+                (Err(err), MissingCorrectionPolicy::Fail) => return Err(err),
+                (Err(err), MissingCorrectionPolicy::Warn) => {
+                    if self.state.missing_correction_warned != Some(wavelength) {
+                        self.state.missing_correction_warned = Some(wavelength);
+                        self.send_warning(format!(
+                            "Skipping flatness correction for wavelength {}: {}",
+                            wavelength, err
+                        ))?;
+                    }
+                }
+            }
         }
 
-        let scaling = &self.config.compute_pattern.slm_calib_scaling;
-        let scale_id = match scaling
-            .known_wavelengths
+        if metadata.blaze {
+            let blaze = self.blaze();
+            let wvlen_fact = T::TWO_PI * T::from_f64(f64::from(blaze.reference_wavelength))
+                / T::from_f64(f64::from(wavelength));
+            let phi_max_x = T::from_f32(blaze.phi_max); // Change for 12-bit mode
+            let sign = match self.state.diffraction_order {
+                DiffractionOrder::MinusFirst => -1.0,
+                DiffractionOrder::PlusFirst => 1.0,
+            };
+            let slope_x = T::from_f32(sign) * phi_max_x * wvlen_fact / T::from_f64(size_x as f64);
+            let gradient =
+                &xx * slope_x + phi_max_x * wvlen_fact * T::from_f32(blaze.offset_factor);
+
+            // TODO: add masking
+            pattern += &gradient;
+            stages.add("blaze", &pattern);
+        }
+
+        if tilt_xy != (0.0, 0.0) {
+            pattern += &(&xx * T::from_f32(tilt_xy.0) + &(&yy * T::from_f32(tilt_xy.1)));
+        }
+
+        let astigmatic = self.state.astigmatic_fresnel.clone();
+        if metadata.fresnel && (fresnel != 0 || astigmatic.is_some()) {
+            // centered on the illuminated part of the panel
+            let geometry = &self.config.slm_geometry;
+            let (xc, yc) = match &geometry.active_area {
+                Some(area) => (
+                    area.offset_xy.0 as f64 + area.size_xy.0 as f64 / 2.0,
+                    area.offset_xy.1 as f64 + area.size_xy.1 as f64 / 2.0,
+                ),
+                None => (size_x as f64 / 2.0, size_y as f64 / 2.0),
+            };
+            let (xc, yc) = (T::from_f64(xc), T::from_f64(yc));
+            let (pitch_x_nm, pitch_y_nm) = (
+                T::from_f32(geometry.pixel_pitch_um.0) * T::from_f64(1e3),
+                T::from_f32(geometry.pixel_pitch_um.1) * T::from_f64(1e3),
+            );
+            // focal powers along the (rotated) axes
+            let (power_u, power_v, rotation_deg) = match &astigmatic {
+                Some(astigmatic) => (
+                    f64::from(fresnel) + f64::from(astigmatic.power_xy.0),
+                    f64::from(fresnel) + f64::from(astigmatic.power_xy.1),
+                    f64::from(astigmatic.rotation_deg),
+                ),
+                None => (f64::from(fresnel), f64::from(fresnel), 0.0),
+            };
+            let (power_u, power_v) = (T::from_f64(power_u), T::from_f64(power_v));
+            let (sin, cos) = rotation_deg.to_radians().sin_cos();
+            let (sin, cos) = (T::from_f64(sin), T::from_f64(cos));
+            let pre_factor = T::from_f64(std::f64::consts::PI * 1e-9 / f64::from(wavelength));
+
+            ndarray::Zip::from(&mut pattern)
+                .and(&xx)
+                .and(&yy)
+                .apply(|e, &x, &y| {
+                    let (dx, dy) = ((x - xc) * pitch_x_nm, (y - yc) * pitch_y_nm);
+                    let (u, v) = (dx * cos + dy * sin, -dx * sin + dy * cos);
+                    *e += pre_factor * (power_u * u * u + power_v * v * v);
+                });
+            stages.add("fresnel", &pattern);
+        }
+
+        for mask in &self.state.calibration.defect_masks {
+            let (x, y) = mask.position_xy;
+            // a mask from a bigger panel (or a corrupt file) may reach past this one
+            let x_end = x.saturating_add(mask.size_xy.0).min(size_x);
+            let y_end = y.saturating_add(mask.size_xy.1).min(size_y);
+            if x < x_end && y < y_end {
+                pattern
+                    .slice_mut(ndarray::s![x..x_end, y..y_end])
+                    .fill(T::from_f32(mask.phase));
+            }
+        }
+
+        self.state.phase_range = pattern
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &e| {
+                (min.min(e.as_f32()), max.max(e.as_f32()))
+            });
+        let zero = T::from_f64(0.0);
+        self.state.wrapped_fraction = pattern
             .iter()
-            .position(|&e| e == wavelength)
-        {
-            Some(id) => id,
+            .filter(|e| !(zero..T::TWO_PI).contains(*e))
+            .count() as f32
+            / pattern.len() as f32;
+
+        // A shallower phase modulation diffracts less power into the first order
+        let depth = 1.0 - attenuation.max(self.safety().min_attenuation) / 100.0;
+
+        let pattern = match &self.config.compute_pattern.binary {
+            // A DMD can't shift the phase, so the phase can only be encoded in a binary hologram
+            Some(encoding) => patterns::binarize(&pattern.mapv(T::as_f32), encoding, depth),
             None => {
-                scaling
-                    .known_wavelengths
-                    .iter()
-                    .enumerate()
-                    .min_by_key(|(_, &e)| e.max(wavelength) - e.min(wavelength))
-                    .ok_or("no known wavelengths available")?
-                    .0
+                let levels = T::from_f32(self.scale_factor(wavelength)? * depth);
+                pattern.mapv(|e| quantize(e, levels))
             }
         };
-        let scale = scaling.scale_factors[scale_id];
+        self.state.applied_corrections = applied_corrections;
+        stages.finish(debug.as_ref(), &pattern);
 
-        let pattern = pattern.mapv(|e| (e.rem_euclid(TWO_PI) / TWO_PI * scale) as u8);
-
-        if self
-            .config
-            .compute_pattern
-            .debug
+        if debug
             .as_ref()
             .map(|d| d.save_computed_to_image)
             .unwrap_or(false)
@@ -425,6 +1174,78 @@ impl<'a, 'b> Context<'a, 'b> {
         Ok(pattern)
     }
 
+    /// Preset configured for the laser with the given wavelength, if any
+    fn laser_preset(&self, wavelength: u32) -> Result<Option<AimState>> {
+        let name = match self.config.laser_presets.get(&wavelength) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let preset = self.config.presets.get(name).ok_or_else(|| {
+            SlmError::Config(format!(
+                "Preset {} for wavelength {} is not defined",
+                name, wavelength
+            ))
+        })?;
+        Ok(Some(preset.clone()))
+    }
+
+    /// Tilt the beam by angles in milliradians (counterclockwise in the client
+    /// coordinate system) on top of the blaze, and set the fresnel if given
+    pub fn steer_beam(
+        &mut self,
+        (tilt_x_mrad, tilt_y_mrad): (f32, f32),
+        defocus: Option<u32>,
+    ) -> Result<&mut Self> {
+        if !tilt_x_mrad.is_finite() || !tilt_y_mrad.is_finite() {
+            Err(SlmError::Request(format!(
+                "Invalid tilt ({}, {}) mrad",
+                tilt_x_mrad, tilt_y_mrad
+            )))?
+        }
+        // phase difference between neighbouring pixels deflecting by the angle
+        let wavelength_nm = self.state.wavelength as f32;
+        let (pitch_x_nm, pitch_y_nm) = (
+            self.config.slm_geometry.pixel_pitch_um.0 * 1e3,
+            self.config.slm_geometry.pixel_pitch_um.1 * 1e3,
+        );
+        let gradient =
+            |mrad: f32, pitch_nm: f32| TWO_PI * pitch_nm * (mrad * 1e-3).sin() / wavelength_nm;
+        let gradient_y = match self.config.coordinates.y_axis {
+            YAxis::Down => gradient(tilt_y_mrad, pitch_y_nm),
+            YAxis::Up => -gradient(tilt_y_mrad, pitch_y_nm),
+        };
+
+        let previous = self.state.tilt_xy;
+        self.state.tilt_xy = (gradient(tilt_x_mrad, pitch_x_nm), gradient_y);
+        if let Err(err) = self.update_state(None, defocus, None) {
+            self.state.tilt_xy = previous;
+            Err(err)?
+        }
+        Ok(self)
+    }
+
+    /// Set the astigmatic part of the fresnel, or clear it with `None`
+    pub fn set_astigmatic_fresnel(
+        &mut self,
+        fresnel: Option<AstigmaticFresnel>,
+    ) -> Result<&mut Self> {
+        if let Some(astigmatic) = &fresnel {
+            let (x, y) = astigmatic.power_xy;
+            if !(x.is_finite() && y.is_finite() && astigmatic.rotation_deg.is_finite()) {
+                Err(SlmError::Request(format!(
+                    "Invalid astigmatic fresnel {:?}",
+                    astigmatic
+                )))?
+            }
+        }
+        let previous = std::mem::replace(&mut self.state.astigmatic_fresnel, fresnel);
+        if let Err(err) = self.update_state(None, None, None) {
+            self.state.astigmatic_fresnel = previous;
+            Err(err)?
+        }
+        Ok(self)
+    }
+
     /// Update current state, with an ability to leave
     /// the existing value if passed `None`
     pub fn update_state(
@@ -433,22 +1254,110 @@ impl<'a, 'b> Context<'a, 'b> {
         fresnel: Option<u32>,
         wavelength: Option<u32>,
     ) -> Result<&mut Self> {
+        self.update_state_raw(pattern_params, fresnel, wavelength, false)
+    }
+
+    /// As `update_state`; a new pattern is shown without blaze, fresnel,
+    /// corrections or calibration scaling if `raw`
+    pub fn update_state_raw(
+        &mut self,
+        mut pattern_params: Option<PatternParams>,
+        fresnel: Option<u32>,
+        wavelength: Option<u32>,
+        raw: bool,
+    ) -> Result<&mut Self> {
+        // everything is checked before the state changes, so a rejected
+        // update leaves it as it was
+        if let Some(wavelength) = wavelength {
+            let available = self.available_wavelengths();
+            if !available.contains(&wavelength) {
+                Err(SlmError::UnavailableWavelength {
+                    wavelength,
+                    available,
+                })?
+            }
+        }
+        if let Some(max_fresnel) = self.safety().max_fresnel {
+            // the strongest axis of an astigmatic lens counts
+            let strongest = fresnel.unwrap_or(self.state.fresnel) as f32
+                + match &self.state.astigmatic_fresnel {
+                    Some(astigmatic) => astigmatic.power_xy.0.max(astigmatic.power_xy.1),
+                    None => 0.0,
+                };
+            if strongest > max_fresnel as f32 {
+                Err(SlmError::Request(format!(
+                    "Fresnel {} is above the maximum of {}",
+                    strongest, max_fresnel
+                )))?
+            }
+        }
+        if let Some(PatternParams::Spot { spot }) = &mut pattern_params {
+            for (code, warning) in patterns::clamp_spot(spot, &self.config)? {
+                self.send_coded_warning(Some(code), warning)?;
+            }
+        }
+
         if let Some(pattern_params) = pattern_params {
+            self.state.raw = raw;
+            self.state.blanked = false;
             self.state.pattern_params = pattern_params;
+            if self.state.morph.take().is_some() {
+                self.clear_checkpoint();
+            }
         }
         self.state.fresnel = fresnel.unwrap_or(self.state.fresnel);
         self.state.wavelength = wavelength.unwrap_or(self.state.wavelength);
+        if self.state.batching {
+            // computed once, after the last command of the batch
+            return Ok(self);
+        }
         let pattern = self.compute_pattern()?;
         self.put_pattern(&pattern)?;
+        self.state_changed();
 
         Ok(self)
     }
 
+    /// Recreate the display buffers after the output changed, and compute
+    /// the pattern for the new size if it changed
+    fn on_display_change(&mut self) -> Result<()> {
+        let size = match self.screen_context.display.reinitialize()? {
+            Some(size) => size,
+            None => return Ok(()),
+        };
+        let previous_size = self.config.screen.size;
+        if size != previous_size {
+            warn!(
+                "Display changed from {:?} to {:?} pixels",
+                previous_size, size
+            );
+            self.config.screen.size = size;
+            // the probe patterns were computed for the old size
+            self.state.probe_run = None;
+            self.send_aim_message(&Message {
+                m_type: MessageType::Device,
+                seq: None,
+                expect: None,
+                client: None,
+                data: MessageData::Aim(AimCommand::DisplayChanged {
+                    size,
+                    previous_size,
+                }),
+            })?;
+        }
+
+        // the old frame is gone with the texture, and is no base for overdrive
+        self.state.displayed = None;
+        self.update_state(None, None, None)?;
+        Ok(())
+    }
+
     fn on_connect(&mut self) -> Result<()> {
-        const SUBTOPICS: [&str; 4] = [
+        const SUBTOPICS: [&str; 5] = [
             "embedded/aim",
             "gui/aim",
             "calibration/aim",
+            "cli/aim",         // `rasp_pi send`
             "embedded/lasers", // start -> give me all the lasers -> reply
         ];
 
@@ -457,112 +1366,603 @@ impl<'a, 'b> Context<'a, 'b> {
             let topic = self.config.main_topic().subtopic(subtopic);
             self.client.subscribe(&topic, 0)?;
             info!("Subscribed to {}", topic);
+            for encoding in BINARY_ENCODINGS.iter() {
+                self.client
+                    .subscribe(&topic.as_str().subtopic(encoding.suffix()), 0)?;
+            }
         }
+        self.subscribe_raw_frames()?;
 
         self.send_get_lasers()?
             .send_available_patterns()?
-            .send_current_state()?;
+            .resend_current_state()?;
 
         Ok(())
     }
 
     pub fn process_message(&mut self, mqtt_message: &MqttMessage) -> Result<()> {
+        if self.is_raw_frame(mqtt_message) {
+            return self.process_raw_frame(mqtt_message.topic(), mqtt_message.payload());
+        }
+
         // Note: here the python script decodes the payload as a cp437 string,
         // however I feel like here there shouldn't be any interesting characters from cp437,
         // so it's fine to parse it as unicode
-        let message: Message = serde_json::from_slice(mqtt_message.payload())?;
+        let (topic, message) =
+            encoding::decode_message(mqtt_message, self.config.mqtt.max_decompressed_bytes)?;
 
         info!(
             "Message recieved: Topic: {}, Contents: {:?}",
-            mqtt_message.topic(),
-            message
+            topic, message
         );
 
+        if self.suppressed_by_maintenance(&message, topic) {
+            info!("Maintenance mode; not applying the message");
+            if let Some(seq) = message.seq {
+                self.send_ack(seq, false)?;
+            }
+            self.send_response(ResponseCode::Maintenance)?;
+            return Ok(());
+        }
+
         match (&message.m_type, &message.data) {
             (MessageType::Status, MessageData::Embedded(EmbeddedCommand::InitDone)) => {
                 self.send_get_lasers()?
                     .send_available_patterns()?
-                    .send_current_state()?;
+                    .resend_current_state()?;
+                return Ok(());
             }
             (MessageType::Device, MessageData::Lasers(LaserCommand::Set { lasers })) => {
                 info!("Received laser wavelengths and intensities.");
                 info!("Selecting wavelength with highest intensity.");
 
-                let strongest = lasers
-                    .iter()
-                    .filter(|laser| laser.state != 0 && laser.name != "led")
-                    .max_by_key(|laser| laser.intensity);
-
-                let strongest = match strongest {
-                    Some(strongest) => strongest.wavelength,
+                let strongest = match self.select_wavelength(lasers) {
+                    Some(strongest) => strongest,
                     None => {
                         info!("No lasers enabled; skipping");
                         return Ok(());
                     }
                 };
 
-                self.update_state(None, None, Some(strongest))?
-                    .send_current_state()?;
+                // Only switch presets when the active laser changes,
+                // so patterns set from the GUI in the meantime are kept
+                let preset = if strongest != self.state.wavelength {
+                    self.laser_preset(strongest)?
+                } else {
+                    None
+                };
+
+                match preset {
+                    Some(preset) => {
+                        info!("Applying preset for wavelength {}", strongest);
+                        let pattern = self.pattern_to_slm(preset.space, preset.pattern)?;
+                        self.update_state(Some(pattern), Some(preset.fresnel), Some(strongest))?
+                    }
+                    None => self.update_state(None, None, Some(strongest))?,
+                }
+                .send_current_state()?;
+                return Ok(());
             }
             _ => (),
         };
 
         let aim_command = match (&message.m_type, &message.data) {
             (MessageType::Device, MessageData::Aim(aim_command)) => aim_command.clone(),
-            _ => Err(format!("Unexpected message: {:?}", message))?,
+            _ => Err(SlmError::Request(format!(
+                "Unexpected message: {:?}",
+                message
+            )))?,
         };
 
-        let custom_pattern_path = |name: &str| -> Result<PathBuf> {
-            let mut path = std::env::current_dir()?;
-            path.push(&self.config.dir_path.base_patterns);
-            path.push("custom_patterns");
-            path.push(name);
-            Ok(path)
+        if !self.config.is_permitted(aim_command.name(), topic) {
+            Err(SlmError::Request(format!(
+                "Command {} is not permitted on {}",
+                aim_command.name(),
+                topic
+            )))?
+        }
+
+        self.wake_from_idle()?;
+
+        match self.state.rate_limiter.admit(aim_command.name()) {
+            Admission::Accept => (),
+            Admission::AcceptAfterDropping(dropped) => {
+                self.send_warning(format!(
+                    "Dropped {} {} commands over the rate limit",
+                    dropped,
+                    aim_command.name()
+                ))?;
+            }
+            Admission::Drop { first } => {
+                if first {
+                    self.send_warning(format!(
+                        "Too many {} commands (limit {}/s); dropping",
+                        aim_command.name(),
+                        self.state.rate_limiter.limit(aim_command.name()).unwrap()
+                    ))?;
+                }
+                return Ok(());
+            }
+        }
+
+        // Redelivered commands are re-acked without being processed again,
+        // older ones would overwrite newer state
+        let sender = (topic.to_owned(), message.client.clone());
+        if let (Some(seq), Some(processed)) = (message.seq, self.state.processed_seqs.get(&sender))
+        {
+            if processed.contains(&seq) {
+                info!("Command {} was already processed", seq);
+                self.send_ack(seq, true)?;
+                return Ok(());
+            }
+            if let Some(&last) = processed.back().filter(|&&last| seq < last) {
+                Err(SlmError::Request(format!(
+                    "Stale command {}; already processed {}",
+                    seq, last
+                )))?
+            }
+        }
+
+        let client = message.client.as_deref();
+        self.check_control(&aim_command, client, topic)?;
+        if let Some(expect) = &message.expect {
+            self.check_precondition(expect)?;
+        }
+
+        // Set before running the command, so the state it publishes names the origin
+        let origin = client.unwrap_or(topic).to_owned();
+        let modifies = !aim_command.is_query();
+        let previous_modifier = self.state.last_modified_by.clone();
+        if modifies {
+            self.state.last_modified_by = Some(origin.clone());
+        }
+
+        // a crashed acquisition would send nothing more
+        if modifies && !matches!(aim_command, AimCommand::PreStack(_)) {
+            self.disarm_prestack();
+        }
+
+        let name = aim_command.name();
+        let started = Instant::now();
+        self.state.handling_request = true;
+        let result = match aim_command {
+            AimCommand::Batch { commands } => self.execute_batch(commands, topic).map(|_| ()),
+            AimCommand::AcquireControl => self
+                .acquire_control(client)
+                .and_then(|context| context.send_current_state())
+                .map(|_| ()),
+            AimCommand::ReleaseControl => self
+                .release_control(client, topic)
+                .and_then(|context| context.send_current_state())
+                .map(|_| ()),
+            aim_command => self.execute(aim_command),
         };
+        self.state.handling_request = false;
+        self.state
+            .metrics
+            .record(name, started.elapsed(), result.is_err());
+        if let Err(err) = result {
+            self.state.last_modified_by = previous_modifier;
+            Err(err)?
+        }
+        if modifies {
+            self.journal(&origin, name);
+        }
+
+        if let Some(seq) = message.seq {
+            let processed = self.state.processed_seqs.entry(sender).or_default();
+            processed.push_back(seq);
+            if processed.len() > RECENT_SEQS {
+                processed.pop_front();
+            }
+            self.send_ack(seq, false)?;
+        }
+
+        Ok(())
+    }
 
+    /// Process a message from a local transport as if it arrived on `subtopic`;
+    /// the messages sent meanwhile are returned as the replies, and are only
+    /// published as well while the broker is connected
+    pub fn process_local_message(
+        &mut self,
+        subtopic: &str,
+        payload: Vec<u8>,
+    ) -> Result<Vec<Message>> {
+        let topic = self.config.main_topic().subtopic(subtopic);
+        let offline = self.offline;
+        self.offline |= !self.client.is_connected();
+        self.state.captured = Some(Vec::new());
+
+        let mqtt_message = MqttMessage::new(&topic, payload, 0);
+        let result = self.process_message(&mqtt_message).or_else(|err| {
+            error!(
+                "Error {} while processing local message {}",
+                err, mqtt_message
+            );
+            let seq =
+                encoding::decode_message(&mqtt_message, self.config.mqtt.max_decompressed_bytes)
+                    .ok()
+                    .and_then(|(_, message)| message.seq);
+            self.send_error(&err, seq).map(|_| ())
+        });
+
+        let replies = self.state.captured.take().unwrap_or_default();
+        self.offline = offline;
+        result.map(|_| replies)
+    }
+
+    /// Escaping keeps the path in the custom patterns directory whatever the name
+    pub fn custom_pattern_path(&self, name: &str) -> Result<PathBuf> {
+        let mut dir = std::env::current_dir()?;
+        dir.push(&self.config.dir_path.base_patterns);
+        dir.push("custom_patterns");
+        let escaped = dir.join(pattern_names::escape(name));
+        // a legacy file named as it is, if that's a plain file name
+        let legacy = dir.join(name);
+        if !escaped.exists()
+            && Path::new(name).file_name() == Some(name.as_ref())
+            && legacy.exists()
+        {
+            return Ok(legacy);
+        }
+        Ok(escaped)
+    }
+
+    /// The pattern may be cached under its previous contents
+    pub fn forget_cached(&mut self, path: &Path) {
+        let file_name = path.file_name();
+        self.state
+            .cache
+            .retain(|cached, _| cached.file_name() != file_name);
+    }
+
+    /// Save uploaded phase samples as the custom pattern `name`
+    fn save_phase_samples(&mut self, name: &str, data: &str, samples: &PhaseSamples) -> Result<()> {
+        let phase = parse_phase_samples(data, samples)?;
+        let path = pattern_names::with_extension(&self.custom_pattern_path(name)?, NPY_EXTENSION);
+        info!("Saving phase samples to {:?}", path);
+        save_npy(&path, &phase)?;
+        self.forget_cached(&path);
+        Ok(())
+    }
+
+    /// Carry out a command that was accepted
+    pub fn execute(&mut self, aim_command: AimCommand) -> Result<()> {
         match aim_command {
             AimCommand::Set(aim_state) => {
-                self.update_state(Some(aim_state.pattern), Some(aim_state.fresnel), None)?
+                let pattern = self.pattern_to_slm(aim_state.space, aim_state.pattern)?;
+                self.update_state_raw(Some(pattern), Some(aim_state.fresnel), None, aim_state.raw)?
                     .send_current_state()?;
             }
             AimCommand::PreStack(aim_state) => {
-                self.update_state(Some(aim_state.pattern), Some(aim_state.fresnel), None)?
+                self.arm_prestack();
+                let pattern = self.pattern_to_slm(aim_state.space, aim_state.pattern)?;
+                self.update_state_raw(Some(pattern), Some(aim_state.fresnel), None, aim_state.raw)?
                     .send_current_state()?
-                    .send_prestack_done()?;
+                    .send_response(ResponseCode::PrestackDone)?;
             }
             // --------------  Messages coming from LuxControl GUI in live mode -----------
             AimCommand::Get => {
-                self.send_current_state()?;
+                self.resend_current_state()?;
             }
-            AimCommand::GetAllPatterns => {
+            AimCommand::GetAllPatterns {
+                prefix: None,
+                offset: None,
+                limit: None,
+            } => {
                 self.send_available_patterns()?;
             }
+            AimCommand::GetAllPatterns {
+                prefix,
+                offset,
+                limit,
+            } => {
+                self.send_pattern_page(
+                    prefix.as_deref().unwrap_or(""),
+                    offset.unwrap_or(0),
+                    limit,
+                )?;
+            }
+            AimCommand::GetAvailableWavelengths => {
+                self.send_available_wavelengths()?;
+            }
+            AimCommand::SteerBeam {
+                tilt_x_mrad,
+                tilt_y_mrad,
+                defocus,
+            } => {
+                self.steer_beam((tilt_x_mrad, tilt_y_mrad), defocus)?
+                    .send_current_state()?;
+            }
+            AimCommand::SetAstigmaticFresnel { fresnel } => {
+                self.set_astigmatic_fresnel(fresnel)?.send_current_state()?;
+            }
             AimCommand::SetFresnel { value } => {
                 self.update_state(None, Some(value), None)?
                     .send_current_state()?;
             }
-            AimCommand::SetPattern { pattern } => {
-                self.update_state(Some(pattern), None, None)?
+            AimCommand::SetAttenuation { percent } => {
+                if !(0.0..=100.0).contains(&percent) {
+                    Err(SlmError::Request(format!(
+                        "Attenuation {}% is out of range 0-100",
+                        percent
+                    )))?
+                }
+                let previous = self.state.attenuation;
+                self.state.attenuation = percent;
+                if let Err(err) = self.update_state(None, None, None) {
+                    self.state.attenuation = previous;
+                    Err(err)?
+                }
+                self.send_current_state()?;
+            }
+            AimCommand::SetDiffractionOrder { order } => {
+                let previous = self.state.diffraction_order;
+                self.state.diffraction_order = order;
+                if let Err(err) = self.update_state(None, None, None) {
+                    self.state.diffraction_order = previous;
+                    Err(err)?
+                }
+                self.send_current_state()?;
+            }
+            AimCommand::SetProfile { name } => {
+                self.set_profile(name)?.send_current_state()?;
+            }
+            AimCommand::SetPattern { pattern, raw } => {
+                self.update_state_raw(Some(pattern), None, None, raw)?
                     .send_current_state()?;
             }
-            AimCommand::UploadImage { name, imagedata } => {
-                save_image_data(custom_pattern_path(&name)?, imagedata)?;
-                self.send_available_patterns()?.send_current_state()?;
+            AimCommand::UploadImage {
+                name,
+                imagedata,
+                extension,
+                samples,
+            } => {
+                match samples {
+                    Some(samples) => self.save_phase_samples(&name, &imagedata, &samples)?,
+                    None => {
+                        let path = save_image_data(
+                            self.custom_pattern_path(&name)?,
+                            &imagedata,
+                            extension.as_deref(),
+                        )?;
+                        self.forget_cached(&path);
+                    }
+                }
+                self.state.available_patterns_changed = true;
+                self.send_current_state()?;
+            }
+            AimCommand::DerivePattern { operation, output } => {
+                self.derive_pattern(&operation, &output)?;
+                self.state.available_patterns_changed = true;
+                self.send_current_state()?;
             }
             AimCommand::DeleteImage { name } => {
-                std::fs::remove_file(custom_pattern_path(&name)?)?;
-                self.send_available_patterns()?.send_current_state()?;
+                let path = self.custom_pattern_path(&name)?;
+                storage::remove_file(&path)?;
+                self.forget_cached(&path);
+                let metadata = metadata_path(&path);
+                if metadata.is_file() {
+                    storage::remove_file(&metadata)?;
+                }
+                self.state.available_patterns_changed = true;
+                self.send_current_state()?;
             }
             // ----------  END Messages coming from LuxControl GUI in live mode -----------
             // --------------  Messages coming from SLM-calibraton software ---------------
             AimCommand::SetCorrectionPatternDeltas(pattern_deltas) => {
                 self.add_correction_pattern_deltas(&pattern_deltas)?
                     .send_set_correction_pattern_deltas(pattern_deltas.wavelength)?;
+                let recompute = pattern_deltas.recompute
+                    || self.config.compute_pattern.recompute_on_correction_change;
+                if recompute && pattern_deltas.wavelength == self.state.wavelength {
+                    self.update_state(None, None, None)?.send_current_state()?;
+                }
+            }
+            AimCommand::SetCorrectionPatternDeltasBatch { deltas } => {
+                self.add_correction_pattern_deltas_batch(&deltas)?;
+                let recompute = self.config.compute_pattern.recompute_on_correction_change
+                    || deltas.iter().any(|d| d.recompute);
+                if recompute && deltas.iter().any(|d| d.wavelength == self.state.wavelength) {
+                    self.update_state(None, None, None)?.send_current_state()?;
+                }
+            }
+            AimCommand::ReloadCalibration => {
+                self.reload_calibration()?.send_current_state()?;
+            }
+            AimCommand::AddDefectMask(mask) => {
+                self.change_calibration(|calibration| {
+                    let masks = &mut calibration.defect_masks;
+                    masks.retain(|m| m.name != mask.name);
+                    masks.push(mask);
+                    Ok(())
+                })?
+                .send_current_state()?;
+            }
+            AimCommand::RemoveDefectMask { name } => {
+                self.change_calibration(|calibration| {
+                    let masks = &mut calibration.defect_masks;
+                    let count = masks.len();
+                    masks.retain(|m| m.name != name);
+                    if masks.len() == count {
+                        Err(SlmError::Request(format!("No defect mask named {}", name)))?
+                    }
+                    Ok(())
+                })?
+                .send_current_state()?;
             }
             // ----------- END Messages coming from SLM-calibraton software ---------------
+            AimCommand::MeasureLatency {
+                iterations,
+                photodiode,
+            } => {
+                self.measure_latency(iterations, photodiode)?;
+            }
+            AimCommand::StartProbeSequence {
+                sequence,
+                interval_ms,
+            } => {
+                self.start_probe_sequence(sequence, interval_ms, 0)?;
+            }
+            AimCommand::NextProbe => {
+                self.next_probe()?;
+            }
+            AimCommand::ScanSpot {
+                trajectory,
+                dwell_ms,
+                triggered,
+                space,
+            } => {
+                let dwell = Duration::from_millis(dwell_ms);
+                let points = trajectory_points(&trajectory, self.config.max_scan_points)?
+                    .into_iter()
+                    .map(|point| Ok((self.point_to_slm(space, point)?, dwell)))
+                    .collect::<Result<_>>()?;
+                self.start_scan(points, triggered, false)?;
+            }
+            AimCommand::FollowWaypoints {
+                waypoints,
+                looping,
+                space,
+            } => {
+                let points = waypoints
+                    .into_iter()
+                    .map(|waypoint| {
+                        Ok((
+                            self.point_to_slm(space, waypoint.position_xy)?,
+                            Duration::from_millis(waypoint.dwell_ms),
+                        ))
+                    })
+                    .collect::<Result<_>>()?;
+                self.start_scan(points, false, looping)?;
+            }
+            AimCommand::ScanTrigger => {
+                self.trigger_scan()?;
+            }
+            AimCommand::PauseScan => {
+                self.pause_scan(true)?;
+            }
+            AimCommand::ResumeScan => {
+                self.pause_scan(false)?;
+            }
+            AimCommand::StopScan => {
+                self.stop_scan()?;
+            }
+            AimCommand::FresnelSweep {
+                from,
+                to,
+                steps,
+                dwell_ms,
+            } => {
+                self.start_fresnel_sweep(from, to, steps, dwell_ms, 0)?;
+            }
+            AimCommand::Morph {
+                from,
+                to,
+                steps,
+                dwell_ms,
+            } => {
+                self.start_morph(from, to, steps, dwell_ms, 0)?;
+            }
+            AimCommand::StartGrayLevels { step, interval_ms } => {
+                self.start_gray_levels(step, interval_ms, 0)?;
+            }
+            AimCommand::NextGrayLevel => {
+                self.next_gray_level()?;
+            }
+            AimCommand::StopGrayLevels => {
+                self.stop_gray_levels()?;
+            }
+            AimCommand::StopProbeSequence => {
+                self.stop_probe_sequence()?;
+            }
+            AimCommand::ResumeSequence => {
+                self.resume_sequence()?;
+            }
+            AimCommand::DiscardSequence => {
+                self.discard_sequence()?;
+            }
+            AimCommand::SimulateFarField { thumbnail_size } => {
+                self.send_far_field(thumbnail_size.unwrap_or(256))?;
+            }
+            AimCommand::AddScheduledTask(task) => {
+                self.add_scheduled_task(task)?;
+            }
+            AimCommand::RemoveScheduledTask { name } => {
+                self.remove_scheduled_task(&name)?;
+            }
+            AimCommand::GetSchedule => {
+                self.get_schedule()?;
+            }
+            AimCommand::GetPatternStats => {
+                self.send_pattern_stats()?;
+            }
+            AimCommand::Identify => {
+                self.send_aim_message(&Message {
+                    m_type: MessageType::Device,
+                    seq: None,
+                    expect: None,
+                    client: None,
+                    data: MessageData::Aim(AimCommand::Identity(build_info())),
+                })?;
+            }
+            AimCommand::SetRegistration(source) => {
+                self.set_registration(source)?;
+            }
+            AimCommand::ClearRegistration => {
+                self.clear_registration()?;
+            }
+            AimCommand::GetRegistration => {
+                self.get_registration()?;
+            }
+            AimCommand::StartFiducials {
+                positions,
+                homography,
+            } => {
+                self.start_fiducials(positions, homography)?;
+            }
+            AimCommand::FiducialDetected { camera_xy } => {
+                self.fiducial_detected(camera_xy)?;
+            }
+            AimCommand::StopFiducials => {
+                self.stop_fiducials()?;
+            }
+            AimCommand::GetCapabilities => {
+                self.send_aim_message(&Message {
+                    m_type: MessageType::Device,
+                    seq: None,
+                    expect: None,
+                    client: None,
+                    data: MessageData::Aim(AimCommand::Capabilities(Capabilities {
+                        coordinates: self.config.coordinates.clone(),
+                        panel_size: self.config.screen.size,
+                        geometry: self.config.slm_geometry.clone(),
+                    })),
+                })?;
+            }
+            AimCommand::GetParameterSchema => {
+                self.send_parameter_schema()?;
+            }
+            AimCommand::SetMaintenanceMode { on } => {
+                self.set_maintenance_mode(on)?.send_current_state()?;
+            }
+            AimCommand::CaptureFrame { label } => {
+                self.capture_frame(label)?;
+            }
+            AimCommand::CreateBackup { publish } => {
+                self.create_backup(publish)?;
+            }
+            AimCommand::RestoreBackup { name } => {
+                self.restore_backup(&name)?
+                    .send_response(ResponseCode::BackupRestored)?;
+            }
             AimCommand::Reboot => {
                 system_shutdown::reboot()?;
             }
+            AimCommand::Update { url, sha256 } => {
+                self.start_update(&url, &sha256)?;
+            }
             _ => (),
         }
 
@@ -576,23 +1976,80 @@ impl<'a, 'b> Context<'a, 'b> {
         // need to create the channel before calling on_connect, otherwise messages might be lost
         let message_channel = self.client.start_consuming();
 
-        let mut window_events = self.screen_context.sdl_context.event_pump()?;
-
+        let mut window_events = self
+            .screen_context
+            .sdl_context
+            .event_pump()
+            .map_err(SlmError::Display)?;
+        let mut gamepad = self.open_gamepad()?;
+        let sensors = SensorPoller::spawn(
+            &self.config.sensors,
+            Duration::from_secs(self.config.sensor_interval_secs),
+        );
+        let mut last_status: Option<Instant> = None;
+        let mut backlog = VecDeque::new();
+
+        self.open_zeromq()?;
+        self.open_grpc()?;
+        self.open_video_stream()?;
+        self.open_session_log()?;
+        for dir in [
+            &self.config.dir_path.flatness_corr_patterns,
+            &self.config.dir_path.base_patterns,
+        ] {
+            match storage::protect_factory_files(dir) {
+                Ok(protected) => info!("{} factory files in {:?} are read-only", protected, dir),
+                Err(err) => error!("Error {} while protecting factory files; continuing", err),
+            }
+        }
         self.on_connect()?;
+        if let Err(err) = self.send_startup_summary() {
+            error!(
+                "Error {} while sending the startup summary; continuing",
+                err
+            );
+        }
+        if let Err(err) = self.offer_resume() {
+            error!(
+                "Error {} while loading the sequence checkpoint; continuing",
+                err
+            );
+        }
 
         info!("Starting message processing");
         'message_loop: loop {
             // process messages from server
-            if let Ok(Some(message)) = message_channel.try_recv() {
+            // drain the channel, so the backlog depth is known
+            while let Ok(Some(message)) = message_channel.try_recv() {
+                // only the latest of the raw frames waiting is worth showing
+                if self.is_raw_frame(&message) {
+                    backlog.retain(|waiting| !self.is_raw_frame(waiting));
+                }
+                backlog.push_back(message);
+            }
+            if let Some(message) = backlog.pop_front() {
                 if let Err(err) = self.process_message(&message) {
                     error!(
                         "Error {} while processing message {}; continuing",
                         err, message
                     );
+                    // so the sender can tell which of its commands failed
+                    let seq =
+                        encoding::decode_message(&message, self.config.mqtt.max_decompressed_bytes)
+                            .ok()
+                            .and_then(|(_, message)| message.seq);
+                    if let Err(err) = self.send_error(&err, seq) {
+                        error!("Error {} while reporting error; continuing", err);
+                    }
                 }
                 continue;
             }
 
+            // the burst of messages is processed
+            if let Err(err) = self.flush_available_patterns() {
+                error!("Error {} while announcing patterns; continuing", err);
+            }
+
             // process events from the display window
             if let Some(event) = window_events.poll_event() {
                 match event {
@@ -601,11 +2058,106 @@ impl<'a, 'b> Context<'a, 'b> {
                         ..
                     }
                     | Event::Quit { .. } => break 'message_loop,
+                    Event::Window {
+                        win_event: WindowEvent::SizeChanged(..),
+                        ..
+                    }
+                    | Event::RenderTargetsReset { .. }
+                    | Event::RenderDeviceReset { .. } => {
+                        if let Err(err) = self.on_display_change() {
+                            error!("Error {} after display change; continuing", err);
+                            if let Err(err) = self.send_error(&err, None) {
+                                error!("Error {} while reporting error; continuing", err);
+                            }
+                        }
+                    }
                     _ => {}
                 }
 
                 continue;
             }
+
+            if let Some(status) = &self.config.status {
+                let interval = Duration::from_secs(status.interval_secs);
+                if last_status.is_none_or(|last| last.elapsed() >= interval) {
+                    last_status = Some(Instant::now());
+                    if let Err(err) = self.send_status(backlog.len()) {
+                        error!("Error {} while sending status; continuing", err);
+                    }
+                }
+            }
+
+            if let Some(sensors) = &sensors {
+                self.poll_sensors(sensors);
+            }
+
+            if let Err(err) = self.poll_grpc() {
+                error!("Error {} while serving gRPC; continuing", err);
+            }
+
+            if let Err(err) = self.poll_zeromq() {
+                error!("Error {} while serving ZeroMQ; continuing", err);
+            }
+
+            if let Err(err) = self.poll_video_stream() {
+                error!("Error {} while streaming the pattern; continuing", err);
+            }
+
+            if let Err(err) = self.poll_laser_simulator() {
+                error!("Error {} while simulating lasers; continuing", err);
+            }
+
+            if let Err(err) = self.poll_drop_dir() {
+                error!("Error {} while reading command files; continuing", err);
+            }
+
+            if let Err(err) = self.poll_update() {
+                error!("Error {} while updating; continuing", err);
+                if let Err(err) = self.send_error(&err, None) {
+                    error!("Error {} while reporting error; continuing", err);
+                }
+            }
+
+            if let Err(err) = self.poll_clock() {
+                error!("Error {} while checking the clock; continuing", err);
+            }
+
+            if let Err(err) = self.poll_probe_sequence() {
+                error!("Error {} while showing probe pattern; continuing", err);
+            }
+
+            self.poll_schedule();
+
+            if let Err(err) = self.poll_scan() {
+                error!("Error {} while scanning spot; continuing", err);
+            }
+
+            if let Err(err) = self.poll_fresnel_sweep() {
+                error!("Error {} during fresnel sweep; continuing", err);
+            }
+
+            if let Err(err) = self.poll_morph() {
+                error!("Error {} during morph; continuing", err);
+            }
+
+            if let Err(err) = self.poll_gray_levels() {
+                error!("Error {} while showing gray level; continuing", err);
+            }
+
+            if let Err(err) = self.poll_prestack() {
+                error!("Error {} while reverting PreStack; continuing", err);
+            }
+
+            if let Err(err) = self.poll_idle() {
+                error!("Error {} during burn-in protection; continuing", err);
+            }
+
+            // adjust tilt and fresnel from the gamepad sticks
+            if let Some(gamepad) = &mut gamepad {
+                if let Err(err) = self.poll_gamepad(gamepad) {
+                    error!("Error {} while processing gamepad input; continuing", err);
+                }
+            }
         }
 
         Ok(())
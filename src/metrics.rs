@@ -0,0 +1,81 @@
+//! Processing time of each command, reported with the status message
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::schema::CommandMetrics;
+
+struct Sample {
+    at: Instant,
+    took: Duration,
+    failed: bool,
+}
+
+/// Samples within a rolling window, by command name
+pub struct Metrics {
+    window: Duration,
+    samples: HashMap<&'static str, VecDeque<Sample>>,
+}
+
+fn drop_older(samples: &mut VecDeque<Sample>, window: Duration, now: Instant) {
+    while samples
+        .front()
+        .is_some_and(|sample| now.duration_since(sample.at) > window)
+    {
+        samples.pop_front();
+    }
+}
+
+/// Nearest-rank percentile of sorted durations
+fn percentile_ms(sorted: &[Duration], percent: usize) -> f64 {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1].as_secs_f64() * 1e3
+}
+
+impl Metrics {
+    pub fn new(window: Duration) -> Self {
+        Metrics {
+            window,
+            samples: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, command: &'static str, took: Duration, failed: bool) {
+        let now = Instant::now();
+        let samples = self.samples.entry(command).or_default();
+        drop_older(samples, self.window, now);
+        samples.push_back(Sample {
+            at: now,
+            took,
+            failed,
+        });
+    }
+
+    /// Summary of the samples within the window
+    pub fn report(&mut self) -> Vec<CommandMetrics> {
+        let now = Instant::now();
+        let window = self.window;
+        self.samples.retain(|_, samples| {
+            drop_older(samples, window, now);
+            !samples.is_empty()
+        });
+
+        let mut report: Vec<_> = self
+            .samples
+            .iter()
+            .map(|(&command, samples)| {
+                let mut took: Vec<_> = samples.iter().map(|sample| sample.took).collect();
+                took.sort();
+                CommandMetrics {
+                    command: command.to_owned(),
+                    count: samples.len(),
+                    errors: samples.iter().filter(|sample| sample.failed).count(),
+                    p50_ms: percentile_ms(&took, 50),
+                    p95_ms: percentile_ms(&took, 95),
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| a.command.cmp(&b.command));
+        report
+    }
+}
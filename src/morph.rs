@@ -0,0 +1,119 @@
+//! Linear phase interpolation between two patterns over a number of frames,
+//! for moving trapped particles adiabatically from one configuration to another
+
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::{
+    schema::{AimCommand, Message, MessageData, MessageType, PatternParams, ResponseCode},
+    Array, Context, Result, SlmError,
+};
+
+pub struct MorphRun {
+    from: Array,
+    to: Array,
+    /// Shown with its own parameters once the morph is over
+    to_params: PatternParams,
+    index: u32,
+    steps: u32,
+    dwell: Duration,
+    shown_at: Instant,
+}
+
+impl MorphRun {
+    /// Base phase of the current step, used instead of the pattern's own
+    pub fn phase(&self) -> Array {
+        let t = self.index as f32 / self.steps as f32;
+        &self.from * (1.0 - t) + &(&self.to * t)
+    }
+}
+
+impl<'a> Context<'a> {
+    fn send_morph_step(&mut self, index: u32, total: u32) -> Result<&mut Self> {
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::MorphStep { index, total }),
+        })
+    }
+
+    /// `first` is the index of the first step shown, when resuming
+    pub fn start_morph(
+        &mut self,
+        from: PatternParams,
+        to: PatternParams,
+        steps: u32,
+        dwell_ms: u64,
+        first: u32,
+    ) -> Result<&mut Self> {
+        if steps == 0 {
+            Err(SlmError::Request("Morph needs steps".to_owned()))?
+        }
+        if first >= steps {
+            Err(SlmError::Request(format!("Morph has no step {}", first)))?
+        }
+        let from_phase = self.base_pattern(&from)?;
+        let to_phase = self.base_pattern(&to)?;
+        if from_phase.dim() != to_phase.dim() {
+            Err(SlmError::Pattern(format!(
+                "Can't morph between patterns of size {:?} and {:?}",
+                from_phase.dim(),
+                to_phase.dim()
+            )))?
+        }
+
+        info!("Morphing in {} steps", steps);
+        // also ends a running morph
+        self.update_state(Some(from.clone()), None, None)?;
+        self.begin_checkpoint(
+            AimCommand::Morph {
+                from,
+                to: to.clone(),
+                steps,
+                dwell_ms,
+            },
+            steps,
+        );
+        self.state.morph = Some(MorphRun {
+            from: from_phase,
+            to: to_phase,
+            to_params: to,
+            index: first,
+            steps,
+            dwell: Duration::from_millis(dwell_ms),
+            shown_at: Instant::now(),
+        });
+        if first > 0 {
+            self.update_state(None, None, None)?;
+        }
+        self.checkpoint(first);
+        self.send_morph_step(first, steps)
+    }
+
+    /// Show the next step once the dwell time is over
+    pub fn poll_morph(&mut self) -> Result<()> {
+        let run = match &mut self.state.morph {
+            Some(run) if run.shown_at.elapsed() >= run.dwell => run,
+            _ => return Ok(()),
+        };
+
+        run.index += 1;
+        run.shown_at = Instant::now();
+        let (index, steps) = (run.index, run.steps);
+        if index < steps {
+            self.update_state(None, None, None)?;
+            self.checkpoint(index);
+            self.send_morph_step(index, steps)?;
+        } else {
+            info!("Morph done");
+            let to = run.to_params.clone();
+            self.update_state(Some(to), None, None)?
+                .send_current_state()?
+                .send_response(ResponseCode::MorphDone)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+//! Overdrive frames for liquid crystal SLMs: a transient frame, looked up from
+//! the previous and target gray levels, drives the pixels to the target faster
+
+use std::path::Path;
+
+use ndarray::Array2;
+
+use crate::{storage::read_npy, Result, SlmError};
+
+pub fn load_lut(path: &Path) -> Result<Array2<u8>> {
+    let lut = read_npy::<u8>(path)?;
+    if lut.shape() != [256, 256] {
+        Err(SlmError::Config(format!(
+            "Overdrive LUT {:?} has shape {:?} instead of [256, 256]",
+            path,
+            lut.shape()
+        )))?
+    }
+    Ok(lut)
+}
+
+pub fn overdrive_frame(lut: &Array2<u8>, previous: &Array2<u8>, target: &Array2<u8>) -> Array2<u8> {
+    Array2::from_shape_fn(target.dim(), |id| {
+        lut[[previous[id] as usize, target[id] as usize]]
+    })
+}
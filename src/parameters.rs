@@ -0,0 +1,196 @@
+//! `getParameterSchema`: the parameters clients can set at runtime, with their
+//! types, ranges, units and startup values, so a GUI can generate its controls
+//! instead of hard-coding them for each controller version
+
+use serde::Serialize;
+
+use crate::{
+    schema::{
+        AimCommand, DiffractionOrder, Message, MessageData, MessageType, Parameter, ParameterValue,
+        PatternParams,
+    },
+    Context, Result,
+};
+
+/// Name of an enum variant as it is sent
+fn wire_name<T: Serialize>(value: &T) -> Result<String> {
+    Ok(serde_json::to_value(value)?
+        .as_str()
+        .unwrap_or_default()
+        .to_owned())
+}
+
+fn parameter(
+    name: &str,
+    (command, field): (&str, &str),
+    unit: Option<&str>,
+    value: ParameterValue,
+) -> Parameter {
+    Parameter {
+        name: name.to_owned(),
+        device: None,
+        command: command.to_owned(),
+        field: field.to_owned(),
+        unit: unit.map(str::to_owned),
+        value,
+    }
+}
+
+/// Value of a property of the pattern `name` in `pattern`, if it's that pattern
+fn pattern_property(pattern: &PatternParams, name: &str, property: &str) -> Option<String> {
+    match pattern {
+        PatternParams::Base { base } if base.filename == name => {
+            base.properties.get(property).cloned()
+        }
+        PatternParams::Custom { custom } if name == "custom" && property == "filename" => {
+            Some(custom.filename.clone())
+        }
+        _ => None,
+    }
+}
+
+impl<'a> Context<'a> {
+    /// A choice for each property of each available pattern, set with `set`
+    fn pattern_parameters(&self, default: &PatternParams) -> Vec<Parameter> {
+        let patterns = self.available_patterns();
+        let mut parameters = Vec::new();
+        for name in &patterns.pattern_names {
+            let pattern = &patterns.patterns[name];
+            for property in &pattern.properties {
+                let path = format!("pattern.{}.{}", name, property);
+                parameters.push(parameter(
+                    &path,
+                    ("set", &path),
+                    None,
+                    ParameterValue::Choice {
+                        choices: pattern.property_values[property].values.clone(),
+                        default: pattern_property(default, name, property),
+                    },
+                ));
+            }
+        }
+        parameters
+    }
+
+    /// Ranges are those of the active profile, defaults the values after startup
+    pub fn parameter_schema(&self) -> Result<Vec<Parameter>> {
+        let defaults = self
+            .config
+            .profile_defaults(self.config.profile.as_deref())?;
+        let mut profiles: Vec<_> = self.config.profiles.keys().cloned().collect();
+        profiles.sort();
+        let wavelengths = self.available_wavelengths();
+        let tilt = |name, field| {
+            parameter(
+                name,
+                ("steerBeam", field),
+                Some("mrad"),
+                ParameterValue::Number {
+                    min: None,
+                    max: None,
+                    default: 0.0,
+                },
+            )
+        };
+
+        let astigmatic = |name, field, unit| {
+            parameter(
+                name,
+                ("setAstigmaticFresnel", field),
+                Some(unit),
+                ParameterValue::Number {
+                    min: None,
+                    max: None,
+                    default: 0.0,
+                },
+            )
+        };
+
+        let mut parameters = vec![
+            parameter(
+                "fresnel",
+                ("setfresnel", "value"),
+                Some("D"),
+                ParameterValue::Integer {
+                    min: Some(0),
+                    max: self.safety().max_fresnel.map(i64::from),
+                    default: i64::from(defaults.fresnel),
+                },
+            ),
+            Parameter {
+                // follows the strongest laser that's on
+                device: Some("lasers".to_owned()),
+                ..parameter(
+                    "wavelength",
+                    ("set", "lasers.wavelength"),
+                    Some("nm"),
+                    ParameterValue::Choice {
+                        choices: wavelengths.iter().map(u32::to_string).collect(),
+                        default: Some(defaults.wavelength.to_string()),
+                    },
+                )
+            },
+            parameter(
+                "attenuation",
+                ("setattenuation", "percent"),
+                Some("%"),
+                ParameterValue::Number {
+                    min: Some(0.0),
+                    max: Some(100.0),
+                    default: 0.0,
+                },
+            ),
+            tilt("tilt_x", "tilt_x_mrad"),
+            tilt("tilt_y", "tilt_y_mrad"),
+            astigmatic("astigmatic_fresnel_x", "fresnel.power_xy.0", "D"),
+            astigmatic("astigmatic_fresnel_y", "fresnel.power_xy.1", "D"),
+            astigmatic("astigmatic_rotation", "fresnel.rotation_deg", "deg"),
+            parameter(
+                "diffraction_order",
+                ("setDiffractionOrder", "order"),
+                None,
+                ParameterValue::Choice {
+                    choices: vec![
+                        wire_name(&DiffractionOrder::MinusFirst)?,
+                        wire_name(&DiffractionOrder::PlusFirst)?,
+                    ],
+                    default: Some(wire_name(&DiffractionOrder::default())?),
+                },
+            ),
+            parameter(
+                "raw",
+                ("setpattern", "raw"),
+                None,
+                ParameterValue::Boolean { default: false },
+            ),
+            parameter(
+                "profile",
+                ("setprofile", "name"),
+                None,
+                ParameterValue::Choice {
+                    choices: profiles,
+                    default: self.config.profile.clone(),
+                },
+            ),
+            parameter(
+                "maintenance",
+                ("setMaintenanceMode", "on"),
+                None,
+                ParameterValue::Boolean { default: false },
+            ),
+        ];
+        parameters.extend(self.pattern_parameters(&defaults.pattern));
+        Ok(parameters)
+    }
+
+    pub fn send_parameter_schema(&mut self) -> Result<&mut Self> {
+        let parameters = self.parameter_schema()?;
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::ParameterSchema { parameters }),
+        })
+    }
+}
@@ -0,0 +1,70 @@
+//! Operations on custom patterns, so simple corrections can be composed on
+//! the controller instead of being downloaded, edited and uploaded again
+
+use log::info;
+
+use crate::{
+    pattern_names,
+    phase::Phase,
+    schema::PatternOperation,
+    storage::{read_image_from_file, save_npy, NPY_EXTENSION},
+    Array, Context, Result, SlmError,
+};
+
+impl<'a> Context<'a> {
+    fn read_custom_pattern(&self, name: &str) -> Result<Array> {
+        let path = self.custom_pattern_path(name)?;
+        if !path.is_file() {
+            Err(SlmError::Pattern(format!("No custom pattern {}", name)))?
+        }
+        read_image_from_file(&path, None)
+    }
+
+    fn read_custom_pair(&self, a: &str, b: &str) -> Result<(Array, Array)> {
+        let (a_pattern, b_pattern) = (self.read_custom_pattern(a)?, self.read_custom_pattern(b)?);
+        if a_pattern.dim() != b_pattern.dim() {
+            Err(SlmError::Pattern(format!(
+                "Custom patterns {} and {} differ in size: {:?} and {:?}",
+                a,
+                b,
+                a_pattern.dim(),
+                b_pattern.dim()
+            )))?
+        }
+        Ok((a_pattern, b_pattern))
+    }
+
+    /// Apply `operation` and save the result as the custom pattern `output`
+    pub fn derive_pattern(&mut self, operation: &PatternOperation, output: &str) -> Result<()> {
+        let result = match operation {
+            PatternOperation::Add { a, b } => {
+                let (a, b) = self.read_custom_pair(a, b)?;
+                a + &b
+            }
+            PatternOperation::Subtract { a, b } => {
+                let (a, b) = self.read_custom_pair(a, b)?;
+                a - &b
+            }
+            PatternOperation::Scale { pattern, factor } => {
+                self.read_custom_pattern(pattern)? * *factor
+            }
+            PatternOperation::Wrap { pattern } => {
+                self.read_custom_pattern(pattern)?.mapv(Phase::wrapped)
+            }
+            PatternOperation::Threshold {
+                pattern,
+                level,
+                low,
+                high,
+            } => self
+                .read_custom_pattern(pattern)?
+                .mapv(|e| if e >= *level { *high } else { *low }),
+        };
+
+        let path = pattern_names::with_extension(&self.custom_pattern_path(output)?, NPY_EXTENSION);
+        info!("Saving derived pattern to {:?}", path);
+        save_npy(&path, &result)?;
+        self.forget_cached(&path);
+        Ok(())
+    }
+}
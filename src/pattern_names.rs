@@ -0,0 +1,186 @@
+//! Pattern names, properties and values in file names. `_` separates them in
+//! base pattern files, so it's escaped along with anything else that isn't
+//! portable in a file name (spaces, non-ASCII, path separators), as `%` and
+//! the hex digits of each UTF-8 byte. A leading `.` is escaped too, so no name
+//! becomes `.`, `..` or a hidden file. Legacy names are plain and stay as they are.
+//! Other dots are kept, so extensions are appended with `with_extension` rather
+//! than `Path::set_extension`, which would cut a name like `ring 1.5` short.
+
+use std::path::{Path, PathBuf};
+
+fn is_plain(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'.'
+}
+
+pub fn escape(part: &str) -> String {
+    part.bytes()
+        .enumerate()
+        .map(|(i, byte)| {
+            if is_plain(byte) && !(i == 0 && byte == b'.') {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+/// Inverse of `escape`; `%` not followed by two hex digits is kept as it is
+pub fn unescape(part: &str) -> String {
+    let bytes = part.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                unescaped.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                unescaped.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// `path` with `.extension` appended to its file name
+pub fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(extension.trim_start_matches('.'));
+    path.with_file_name(file_name)
+}
+
+/// `name_property_value_..` with every part escaped
+pub fn file_stem<'p>(
+    name: &str,
+    properties: impl IntoIterator<Item = (&'p String, &'p String)>,
+) -> String {
+    properties
+        .into_iter()
+        .fold(escape(name), |stem, (property, value)| {
+            stem + "_" + &escape(property) + "_" + &escape(value)
+        })
+}
+
+/// Name and properties of a base pattern file stem, `None` if the properties
+/// and values don't pair up
+pub fn parse_file_stem(stem: &str) -> Option<(String, Vec<(String, String)>)> {
+    let mut parts = stem.split('_').map(unescape);
+    let name = parts.next()?;
+    let mut properties = Vec::new();
+    while let Some(property) = parts.next() {
+        properties.push((property, parts.next()?));
+    }
+    Some((name, properties))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn natural_names_round_trip() {
+        for name in [
+            "donut",
+            "my pattern",
+            "Ringmuster für 488",
+            "vortex_l2",
+            "100%",
+            "a/b\\c",
+            "渦",
+            "",
+            ".",
+            "..",
+            ".hidden",
+        ] {
+            let escaped = escape(name);
+            assert!(
+                escaped.bytes().all(|byte| is_plain(byte) || byte == b'%'),
+                "{} escapes to {}",
+                name,
+                escaped
+            );
+            assert_eq!(unescape(&escaped), name);
+        }
+    }
+
+    #[test]
+    fn plain_names_are_unchanged() {
+        assert_eq!(escape("donut-2.5"), "donut-2.5");
+        assert_eq!(unescape("donut-2.5"), "donut-2.5");
+        assert_eq!(escape("my pattern"), "my%20pattern");
+        assert_eq!(escape("ü"), "%C3%BC");
+    }
+
+    #[test]
+    fn leading_dots_are_escaped() {
+        assert_eq!(escape("."), "%2E");
+        assert_eq!(escape(".."), "%2E.");
+        assert_eq!(escape(".hidden"), "%2Ehidden");
+        assert_eq!(escape("a.b"), "a.b");
+    }
+
+    #[test]
+    fn stray_percent_signs_are_kept() {
+        assert_eq!(unescape("50%"), "50%");
+        assert_eq!(unescape("50%zz"), "50%zz");
+        assert_eq!(unescape("%4"), "%4");
+    }
+
+    #[test]
+    fn stems_round_trip() {
+        let properties: BTreeMap<String, String> = vec![
+            ("line width".to_owned(), "0.5".to_owned()),
+            ("order_n".to_owned(), "2".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+        let stem = file_stem("Bessel beam", &properties);
+        assert_eq!(stem, "Bessel%20beam_line%20width_0.5_order%5Fn_2");
+
+        let (name, parsed) = parse_file_stem(&stem).unwrap();
+        assert_eq!(name, "Bessel beam");
+        assert_eq!(parsed.into_iter().collect::<BTreeMap<_, _>>(), properties);
+    }
+
+    #[test]
+    fn dotted_names_keep_their_dots_with_an_extension() {
+        let path = with_extension(&Path::new("custom").join(escape("ring 1.5")), "png");
+        assert_eq!(path, Path::new("custom").join("ring%201.5.png"));
+        assert_eq!(
+            unescape(path.file_stem().unwrap().to_str().unwrap()),
+            "ring 1.5"
+        );
+
+        let properties: BTreeMap<String, String> = vec![("width".to_owned(), "0.5".to_owned())]
+            .into_iter()
+            .collect();
+        let path = with_extension(Path::new(&file_stem("ring", &properties)), ".npy");
+        assert_eq!(path, Path::new("ring_width_0.5.npy"));
+        let (name, parsed) = parse_file_stem(path.file_stem().unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(name, "ring");
+        assert_eq!(parsed.into_iter().collect::<BTreeMap<_, _>>(), properties);
+    }
+
+    #[test]
+    fn legacy_stems_are_parsed() {
+        assert_eq!(
+            parse_file_stem("donut_size_10"),
+            Some((
+                "donut".to_owned(),
+                vec![("size".to_owned(), "10".to_owned())]
+            ))
+        );
+        assert_eq!(parse_file_stem("donut"), Some(("donut".to_owned(), vec![])));
+        assert_eq!(parse_file_stem("donut_size"), None);
+    }
+}
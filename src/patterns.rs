@@ -0,0 +1,212 @@
+//! Patterns that are computed from their parameters instead of being loaded from files
+
+use std::f32::consts::PI;
+
+use crate::{
+    coordinates,
+    schema::{
+        AnnulusPattern, BinaryEncoding, ComplexEncoding, Config, KnifeEdgePattern, SpotPattern,
+        WarningCode,
+    },
+    Array, Result, SlmError, TWO_PI,
+};
+
+const BAYER_4X4: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+/// Rotate `(x, y)` counterclockwise by `degrees`
+fn rotate((x, y): (f32, f32), degrees: f32) -> (f32, f32) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    (x * cos - y * sin, x * sin + y * cos)
+}
+
+/// Smaller spots would cover no pixel at all for some positions
+const MIN_SPOT_DIAMETER: f32 = 2.0;
+
+/// Clamp the spot diameters to at least 2 pixels and at most the panel diagonal,
+/// and its center onto the panel, describing every change that was needed
+pub fn clamp_spot(spot: &mut SpotPattern, config: &Config) -> Result<Vec<(WarningCode, String)>> {
+    let mut warnings = Vec::new();
+    let (size_x, size_y) = config.screen.size;
+    let (pixel_x, pixel_y) = coordinates::pixel_size(config);
+    let min_diameter = MIN_SPOT_DIAMETER * pixel_x.max(pixel_y);
+    let max_diameter = (size_x as f32 * pixel_x).hypot(size_y as f32 * pixel_y);
+
+    let diameters = std::iter::once(&mut spot.diameter).chain(spot.minor_diameter.as_mut());
+    for diameter in diameters {
+        if !diameter.is_finite() || *diameter <= 0.0 {
+            Err(SlmError::Request(format!(
+                "Invalid spot diameter {}",
+                diameter
+            )))?
+        }
+        let clamped = diameter.max(min_diameter).min(max_diameter);
+        if clamped != *diameter {
+            warnings.push((
+                WarningCode::SpotDiameterClamped,
+                format!("Spot diameter {} clamped to {}", diameter, clamped),
+            ));
+            *diameter = clamped;
+        }
+    }
+
+    let (x, y) = spot.position_xy;
+    if !x.is_finite() || !y.is_finite() {
+        Err(SlmError::Request(format!(
+            "Invalid spot position ({}, {})",
+            x, y
+        )))?
+    }
+    let (min, max) = coordinates::bounds(config);
+    let clamped = (x.max(min.0).min(max.0), y.max(min.1).min(max.1));
+    if clamped != spot.position_xy {
+        warnings.push((
+            WarningCode::SpotPositionClamped,
+            format!(
+                "Spot position ({}, {}) is off the panel; moved to ({}, {})",
+                x, y, clamped.0, clamped.1
+            ),
+        ));
+        spot.position_xy = clamped;
+    }
+
+    Ok(warnings)
+}
+
+/// Samples per pixel side used to find how much of an edge pixel a spot covers
+const SUBSAMPLES: usize = 4;
+
+/// Gradient inside a (possibly elliptical and rotated) spot, and the background gradient outside.
+///
+/// The inner gradient is referenced to the spot center, and pixels on the edge get the
+/// phase of the coverage-weighted sum of both fields, so fractional positions move the
+/// spot smoothly instead of in whole pixels. `pixel_size` is the size of a pixel
+/// along x and y in the units of `xx` and `yy`.
+pub fn spot(spot: &SpotPattern, xx: &Array, yy: &Array, pixel_size: (f32, f32)) -> Array {
+    let semi_major = spot.diameter / 2.0;
+    let semi_minor = spot.minor_diameter.unwrap_or(spot.diameter) / 2.0;
+    let gradient_xy = rotate(spot.gradient_xy, spot.gradient_rotation_deg);
+    let (center_x, center_y) = spot.position_xy;
+
+    // squared normalized radius, 1 on the edge
+    let radius2 = |x: f32, y: f32| {
+        let (u, v) = rotate((x - center_x, y - center_y), -spot.rotation_deg);
+        (u / semi_major).powf(2.0) + (v / semi_minor).powf(2.0)
+    };
+    // pixels further than this from the edge (in normalized radius) are entirely in or out
+    let margin = pixel_size.0.max(pixel_size.1) / semi_minor.min(semi_major).max(f32::EPSILON);
+
+    Array::from_shape_fn(xx.raw_dim(), |id| {
+        let (x, y) = (xx[id], yy[id]);
+        let inside = gradient_xy.0 * (x - center_x) + gradient_xy.1 * (y - center_y);
+        let outside = spot.background_gradient_xy.0 * x + spot.background_gradient_xy.1 * y;
+
+        let radius = radius2(x, y).sqrt();
+        let coverage = if radius < 1.0 - margin {
+            1.0
+        } else if radius > 1.0 + margin {
+            0.0
+        } else {
+            let offset = |i: usize| (i as f32 + 0.5) / SUBSAMPLES as f32 - 0.5;
+            let covered = (0..SUBSAMPLES * SUBSAMPLES)
+                .filter(|i| {
+                    let dx = offset(i % SUBSAMPLES) * pixel_size.0;
+                    let dy = offset(i / SUBSAMPLES) * pixel_size.1;
+                    radius2(x + dx, y + dy) < 1.0
+                })
+                .count();
+            covered as f32 / (SUBSAMPLES * SUBSAMPLES) as f32
+        };
+
+        if coverage == 1.0 {
+            inside
+        } else if coverage == 0.0 {
+            outside
+        } else {
+            let re = coverage * inside.cos() + (1.0 - coverage) * outside.cos();
+            let im = coverage * inside.sin() + (1.0 - coverage) * outside.sin();
+            im.atan2(re)
+        }
+    })
+}
+
+/// Gradient inside the ring, and the background gradient in the hole and outside
+pub fn annulus(annulus: &AnnulusPattern, xx: &Array, yy: &Array) -> Array {
+    let inner_r2 = (annulus.inner_diameter / 2.0).powf(2.0);
+    let outer_r2 = (annulus.outer_diameter / 2.0).powf(2.0);
+
+    Array::from_shape_fn(xx.raw_dim(), |id| {
+        let r2 =
+            (xx[id] - annulus.position_xy.0).powf(2.0) + (yy[id] - annulus.position_xy.1).powf(2.0);
+        let gradient_xy = if inner_r2 <= r2 && r2 < outer_r2 {
+            annulus.gradient_xy
+        } else {
+            annulus.background_gradient_xy
+        };
+        gradient_xy.0 * xx[id] + gradient_xy.1 * yy[id]
+    })
+}
+
+/// Phase step across a straight edge
+pub fn knife_edge(knife_edge: &KnifeEdgePattern, xx: &Array, yy: &Array) -> Array {
+    let (sin, cos) = knife_edge.orientation_deg.to_radians().sin_cos();
+
+    Array::from_shape_fn(xx.raw_dim(), |id| {
+        let (dx, dy) = (
+            xx[id] - knife_edge.position_xy.0,
+            yy[id] - knife_edge.position_xy.1,
+        );
+        // cross product with the edge direction, positive to the left of the edge
+        if cos * dy - sin * dx > 0.0 {
+            knife_edge.phase_step
+        } else {
+            0.0
+        }
+    })
+}
+
+/// Phase pattern approximating the complex field `amplitude * exp(i * phase)`;
+/// the amplitude is normalized to its maximum
+pub fn encode_complex(amplitude: &Array, phase: &Array, encoding: ComplexEncoding) -> Array {
+    match encoding {
+        ComplexEncoding::PhaseOnly => phase.clone(),
+        ComplexEncoding::DoublePhase => {
+            let max = amplitude.fold(0.0_f32, |max, &e| max.max(e.abs()));
+            Array::from_shape_fn(phase.raw_dim(), |(x, y)| {
+                let normalized = if max > 0.0 {
+                    amplitude[(x, y)].abs() / max
+                } else {
+                    0.0
+                };
+                // exp(i(phase + offset)) + exp(i(phase - offset)) = 2 cos(offset) exp(i phase)
+                let offset = normalized.acos();
+                if (x + y) % 2 == 0 {
+                    phase[(x, y)] + offset
+                } else {
+                    phase[(x, y)] - offset
+                }
+            })
+        }
+    }
+}
+
+/// Encode a phase pattern as a binary amplitude hologram (0 or 255);
+/// `depth` scales the fraction of switched on pixels like it scales the phase depth
+pub fn binarize(phase: &Array, encoding: &BinaryEncoding, depth: f32) -> ndarray::Array2<u8> {
+    let on = |on: bool| if on { u8::MAX } else { 0 };
+
+    match encoding {
+        BinaryEncoding::Threshold { duty } => {
+            let half_width = PI * duty * depth;
+            phase.mapv(|e| on(((e + PI).rem_euclid(TWO_PI) - PI).abs() < half_width))
+        }
+        BinaryEncoding::Ordered => ndarray::Array2::from_shape_fn(phase.raw_dim(), |(x, y)| {
+            let intensity = depth * (1.0 + phase[(x, y)].cos()) / 2.0;
+            on(intensity > (BAYER_4X4[x % 4][y % 4] + 0.5) / 16.0)
+        }),
+    }
+}
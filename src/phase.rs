@@ -0,0 +1,265 @@
+//! Wrapping, quantization and normalization of phases, with the conventions
+//! every part of the controller shares:
+//!
+//! - a wrapped phase is in `[0, 2π)`; rounding never leaves it at 2π itself
+//! - with `levels` gray levels per wave, level `g` shows the phase
+//!   `g * 2π / levels`, and a phase shows as the level at or below it
+//! - a value within `LEVEL_TOLERANCE` below a level counts as that level, so
+//!   a level converted to a phase and back stays the same level
+//! - values that land on a whole wave show as level 0
+//! - levels above 255 (a scale factor above 255) saturate at 255
+//! - images hold `IMAGE_LEVELS` levels per wave
+//!
+//! The layers of a pattern are summed in a `Phase` type: f32 is faster, f64
+//! keeps many layers with large phases (Zernikes, corrections, holograms, a
+//! strong fresnel) from rounding into visible banding
+
+use ndarray::NdFloat;
+
+/// Gray levels per wave in image files
+pub const IMAGE_LEVELS: f32 = 256.0;
+
+/// Part of a level a value may fall short of it by rounding
+const LEVEL_TOLERANCE: f32 = 1e-3;
+
+pub trait Phase: NdFloat {
+    const TWO_PI: Self;
+
+    fn from_f32(value: f32) -> Self;
+    fn from_f64(value: f64) -> Self;
+    fn as_f32(self) -> f32;
+    /// Phase wrapped to one wave, from 0 to 2π
+    fn wrapped(self) -> Self;
+}
+
+impl Phase for f32 {
+    const TWO_PI: Self = crate::TWO_PI;
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn as_f32(self) -> f32 {
+        self
+    }
+
+    fn wrapped(self) -> Self {
+        // a tiny negative phase wraps to 2π by rounding
+        match self.rem_euclid(Self::TWO_PI) {
+            wrapped if wrapped < Self::TWO_PI => wrapped,
+            _ => 0.0,
+        }
+    }
+}
+
+impl Phase for f64 {
+    const TWO_PI: Self = std::f64::consts::PI * 2.0;
+
+    fn from_f32(value: f32) -> Self {
+        f64::from(value)
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn as_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn wrapped(self) -> Self {
+        match self.rem_euclid(Self::TWO_PI) {
+            wrapped if wrapped < Self::TWO_PI => wrapped,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Gray level of a fractional `level`, wrapped to `levels` levels per wave
+pub fn wrap_level(level: f32, levels: f32) -> u8 {
+    let level = level.rem_euclid(levels) + LEVEL_TOLERANCE;
+    let level = if level < levels {
+        level
+    } else {
+        level - levels
+    };
+    level.min(f32::from(u8::MAX)) as u8
+}
+
+/// Gray level showing `phase` with `levels` levels per wave
+pub fn quantize<T: Phase>(phase: T, levels: T) -> u8 {
+    wrap_level(
+        (phase.wrapped() / T::TWO_PI * levels).as_f32(),
+        levels.as_f32(),
+    )
+}
+
+/// Phase shown by the gray level `level` with `levels` levels per wave
+pub fn level_phase(level: u8, levels: f32) -> f32 {
+    f32::from(level) / levels * crate::TWO_PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVELS: [f32; 7] = [2.0, 100.0, 212.5, 255.0, 256.0, 300.0, 1000.0];
+
+    #[test]
+    fn wrapped_is_within_a_wave() {
+        for &phase in &[-1e-9, -1e-6, -0.5, -100.0, 0.0, 1e-9, 3.0, 7.0, 1e4] {
+            let wrapped = Phase::wrapped(phase as f32);
+            assert!(
+                (0.0..f32::TWO_PI).contains(&wrapped),
+                "{} -> {}",
+                phase,
+                wrapped
+            );
+            let wrapped = Phase::wrapped(phase);
+            assert!(
+                (0.0..f64::TWO_PI).contains(&wrapped),
+                "{} -> {}",
+                phase,
+                wrapped
+            );
+        }
+    }
+
+    #[test]
+    fn negative_phases_wrap_from_the_top() {
+        assert!((Phase::wrapped(-0.5f32) - (f32::TWO_PI - 0.5)).abs() < 1e-6);
+        assert!((Phase::wrapped(-0.5f64) - (f64::TWO_PI - 0.5)).abs() < 1e-12);
+        assert_eq!(Phase::wrapped(-1e-9f32), 0.0);
+        assert_eq!(Phase::wrapped(-1e-20f64), 0.0);
+    }
+
+    #[test]
+    fn whole_waves_wrap_to_zero() {
+        for waves in -5..=5 {
+            // a whole wave rounded in f32 may wrap to just below 2π
+            let wrapped = Phase::wrapped(waves as f32 * f32::TWO_PI);
+            assert!(
+                wrapped.min(f32::TWO_PI - wrapped) < 1e-5,
+                "{} waves -> {}",
+                waves,
+                wrapped
+            );
+            let wrapped = Phase::wrapped(waves as f64 * f64::TWO_PI);
+            assert!(
+                wrapped.min(f64::TWO_PI - wrapped) < 1e-12,
+                "{} waves -> {}",
+                waves,
+                wrapped
+            );
+            for &levels in &LEVELS {
+                assert_eq!(quantize(waves as f32 * f32::TWO_PI, levels), 0);
+                assert_eq!(quantize(waves as f64 * f64::TWO_PI, f64::from(levels)), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn phases_just_short_of_a_wave_show_as_zero() {
+        for &levels in &LEVELS {
+            assert_eq!(quantize(-1e-9f32, levels), 0);
+            assert_eq!(quantize(-1e-12f64, f64::from(levels)), 0);
+            assert_eq!(quantize(f32::TWO_PI - 1e-7, levels), 0);
+        }
+    }
+
+    #[test]
+    fn levels_round_trip() {
+        for &levels in &LEVELS {
+            let top = (levels.ceil() as u32).min(256);
+            for level in 0..top {
+                let level = level as u8;
+                let phase = level_phase(level, levels);
+                assert_eq!(quantize(phase, levels), level, "{} of {}", level, levels);
+                assert_eq!(
+                    quantize(f64::from(phase), f64::from(levels)),
+                    level,
+                    "{} of {} in f64",
+                    level,
+                    levels
+                );
+                // the same level for any phase up to the next one
+                let below_next = level_phase(level, levels) + 0.9 * f32::TWO_PI / levels;
+                if f32::from(level) + 1.0 < levels {
+                    assert_eq!(
+                        quantize(below_next, levels),
+                        level,
+                        "{} of {}",
+                        level,
+                        levels
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn levels_shifted_by_a_wave_are_unchanged() {
+        for &levels in &LEVELS {
+            let top = (levels.ceil() as u32).min(256);
+            for level in 0..top {
+                let phase = level_phase(level as u8, levels);
+                for waves in &[-3.0, -1.0, 1.0, 3.0] {
+                    assert_eq!(
+                        quantize(f64::from(phase) + waves * f64::TWO_PI, f64::from(levels)),
+                        level as u8,
+                        "{} of {} shifted by {} waves",
+                        level,
+                        levels,
+                        waves
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn negative_phases_quantize_from_the_top() {
+        assert_eq!(quantize(-std::f32::consts::PI, 256.0), 128);
+        assert_eq!(quantize(-f32::TWO_PI / 256.0, 256.0), 255);
+        assert_eq!(quantize(-f32::TWO_PI / 256.0 * 1.5, 256.0), 254);
+        assert_eq!(quantize(-f64::TWO_PI / 100.0, 100.0), 99);
+    }
+
+    #[test]
+    fn scales_above_255_saturate() {
+        assert_eq!(quantize(std::f32::consts::PI, 300.0), 150);
+        assert_eq!(quantize(f32::TWO_PI * 255.0 / 300.0, 300.0), 255);
+        assert_eq!(quantize(f32::TWO_PI * 256.0 / 300.0, 300.0), 255);
+        assert_eq!(quantize(f32::TWO_PI * 299.0 / 300.0, 300.0), 255);
+        assert_eq!(quantize(f64::TWO_PI * 0.999, 1000.0), 255);
+        assert_eq!(wrap_level(299.5, 300.0), 255);
+    }
+
+    #[test]
+    fn shifted_levels_wrap() {
+        assert_eq!(wrap_level(-1.0, 256.0), 255);
+        assert_eq!(wrap_level(256.0, 256.0), 0);
+        assert_eq!(wrap_level(257.5, 256.0), 1);
+        assert_eq!(wrap_level(212.5, 212.5), 0);
+        assert_eq!(wrap_level(-0.0001, 212.5), 0);
+        assert_eq!(wrap_level(211.9999, 212.5), 212);
+        assert_eq!(wrap_level(5.0 - 1e-5, 100.0), 5);
+    }
+
+    #[test]
+    fn image_levels_span_a_wave() {
+        assert_eq!(level_phase(0, IMAGE_LEVELS), 0.0);
+        assert_eq!(level_phase(128, IMAGE_LEVELS), std::f32::consts::PI);
+        assert!(level_phase(255, IMAGE_LEVELS) < f32::TWO_PI);
+        for level in 0..=u8::MAX {
+            assert_eq!(
+                quantize(level_phase(level, IMAGE_LEVELS), IMAGE_LEVELS),
+                level
+            );
+        }
+    }
+}
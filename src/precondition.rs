@@ -0,0 +1,41 @@
+//! Preconditions on commands, so two clients don't clobber each other's changes
+
+use crate::{
+    schema::{PatternParams, Precondition},
+    Context, Result, SlmError,
+};
+
+/// FNV-1a of the pattern parameters as JSON; the keys of `serde_json::Value`
+/// are sorted, so equal parameters always hash the same
+pub fn pattern_hash(pattern: &PatternParams) -> Result<String> {
+    let encoded = serde_json::to_vec(&serde_json::to_value(pattern)?)?;
+    let hash = encoded
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+    Ok(format!("{:016x}", hash))
+}
+
+impl<'a> Context<'a> {
+    pub fn check_precondition(&self, expect: &Precondition) -> Result<()> {
+        if let Some(wavelength) = expect.wavelength {
+            if wavelength != self.state.wavelength {
+                Err(SlmError::Conflict(format!(
+                    "Expected wavelength {}, but it is {}",
+                    wavelength, self.state.wavelength
+                )))?
+            }
+        }
+        if let Some(expected) = &expect.pattern_hash {
+            let hash = pattern_hash(&self.state.pattern_params)?;
+            if *expected != hash {
+                Err(SlmError::Conflict(format!(
+                    "Expected pattern {}, but it is {}",
+                    expected, hash
+                )))?
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! Dead man's switch for `PreStack`: if the acquisition sends nothing after it
+//! (e.g. because it crashed), the live state is restored instead of leaving
+//! the stack pattern up
+
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::{batch::Snapshot, Context, Result};
+
+pub struct Prestack {
+    since: Instant,
+    /// State restored on timeout
+    previous: Snapshot,
+}
+
+impl<'a> Context<'a> {
+    /// Start the timeout; repeated `PreStack`s keep the state from before the first
+    pub fn arm_prestack(&mut self) {
+        if self.config.prestack_timeout_secs.is_none() {
+            return;
+        }
+        let previous = match self.state.prestack.take() {
+            Some(prestack) => prestack.previous,
+            None => self.snapshot(),
+        };
+        self.state.prestack = Some(Prestack {
+            since: Instant::now(),
+            previous,
+        });
+    }
+
+    /// A follow-up command arrived, the stack pattern stays
+    pub fn disarm_prestack(&mut self) {
+        self.state.prestack = None;
+    }
+
+    pub fn poll_prestack(&mut self) -> Result<()> {
+        let timeout = match self.config.prestack_timeout_secs {
+            Some(timeout) => Duration::from_secs(timeout),
+            None => return Ok(()),
+        };
+        let prestack = match self.state.prestack.take() {
+            Some(prestack) if prestack.since.elapsed() >= timeout => prestack,
+            prestack => {
+                self.state.prestack = prestack;
+                return Ok(());
+            }
+        };
+
+        info!("No command after PreStack; restoring the previous state");
+        self.restore(prestack.previous);
+        self.state.last_modified_by = Some("prestackTimeout".to_owned());
+        self.update_state(None, None, None)?
+            .send_current_state()?
+            .send_warning(format!(
+                "No command within {} s after PreStack; restored the previous state",
+                timeout.as_secs()
+            ))?;
+        self.journal("prestackTimeout", "PreStack");
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! Probe pattern sequences for aberration measurements, so the camera-side
+//! calibration tool only has to follow the published probe indices
+
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::{
+    phase::wrap_level,
+    schema::{AimCommand, Message, MessageData, MessageType, ProbeSequence, ResponseCode},
+    Context, Result, SlmError, TWO_PI,
+};
+
+pub struct ProbeRun {
+    sequence: ProbeSequence,
+    interval: Option<Duration>,
+    index: u32,
+    shown_at: Instant,
+    /// Pattern the probes are added to, restored once the sequence is over
+    base: ndarray::Array2<u8>,
+    /// Gray level corresponding to a phase of 2 pi
+    scale: f32,
+}
+
+fn probe_count(sequence: &ProbeSequence) -> u32 {
+    match sequence {
+        ProbeSequence::PhaseSteps { steps } => *steps,
+        ProbeSequence::SubApertures { grid_xy, .. } => grid_xy.0 * grid_xy.1,
+    }
+}
+
+/// Phase in radians added to pixel `(x, y)` by the probe with the given index
+fn probe_phase(
+    sequence: &ProbeSequence,
+    index: u32,
+    (size_x, size_y): (usize, usize),
+    (x, y): (usize, usize),
+) -> f32 {
+    match sequence {
+        ProbeSequence::PhaseSteps { steps } => TWO_PI * index as f32 / *steps as f32,
+        ProbeSequence::SubApertures { grid_xy, tilt_xy } => {
+            let (cell_x, cell_y) = (index % grid_xy.0, index / grid_xy.0);
+            let in_cell = x * grid_xy.0 as usize / size_x == cell_x as usize
+                && y * grid_xy.1 as usize / size_y == cell_y as usize;
+            if in_cell {
+                tilt_xy.0 * x as f32 + tilt_xy.1 * y as f32
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+impl<'a> Context<'a> {
+    fn send_probe(&mut self, index: u32, total: u32) -> Result<&mut Self> {
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Probe { index, total }),
+        })
+    }
+
+    fn show_probe(&mut self, run: &mut ProbeRun) -> Result<()> {
+        let dim = run.base.dim();
+        let pattern = ndarray::Array2::from_shape_fn(dim, |id| {
+            let offset = probe_phase(&run.sequence, run.index, dim, id) / TWO_PI * run.scale;
+            wrap_level(f32::from(run.base[id]) + offset, run.scale)
+        });
+        self.put_pattern(&pattern)?;
+        run.shown_at = Instant::now();
+        self.checkpoint(run.index);
+        self.send_probe(run.index, probe_count(&run.sequence))?;
+        Ok(())
+    }
+
+    /// `first` is the index of the first probe shown, when resuming
+    pub fn start_probe_sequence(
+        &mut self,
+        sequence: ProbeSequence,
+        interval_ms: Option<u64>,
+        first: u32,
+    ) -> Result<&mut Self> {
+        if self.config.compute_pattern.binary.is_some() {
+            Err(SlmError::Request(
+                "Probe sequences need a phase panel, not binary output".to_owned(),
+            ))?
+        }
+        let total = probe_count(&sequence);
+        if total == 0 {
+            Err(SlmError::Request("Empty probe sequence".to_owned()))?
+        }
+        if first >= total {
+            Err(SlmError::Request(format!(
+                "Probe sequence has no probe {}",
+                first
+            )))?
+        }
+        self.stop_probe_sequence()?;
+        self.begin_checkpoint(
+            AimCommand::StartProbeSequence {
+                sequence: sequence.clone(),
+                interval_ms,
+            },
+            total,
+        );
+
+        info!("Starting probe sequence {:?}", sequence);
+        let mut run = ProbeRun {
+            sequence,
+            interval: interval_ms.map(Duration::from_millis),
+            index: first,
+            shown_at: Instant::now(),
+            base: self.compute_pattern()?,
+            scale: self.scale_factor(self.state.wavelength)?,
+        };
+        self.show_probe(&mut run)?;
+        self.state.probe_run = Some(run);
+        Ok(self)
+    }
+
+    pub fn next_probe(&mut self) -> Result<&mut Self> {
+        let mut run = match self.state.probe_run.take() {
+            Some(run) => run,
+            None => Err(SlmError::Request("No probe sequence running".to_owned()))?,
+        };
+
+        run.index += 1;
+        if run.index < probe_count(&run.sequence) {
+            self.show_probe(&mut run)?;
+            self.state.probe_run = Some(run);
+            Ok(self)
+        } else {
+            info!("Probe sequence done");
+            self.clear_checkpoint();
+            self.put_pattern(&run.base)?;
+            self.send_response(ResponseCode::ProbeSequenceDone)
+        }
+    }
+
+    /// Restore the pattern shown before the sequence, if one is running
+    pub fn stop_probe_sequence(&mut self) -> Result<&mut Self> {
+        if let Some(run) = self.state.probe_run.take() {
+            info!("Stopping probe sequence at probe {}", run.index);
+            self.clear_checkpoint();
+            self.put_pattern(&run.base)?;
+        }
+        Ok(self)
+    }
+
+    /// Advance timed probe sequences
+    pub fn poll_probe_sequence(&mut self) -> Result<()> {
+        let due = match &self.state.probe_run {
+            Some(ProbeRun {
+                interval: Some(interval),
+                shown_at,
+                ..
+            }) => shown_at.elapsed() >= *interval,
+            _ => false,
+        };
+        if due {
+            self.next_probe()?;
+        }
+        Ok(())
+    }
+}
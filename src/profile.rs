@@ -0,0 +1,70 @@
+//! Named config profiles (e.g. "tweezers", "SIM", "service"), switchable at runtime
+
+use log::info;
+
+use crate::{
+    schema::{BlazeConfig, Config, DefaultState, Profile, SafetyConfig},
+    Context, Result, SlmError,
+};
+
+impl Config {
+    pub fn get_profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| SlmError::Config(format!("Profile {} is not defined", name)))
+    }
+
+    /// Defaults of the profile, falling back to the top-level ones
+    pub fn profile_defaults(&self, profile: Option<&str>) -> Result<&DefaultState> {
+        Ok(match profile {
+            Some(name) => self.get_profile(name)?.defaults.as_ref(),
+            None => None,
+        }
+        .unwrap_or(&self.defaults))
+    }
+}
+
+impl<'a> Context<'a> {
+    fn active_profile(&self) -> Option<&Profile> {
+        self.state
+            .profile
+            .as_ref()
+            .and_then(|name| self.config.profiles.get(name))
+    }
+
+    pub fn blaze(&self) -> &BlazeConfig {
+        self.active_profile()
+            .and_then(|profile| profile.blaze.as_ref())
+            .unwrap_or(&self.config.blaze)
+    }
+
+    pub fn safety(&self) -> &SafetyConfig {
+        self.active_profile()
+            .and_then(|profile| profile.safety.as_ref())
+            .unwrap_or(&self.config.safety)
+    }
+
+    /// Switch to the profile and apply its defaults, if it has any; the
+    /// previous profile stays active if they can't be applied
+    pub fn set_profile(&mut self, name: String) -> Result<&mut Self> {
+        let defaults = self.config.get_profile(&name)?.defaults.clone();
+        info!("Switching to profile {}", name);
+        let previous = self.state.profile.replace(name);
+
+        let updated = match defaults {
+            Some(defaults) => self.update_state(
+                Some(defaults.pattern),
+                Some(defaults.fresnel),
+                Some(defaults.wavelength),
+            ),
+            // The blaze or safety settings may have changed
+            None => self.update_state(None, None, None),
+        }
+        .map(|_| ());
+        if let Err(err) = updated {
+            self.state.profile = previous;
+            Err(err)?
+        }
+        Ok(self)
+    }
+}
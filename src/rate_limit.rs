@@ -0,0 +1,75 @@
+//! Per-command rate limits, so a client flooding the broker with commands
+//! can't keep the message loop from catching up
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+struct Bucket {
+    tokens: f32,
+    last_refill: Instant,
+    dropped: u32,
+}
+
+pub enum Admission {
+    Accept,
+    /// Accepted, but `dropped` commands were dropped since the last accepted one
+    AcceptAfterDropping(u32),
+    /// Over the limit; `first` is set for the first drop of a burst
+    Drop {
+        first: bool,
+    },
+}
+
+pub struct RateLimiter {
+    /// Maximum commands per second, by command name
+    limits: HashMap<String, f32>,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: HashMap<String, f32>) -> Self {
+        RateLimiter {
+            limits,
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn limit(&self, command: &str) -> Option<f32> {
+        self.limits.get(command).copied()
+    }
+
+    /// Token bucket holding up to one second worth of commands
+    pub fn admit(&mut self, command: &str) -> Admission {
+        let rate = match self.limit(command) {
+            Some(rate) => rate,
+            None => return Admission::Accept,
+        };
+        let capacity = rate.max(1.0);
+
+        let bucket = self
+            .buckets
+            .entry(command.to_owned())
+            .or_insert_with(|| Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+                dropped: 0,
+            });
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f32();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            match std::mem::replace(&mut bucket.dropped, 0) {
+                0 => Admission::Accept,
+                dropped => Admission::AcceptAfterDropping(dropped),
+            }
+        } else {
+            bucket.dropped += 1;
+            Admission::Drop {
+                first: bucket.dropped == 1,
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Frames streamed by external hologram engines as raw pixels on their own
+//! topic, without JSON or base64, so they can be shown at tens of Hz. They are
+//! shown without any corrections, and the aim state isn't changed.
+//! Permissions name them `rawFrame`.
+
+use log::info;
+use mqtt::Message as MqttMessage;
+
+use crate::{util::Subtopic, Context, Result, SlmError};
+
+impl<'a> Context<'a> {
+    fn raw_frames_topic(&self) -> Option<String> {
+        let raw_frames = self.config.raw_frames.as_ref()?;
+        Some(self.config.main_topic().subtopic(&raw_frames.subtopic))
+    }
+
+    pub fn subscribe_raw_frames(&mut self) -> Result<()> {
+        if let Some(topic) = self.raw_frames_topic() {
+            self.client.subscribe(&topic, 0)?;
+            info!("Subscribed to {}", topic);
+        }
+        Ok(())
+    }
+
+    pub fn is_raw_frame(&self, mqtt_message: &MqttMessage) -> bool {
+        self.raw_frames_topic()
+            .is_some_and(|topic| mqtt_message.topic() == topic)
+    }
+
+    /// Raw frames carry no client id, so while a client holds control or
+    /// during maintenance only frames on an override subtopic are shown
+    pub fn process_raw_frame(&mut self, topic: &str, payload: &[u8]) -> Result<()> {
+        if !self.config.is_permitted("rawFrame", topic) {
+            Err(SlmError::Request(format!(
+                "Command rawFrame is not permitted on {}",
+                topic
+            )))?
+        }
+        if !self.config.overrides_control(topic) {
+            if self.state.maintenance {
+                info!("Maintenance mode; not showing the raw frame");
+                return Ok(());
+            }
+            if let Some(holder) = &self.state.controlled_by {
+                Err(SlmError::Conflict(format!(
+                    "{} holds control; raw frame rejected",
+                    holder
+                )))?
+            }
+        }
+        self.show_raw_frame(payload)
+    }
+
+    fn show_raw_frame(&mut self, payload: &[u8]) -> Result<()> {
+        let (size_x, size_y) = self.config.screen.size;
+        let (size_x, size_y) = (size_x as usize, size_y as usize);
+        let pixels = size_x * size_y;
+        let frame = if payload.len() == pixels {
+            ndarray::Array2::from_shape_fn((size_x, size_y), |(x, y)| payload[y * size_x + x])
+        } else if payload.len() == 2 * pixels {
+            // the high byte of each little-endian pixel
+            ndarray::Array2::from_shape_fn((size_x, size_y), |(x, y)| {
+                payload[2 * (y * size_x + x) + 1]
+            })
+        } else {
+            Err(SlmError::Request(format!(
+                "Raw frame of {} bytes doesn't fit the {}x{} screen; expected {} or {} bytes",
+                payload.len(),
+                size_x,
+                size_y,
+                pixels,
+                2 * pixels
+            )))?
+        };
+        self.put_pattern(&frame)
+    }
+}
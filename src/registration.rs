@@ -0,0 +1,487 @@
+//! Mapping from camera pixels to SLM coordinates, so clients can position
+//! patterns where they see them in the camera image
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use log::info;
+
+use crate::{
+    schema::{
+        AimCommand, Message, MessageData, MessageType, PatternParams, PointPair, Registration,
+        RegistrationSource, Space,
+    },
+    Context, Result, SlmError,
+};
+
+/// The registration is kept here, so it survives restarts
+const REGISTRATION_FILE: &str = "registration.json";
+
+type Matrix = [[f64; 3]; 3];
+
+pub fn load() -> Result<Option<Registration>> {
+    let path = Path::new(REGISTRATION_FILE);
+    Ok(if path.is_file() {
+        Some(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    } else {
+        None
+    })
+}
+
+fn save(registration: Option<&Registration>) -> Result<()> {
+    match registration {
+        Some(registration) => serde_json::to_writer_pretty(
+            BufWriter::new(File::create(REGISTRATION_FILE)?),
+            registration,
+        )?,
+        None if Path::new(REGISTRATION_FILE).is_file() => fs::remove_file(REGISTRATION_FILE)?,
+        None => (),
+    }
+    Ok(())
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 3]; 3];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, e) in row.iter_mut().enumerate() {
+            *e = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+fn determinant(m: &Matrix) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// Map a point through the homography
+pub fn apply(matrix: &Matrix, (x, y): (f32, f32)) -> Result<(f32, f32)> {
+    let (x, y) = (x as f64, y as f64);
+    let [u, v, w] = [0, 1, 2].map(|i| matrix[i][0] * x + matrix[i][1] * y + matrix[i][2]);
+    if w.abs() < f64::EPSILON {
+        Err(SlmError::Request(format!(
+            "Point ({}, {}) maps to infinity",
+            x, y
+        )))?
+    }
+    Ok(((u / w) as f32, (v / w) as f32))
+}
+
+/// Derivatives of the mapped point by the original coordinates, `[[dx/dx, dx/dy], [dy/dx, dy/dy]]`
+fn jacobian(matrix: &Matrix, (x, y): (f32, f32)) -> Result<[[f32; 2]; 2]> {
+    let (mx, my) = apply(matrix, (x, y))?;
+    let w = matrix[2][0] * x as f64 + matrix[2][1] * y as f64 + matrix[2][2];
+    let d = |row: usize, mapped: f32, column: usize| {
+        ((matrix[row][column] - mapped as f64 * matrix[2][column]) / w) as f32
+    };
+    Ok([[d(0, mx, 0), d(0, mx, 1)], [d(1, my, 0), d(1, my, 1)]])
+}
+
+/// Similarity transform moving the centroid of the points to the origin and
+/// their mean distance from it to sqrt(2), for better conditioned fits
+fn normalization(points: &[(f32, f32)]) -> Result<(Matrix, Matrix)> {
+    let count = points.len() as f64;
+    let center_x = points.iter().map(|p| p.0 as f64).sum::<f64>() / count;
+    let center_y = points.iter().map(|p| p.1 as f64).sum::<f64>() / count;
+    let distance = points
+        .iter()
+        .map(|p| (p.0 as f64 - center_x).hypot(p.1 as f64 - center_y))
+        .sum::<f64>()
+        / count;
+    if distance < f64::EPSILON {
+        Err(SlmError::Request(
+            "Registration points all coincide".to_owned(),
+        ))?
+    }
+    let scale = 2.0_f64.sqrt() / distance;
+    Ok((
+        [
+            [scale, 0.0, -scale * center_x],
+            [0.0, scale, -scale * center_y],
+            [0.0, 0.0, 1.0],
+        ],
+        [
+            [1.0 / scale, 0.0, center_x],
+            [0.0, 1.0 / scale, center_y],
+            [0.0, 0.0, 1.0],
+        ],
+    ))
+}
+
+/// Solve the square system `a * x = b` by Gaussian elimination with partial pivoting,
+/// `None` if it is singular
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for column in 0..n {
+        let pivot =
+            (column..n).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+        if a[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(column, pivot);
+        b.swap(column, pivot);
+        let (above, below) = a.split_at_mut(column + 1);
+        let pivot_row = &above[column];
+        for (offset, lower_row) in below.iter_mut().enumerate() {
+            let factor = lower_row[column] / pivot_row[column];
+            for (value, pivot_value) in lower_row.iter_mut().zip(pivot_row).skip(column) {
+                *value -= factor * pivot_value;
+            }
+            b[column + 1 + offset] -= factor * b[column];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Least-squares affine transform or homography mapping the camera points onto the SLM ones
+pub fn fit(pairs: &[PointPair], homography: bool) -> Result<Registration> {
+    let needed = if homography { 4 } else { 3 };
+    if pairs.len() < needed {
+        Err(SlmError::Request(format!(
+            "Fitting needs at least {} point pairs, got {}",
+            needed,
+            pairs.len()
+        )))?
+    }
+    let camera: Vec<_> = pairs.iter().map(|pair| pair.camera_xy).collect();
+    let slm: Vec<_> = pairs.iter().map(|pair| pair.slm_xy).collect();
+    let (to_camera, _) = normalization(&camera)?;
+    let (to_slm, from_slm) = normalization(&slm)?;
+
+    // rows of the linear system for the unknowns h00..h12 (and h20, h21 for homographies)
+    let unknowns = if homography { 8 } else { 6 };
+    let mut rows: Vec<(Vec<f64>, f64)> = Vec::new();
+    for pair in pairs {
+        let [x, y, _] = [0, 1, 2].map(|i| {
+            to_camera[i][0] * pair.camera_xy.0 as f64
+                + to_camera[i][1] * pair.camera_xy.1 as f64
+                + to_camera[i][2]
+        });
+        let [u, v, _] = [0, 1, 2].map(|i| {
+            to_slm[i][0] * pair.slm_xy.0 as f64 + to_slm[i][1] * pair.slm_xy.1 as f64 + to_slm[i][2]
+        });
+        let mut row_u = vec![x, y, 1.0, 0.0, 0.0, 0.0];
+        let mut row_v = vec![0.0, 0.0, 0.0, x, y, 1.0];
+        if homography {
+            row_u.extend(&[-x * u, -y * u]);
+            row_v.extend(&[-x * v, -y * v]);
+        }
+        rows.push((row_u, u));
+        rows.push((row_v, v));
+    }
+
+    // normal equations
+    let mut ata = vec![vec![0.0; unknowns]; unknowns];
+    let mut atb = vec![0.0; unknowns];
+    for (row, rhs) in &rows {
+        for i in 0..unknowns {
+            for j in 0..unknowns {
+                ata[i][j] += row[i] * row[j];
+            }
+            atb[i] += row[i] * rhs;
+        }
+    }
+    let h = solve(ata, atb).ok_or_else(|| {
+        SlmError::Request("Registration points are degenerate (e.g. collinear)".to_owned())
+    })?;
+    let last_row = if homography {
+        [h[6], h[7], 1.0]
+    } else {
+        [0.0, 0.0, 1.0]
+    };
+    let normalized = [[h[0], h[1], h[2]], [h[3], h[4], h[5]], last_row];
+    let mut matrix = multiply(&from_slm, &multiply(&normalized, &to_camera));
+    let w = matrix[2][2];
+    for e in matrix.iter_mut().flatten() {
+        *e /= w;
+    }
+
+    let residuals = pairs
+        .iter()
+        .map(|pair| {
+            let (x, y) = apply(&matrix, pair.camera_xy)?;
+            Ok((x - pair.slm_xy.0).hypot(y - pair.slm_xy.1))
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    let rms = (residuals.iter().map(|r| r * r).sum::<f32>() / residuals.len() as f32).sqrt();
+    let max = residuals.iter().cloned().fold(0.0, f32::max);
+
+    Ok(Registration {
+        matrix,
+        rms_residual: Some(rms),
+        max_residual: Some(max),
+    })
+}
+
+/// Map a direction, given as a counterclockwise angle in degrees, through the local Jacobian
+fn map_angle(jacobian: &[[f32; 2]; 2], degrees: f32) -> f32 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let x = jacobian[0][0] * cos + jacobian[0][1] * sin;
+    let y = jacobian[1][0] * cos + jacobian[1][1] * sin;
+    y.atan2(x).to_degrees()
+}
+
+/// Map a pattern given in camera pixels into SLM coordinates; sizes are scaled by the
+/// local magnification and angles follow the local rotation, gradients are kept
+fn map_pattern(matrix: &Matrix, pattern: PatternParams) -> Result<PatternParams> {
+    Ok(match pattern {
+        PatternParams::Spot { mut spot } => {
+            let jacobian = jacobian(matrix, spot.position_xy)?;
+            let scale = local_scale(&jacobian);
+            spot.position_xy = apply(matrix, spot.position_xy)?;
+            spot.diameter *= scale;
+            spot.minor_diameter = spot.minor_diameter.map(|diameter| diameter * scale);
+            spot.rotation_deg = map_angle(&jacobian, spot.rotation_deg);
+            PatternParams::Spot { spot }
+        }
+        PatternParams::Annulus { mut annulus } => {
+            let scale = local_scale(&jacobian(matrix, annulus.position_xy)?);
+            annulus.position_xy = apply(matrix, annulus.position_xy)?;
+            annulus.inner_diameter *= scale;
+            annulus.outer_diameter *= scale;
+            PatternParams::Annulus { annulus }
+        }
+        PatternParams::KnifeEdge { mut knife_edge } => {
+            let jacobian = jacobian(matrix, knife_edge.position_xy)?;
+            knife_edge.position_xy = apply(matrix, knife_edge.position_xy)?;
+            knife_edge.orientation_deg = map_angle(&jacobian, knife_edge.orientation_deg);
+            PatternParams::KnifeEdge { knife_edge }
+        }
+        // the other patterns cover the whole panel
+        pattern => pattern,
+    })
+}
+
+/// Linear magnification, the square root of the area magnification
+fn local_scale(jacobian: &[[f32; 2]; 2]) -> f32 {
+    (jacobian[0][0] * jacobian[1][1] - jacobian[0][1] * jacobian[1][0])
+        .abs()
+        .sqrt()
+}
+
+impl<'a> Context<'a> {
+    fn camera_registration(&self) -> Result<&Matrix> {
+        match &self.state.registration {
+            Some(registration) => Ok(&registration.matrix),
+            None => Err(SlmError::Request(
+                "Positions in camera space need a registration".to_owned(),
+            ))?,
+        }
+    }
+
+    /// Convert a position given in `space` into SLM coordinates
+    pub fn point_to_slm(&self, space: Space, point: (f32, f32)) -> Result<(f32, f32)> {
+        match space {
+            Space::Slm => Ok(point),
+            Space::Camera => apply(self.camera_registration()?, point),
+        }
+    }
+
+    /// Convert the positions of a pattern given in `space` into SLM coordinates
+    pub fn pattern_to_slm(&self, space: Space, pattern: PatternParams) -> Result<PatternParams> {
+        match space {
+            Space::Slm => Ok(pattern),
+            Space::Camera => map_pattern(self.camera_registration()?, pattern),
+        }
+    }
+
+    fn send_registration(&mut self) -> Result<&mut Self> {
+        let registration = self.state.registration.clone();
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Registration { registration }),
+        })
+    }
+
+    /// Check, persist and publish a new registration
+    pub fn store_registration(&mut self, registration: Registration) -> Result<&mut Self> {
+        let finite = registration.matrix.iter().flatten().all(|e| e.is_finite());
+        if !finite || determinant(&registration.matrix).abs() < f64::EPSILON {
+            Err(SlmError::Request(
+                "Registration matrix is not invertible".to_owned(),
+            ))?
+        }
+
+        info!("Setting camera registration {:?}", registration);
+        save(Some(&registration))?;
+        self.state.registration = Some(registration);
+        self.send_registration()
+    }
+
+    pub fn set_registration(&mut self, source: RegistrationSource) -> Result<&mut Self> {
+        let registration = match source {
+            RegistrationSource::Matrix { matrix } => Registration {
+                matrix,
+                rms_residual: None,
+                max_residual: None,
+            },
+            RegistrationSource::PointPairs { pairs, homography } => fit(&pairs, homography)?,
+        };
+        self.store_registration(registration)
+    }
+
+    pub fn clear_registration(&mut self) -> Result<&mut Self> {
+        info!("Clearing camera registration");
+        save(None)?;
+        self.state.registration = None;
+        self.send_registration()
+    }
+
+    pub fn get_registration(&mut self) -> Result<&mut Self> {
+        self.send_registration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFFINE: Matrix = [[1.5, -0.2, 30.0], [0.3, 1.2, -12.0], [0.0, 0.0, 1.0]];
+    const HOMOGRAPHY: Matrix = [[1.1, 0.05, 20.0], [-0.1, 0.9, 15.0], [1e-4, -2e-4, 1.0]];
+
+    fn pairs(matrix: &Matrix, camera: &[(f32, f32)]) -> Vec<PointPair> {
+        camera
+            .iter()
+            .map(|&camera_xy| PointPair {
+                camera_xy,
+                slm_xy: apply(matrix, camera_xy).unwrap(),
+            })
+            .collect()
+    }
+
+    fn assert_matrix_close(actual: &Matrix, expected: &Matrix, tolerance: f64) {
+        for (actual_row, expected_row) in actual.iter().zip(expected) {
+            for (actual, expected) in actual_row.iter().zip(expected_row) {
+                assert!(
+                    (actual - expected).abs() <= tolerance * expected.abs().max(1.0),
+                    "{:?} != {:?}",
+                    actual_row,
+                    expected_row
+                );
+            }
+        }
+    }
+
+    const GRID: [(f32, f32); 6] = [
+        (0.0, 0.0),
+        (400.0, 10.0),
+        (20.0, 300.0),
+        (390.0, 310.0),
+        (200.0, 150.0),
+        (100.0, 250.0),
+    ];
+
+    #[test]
+    fn solve_recovers_a_known_solution() {
+        let a = vec![
+            vec![0.0, 2.0, 1.0],
+            vec![1.0, -1.0, 3.0],
+            vec![4.0, 1.0, 0.5],
+        ];
+        let x = [1.5, -2.0, 0.25];
+        let b = a
+            .iter()
+            .map(|row| row.iter().zip(&x).map(|(a, x)| a * x).sum())
+            .collect();
+        let solved = solve(a, b).unwrap();
+        for (solved, x) in solved.iter().zip(&x) {
+            assert!((solved - x).abs() < 1e-12, "{} != {}", solved, x);
+        }
+    }
+
+    #[test]
+    fn solve_rejects_singular_systems() {
+        let a = vec![vec![1.0, 2.0], vec![2.0, 4.0]];
+        assert!(solve(a, vec![3.0, 6.0]).is_none());
+    }
+
+    #[test]
+    fn normalization_centers_and_scales() {
+        let points = [(10.0, 20.0), (30.0, 20.0), (10.0, 60.0), (30.0, 60.0)];
+        let (to, from) = normalization(&points).unwrap();
+        let normalized: Vec<_> = points.iter().map(|&p| apply(&to, p).unwrap()).collect();
+        let count = normalized.len() as f32;
+        let center_x = normalized.iter().map(|p| p.0).sum::<f32>() / count;
+        let center_y = normalized.iter().map(|p| p.1).sum::<f32>() / count;
+        let distance = normalized.iter().map(|p| p.0.hypot(p.1)).sum::<f32>() / count;
+        assert!(center_x.abs() < 1e-5 && center_y.abs() < 1e-5);
+        assert!((distance - 2.0_f32.sqrt()).abs() < 1e-5);
+        let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        assert_matrix_close(&multiply(&from, &to), &identity, 1e-12);
+    }
+
+    #[test]
+    fn normalization_rejects_coincident_points() {
+        assert!(normalization(&[(5.0, 5.0), (5.0, 5.0), (5.0, 5.0)]).is_err());
+    }
+
+    #[test]
+    fn fit_recovers_an_affine_transform() {
+        let registration = fit(&pairs(&AFFINE, &GRID), false).unwrap();
+        assert_matrix_close(&registration.matrix, &AFFINE, 1e-5);
+        assert!(registration.max_residual.unwrap() < 1e-2);
+    }
+
+    #[test]
+    fn fit_recovers_a_homography() {
+        let registration = fit(&pairs(&HOMOGRAPHY, &GRID), true).unwrap();
+        assert_matrix_close(&registration.matrix, &HOMOGRAPHY, 1e-4);
+        assert!(registration.max_residual.unwrap() < 1e-2);
+    }
+
+    #[test]
+    fn fit_needs_enough_pairs() {
+        assert!(fit(&pairs(&AFFINE, &GRID[..2]), false).is_err());
+        assert!(fit(&pairs(&HOMOGRAPHY, &GRID[..3]), true).is_err());
+    }
+
+    #[test]
+    fn fit_rejects_degenerate_points() {
+        let collinear = [(0.0, 0.0), (10.0, 10.0), (20.0, 20.0), (35.0, 35.0)];
+        assert!(fit(&pairs(&AFFINE, &collinear), false).is_err());
+        assert!(fit(&pairs(&HOMOGRAPHY, &collinear), true).is_err());
+        let coincident = [(7.0, 3.0); 4];
+        assert!(fit(&pairs(&AFFINE, &coincident), false).is_err());
+    }
+
+    #[test]
+    fn jacobian_of_an_affine_transform_is_its_linear_part() {
+        let jacobian = jacobian(&AFFINE, (123.0, -45.0)).unwrap();
+        assert_eq!(jacobian, [[1.5, -0.2], [0.3, 1.2]]);
+    }
+
+    #[test]
+    fn jacobian_matches_finite_differences() {
+        let point = (150.0, 80.0);
+        let jacobian = jacobian(&HOMOGRAPHY, point).unwrap();
+        let step = 1e-2;
+        let (x0, y0) = apply(&HOMOGRAPHY, point).unwrap();
+        let (x1, y1) = apply(&HOMOGRAPHY, (point.0 + step, point.1)).unwrap();
+        let (x2, y2) = apply(&HOMOGRAPHY, (point.0, point.1 + step)).unwrap();
+        let numeric = [
+            [(x1 - x0) / step, (x2 - x0) / step],
+            [(y1 - y0) / step, (y2 - y0) / step],
+        ];
+        for (row, numeric_row) in jacobian.iter().zip(&numeric) {
+            for (analytic, numeric) in row.iter().zip(numeric_row) {
+                assert!(
+                    (analytic - numeric).abs() < 1e-2,
+                    "{:?} != {:?}",
+                    jacobian,
+                    numeric
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! `render` subcommand: compute a pattern offline, without MQTT or a window,
+//! e.g. to check parameters before an experiment or to make figures
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use mqtt::Client;
+use sdl2::Sdl;
+
+use crate::{
+    display::NullDisplay,
+    initialize_state, read_config,
+    schema::{AimState, Config},
+    Context, Result, ScreenContext, SlmError,
+};
+
+const USAGE: &str =
+    "Usage: rasp_pi render <aim state JSON file> <wavelength> <output .png or .npy>";
+
+impl<'a> Context<'a> {
+    /// Context without a connection or a window; it only logs its messages
+    pub fn offline(config: Config, sdl_context: &'a Sdl) -> Result<Self> {
+        // never connected
+        let client = Client::new(config.mqtt.server_uri())?;
+        let screen_context = ScreenContext {
+            display: Box::new(NullDisplay),
+            sdl_context,
+            mode: None,
+        };
+        let state = initialize_state(&config)?;
+        let mut context = Context::new(config, client, screen_context, state);
+        context.offline = true;
+        Ok(context)
+    }
+
+    /// Pattern the SLM would show for an `AimState` at a wavelength
+    pub fn render_pattern(
+        &mut self,
+        aim_state: AimState,
+        wavelength: u32,
+    ) -> Result<ndarray::Array2<u8>> {
+        let pattern = self.pattern_to_slm(aim_state.space, aim_state.pattern)?;
+        self.update_state_raw(
+            Some(pattern),
+            Some(aim_state.fresnel),
+            Some(wavelength),
+            aim_state.raw,
+        )?;
+        Ok(self
+            .state
+            .displayed
+            .clone()
+            .expect("update_state shows the pattern"))
+    }
+}
+
+/// Compute the pattern for an `AimState` at a wavelength with the calibration
+/// from `config.json`, and write it as an image or NumPy array
+pub fn render(args: &[String]) -> Result<()> {
+    let (state_path, wavelength, output) = match args {
+        [state_path, wavelength, output] => (state_path, wavelength, Path::new(output)),
+        _ => Err(SlmError::Request(USAGE.to_owned()))?,
+    };
+    let wavelength: u32 = wavelength
+        .parse()
+        .map_err(|_| SlmError::Request(format!("Invalid wavelength {}", wavelength)))?;
+    let aim_state: AimState = serde_json::from_reader(BufReader::new(File::open(state_path)?))?;
+
+    let sdl_context = sdl2::init().map_err(SlmError::Display)?;
+    let mut context = Context::offline(read_config()?, &sdl_context)?;
+    let pattern = context.render_pattern(aim_state, wavelength)?;
+
+    match output.extension().and_then(|ext| ext.to_str()) {
+        Some("npy") => ndarray_npy::write_npy(output, pattern.view())?,
+        Some("png") => ndarray_image::save_gray_image(output, pattern.view())?,
+        _ => Err(SlmError::Request(USAGE.to_owned()))?,
+    }
+    println!("Wrote {}", output.display());
+    Ok(())
+}
@@ -0,0 +1,236 @@
+//! Moving the active spot along a trajectory with on-device timing,
+//! e.g. for photo-stimulation
+
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::{
+    schema::{
+        AimCommand, Message, MessageData, MessageType, PatternParams, ResponseCode, ScanTrajectory,
+        SpotPattern,
+    },
+    Context, Result, SlmError,
+};
+
+pub struct ScanRun {
+    /// Spot positions and how long each of them is held
+    points: Vec<((f32, f32), Duration)>,
+    index: usize,
+    /// Advance on `scanTrigger` commands instead of the dwell time
+    triggered: bool,
+    /// Start over after the last point instead of finishing
+    looping: bool,
+    paused: bool,
+    shown_at: Instant,
+    spot: SpotPattern,
+    /// Pattern restored once the scan is over
+    previous: PatternParams,
+}
+
+fn raster((from_x, from_y): (f32, f32), (to_x, to_y): (f32, f32), step: f32) -> Vec<(f32, f32)> {
+    let axis = |from: f32, to: f32| {
+        let count = ((to - from).abs() / step).floor() as usize + 1;
+        let step = if to < from { -step } else { step };
+        (0..count).map(move |i| from + step * i as f32)
+    };
+    axis(from_y, to_y)
+        .flat_map(|y| axis(from_x, to_x).map(move |x| (x, y)))
+        .collect()
+}
+
+fn spiral((center_x, center_y): (f32, f32), max_radius: f32, step: f32) -> Vec<(f32, f32)> {
+    // r = b * angle, turns `step` apart
+    let b = step / (2.0 * PI);
+    let mut angle = 0.0_f32;
+    let mut points = Vec::new();
+    loop {
+        let radius = b * angle;
+        if radius > max_radius {
+            break points;
+        }
+        let (sin, cos) = angle.sin_cos();
+        points.push((center_x + radius * cos, center_y + radius * sin));
+        // arc length of the spiral per radian is sqrt(r^2 + b^2)
+        angle += step / (radius * radius + b * b).sqrt();
+    }
+}
+
+/// Number of points of a trajectory, without computing them; NaN for
+/// invalid trajectories
+fn point_count(trajectory: &ScanTrajectory) -> f64 {
+    match *trajectory {
+        ScanTrajectory::Raster {
+            from_xy,
+            to_xy,
+            step,
+        } => {
+            let axis = |from: f32, to: f32| f64::from(((to - from).abs() / step).floor()) + 1.0;
+            axis(from_xy.0, to_xy.0) * axis(from_xy.1, to_xy.1)
+        }
+        ScanTrajectory::Spiral {
+            max_radius, step, ..
+        } => {
+            // the points are `step` apart along the arc, of length
+            // b / 2 * (angle * sqrt(1 + angle^2) + asinh(angle))
+            let b = f64::from(step) / (2.0 * std::f64::consts::PI);
+            let angle = f64::from(max_radius) / b;
+            let length = b / 2.0 * (angle * (1.0 + angle * angle).sqrt() + angle.asinh());
+            (length / f64::from(step)).floor() + 1.0
+        }
+    }
+}
+
+/// Points of the trajectory; trajectories of more than `max_points` are rejected
+pub fn trajectory_points(
+    trajectory: &ScanTrajectory,
+    max_points: usize,
+) -> Result<Vec<(f32, f32)>> {
+    let step = match trajectory {
+        ScanTrajectory::Raster { step, .. } | ScanTrajectory::Spiral { step, .. } => *step,
+    };
+    if step.is_nan() || step <= 0.0 {
+        Err(SlmError::Request(format!("Invalid scan step {}", step)))?
+    }
+    let count = point_count(trajectory);
+    // NaN positions make the count NaN
+    if count.is_nan() || count > max_points as f64 {
+        Err(SlmError::Request(format!(
+            "Scan trajectory of {} points is above the maximum of {}",
+            count, max_points
+        )))?
+    }
+    Ok(match trajectory {
+        ScanTrajectory::Raster {
+            from_xy,
+            to_xy,
+            step,
+        } => raster(*from_xy, *to_xy, *step),
+        ScanTrajectory::Spiral {
+            center_xy,
+            max_radius,
+            step,
+        } => spiral(*center_xy, *max_radius, *step),
+    })
+}
+
+impl<'a> Context<'a> {
+    fn show_scan_point(&mut self, run: &mut ScanRun) -> Result<()> {
+        let (position_xy, _) = run.points[run.index];
+        let spot = SpotPattern {
+            position_xy,
+            ..run.spot.clone()
+        };
+        self.update_state(Some(PatternParams::Spot { spot }), None, None)?;
+        run.shown_at = Instant::now();
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::ScanPosition {
+                index: run.index as u32,
+                total: run.points.len() as u32,
+                position_xy,
+            }),
+        })?;
+        Ok(())
+    }
+
+    /// Start moving the active spot through `points`
+    pub fn start_scan(
+        &mut self,
+        points: Vec<((f32, f32), Duration)>,
+        triggered: bool,
+        looping: bool,
+    ) -> Result<&mut Self> {
+        self.stop_scan()?;
+
+        let spot = match &self.state.pattern_params {
+            PatternParams::Spot { spot } => spot.clone(),
+            _ => Err(SlmError::Request(
+                "Scanning needs an active spot pattern".to_owned(),
+            ))?,
+        };
+        if points.is_empty() {
+            Err(SlmError::Request("Empty scan trajectory".to_owned()))?
+        }
+
+        info!("Scanning spot through {} points", points.len());
+        let mut run = ScanRun {
+            points,
+            index: 0,
+            triggered,
+            looping,
+            paused: false,
+            shown_at: Instant::now(),
+            previous: self.state.pattern_params.clone(),
+            spot,
+        };
+        self.show_scan_point(&mut run)?;
+        self.state.scan = Some(run);
+        Ok(self)
+    }
+
+    fn next_scan_point(&mut self, mut run: ScanRun) -> Result<&mut Self> {
+        run.index += 1;
+        if run.looping {
+            run.index %= run.points.len();
+        }
+        if run.index < run.points.len() {
+            self.show_scan_point(&mut run)?;
+            self.state.scan = Some(run);
+            Ok(self)
+        } else {
+            info!("Scan done");
+            self.update_state(Some(run.previous), None, None)?
+                .send_current_state()?
+                .send_response(ResponseCode::ScanDone)
+        }
+    }
+
+    pub fn trigger_scan(&mut self) -> Result<&mut Self> {
+        match self.state.scan.take() {
+            Some(run) => self.next_scan_point(run),
+            None => Err(SlmError::Request("No scan running".to_owned()))?,
+        }
+    }
+
+    pub fn pause_scan(&mut self, paused: bool) -> Result<&mut Self> {
+        match &mut self.state.scan {
+            Some(run) => {
+                run.paused = paused;
+                // the current point gets its full dwell time after resuming
+                run.shown_at = Instant::now();
+                Ok(self)
+            }
+            None => Err(SlmError::Request("No scan running".to_owned()))?,
+        }
+    }
+
+    /// Restore the pattern shown before the scan, if one is running
+    pub fn stop_scan(&mut self) -> Result<&mut Self> {
+        if let Some(run) = self.state.scan.take() {
+            info!("Stopping scan at point {}", run.index);
+            self.update_state(Some(run.previous), None, None)?
+                .send_current_state()?;
+        }
+        Ok(self)
+    }
+
+    /// Advance timed scans once the dwell time of the current point is over
+    pub fn poll_scan(&mut self) -> Result<()> {
+        let due = match &self.state.scan {
+            Some(run) => {
+                !run.triggered && !run.paused && run.shown_at.elapsed() >= run.points[run.index].1
+            }
+            None => false,
+        };
+        if due {
+            let run = self.state.scan.take().unwrap();
+            self.next_scan_point(run)?;
+        }
+        Ok(())
+    }
+}
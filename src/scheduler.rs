@@ -0,0 +1,165 @@
+//! Actions run at fixed times of day (e.g. blanking the panel in the evening),
+//! configured in the config file or over MQTT
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use log::{error, info};
+
+use crate::{
+    schema::{AimCommand, Message, MessageData, MessageType, ScheduledAction, ScheduledTask},
+    Context, Result, SlmError,
+};
+
+/// Tasks added over MQTT are kept here, so they survive restarts
+const SCHEDULE_FILE: &str = "schedule.json";
+
+pub struct Schedule {
+    path: PathBuf,
+    pub tasks: Vec<ScheduledTask>,
+    last_check: DateTime<Local>,
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|err| SlmError::Request(format!("Invalid time {}: {}", time, err)))
+}
+
+/// Whether the task time was passed between the two checks
+fn is_due(task: &ScheduledTask, last_check: DateTime<Local>, now: DateTime<Local>) -> Result<bool> {
+    let time = parse_time(&task.time)?;
+    Ok([last_check.date(), now.date()].iter().any(|date| {
+        let at = Local.from_local_datetime(&date.naive_local().and_time(time));
+        at.earliest().is_some_and(|at| last_check < at && at <= now)
+    }))
+}
+
+impl Schedule {
+    pub fn load() -> Result<Self> {
+        let path = Path::new(SCHEDULE_FILE).to_owned();
+        let tasks = if path.is_file() {
+            serde_json::from_reader(BufReader::new(File::open(&path)?))?
+        } else {
+            Vec::new()
+        };
+        Ok(Schedule {
+            path,
+            tasks,
+            last_check: Local::now(),
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(&self.path)?), &self.tasks)?;
+        Ok(())
+    }
+}
+
+impl<'a> Context<'a> {
+    fn send_schedule(&mut self) -> Result<&mut Self> {
+        let tasks = self
+            .config
+            .schedule
+            .iter()
+            .chain(&self.state.schedule.tasks)
+            .cloned()
+            .collect();
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Schedule { tasks }),
+        })
+    }
+
+    pub fn add_scheduled_task(&mut self, task: ScheduledTask) -> Result<&mut Self> {
+        parse_time(&task.time)?;
+        if self.config.schedule.iter().any(|t| t.name == task.name) {
+            Err(SlmError::Request(format!(
+                "Task {} is defined in the config",
+                task.name
+            )))?
+        }
+        let tasks = &mut self.state.schedule.tasks;
+        tasks.retain(|t| t.name != task.name);
+        tasks.push(task);
+        self.state.schedule.save()?;
+        self.send_schedule()
+    }
+
+    pub fn remove_scheduled_task(&mut self, name: &str) -> Result<&mut Self> {
+        let tasks = &mut self.state.schedule.tasks;
+        let count = tasks.len();
+        tasks.retain(|t| t.name != name);
+        if tasks.len() == count {
+            Err(SlmError::Request(format!(
+                "No scheduled task named {}",
+                name
+            )))?
+        }
+        self.state.schedule.save()?;
+        self.send_schedule()
+    }
+
+    pub fn get_schedule(&mut self) -> Result<&mut Self> {
+        self.send_schedule()
+    }
+
+    fn run_scheduled_action(&mut self, action: &ScheduledAction) -> Result<()> {
+        match action {
+            ScheduledAction::Blank => {
+                self.state.blanked = true;
+                self.update_state(None, None, None)?.send_current_state()?;
+            }
+            ScheduledAction::ReloadCalibration => {
+                self.reload_calibration()?.send_current_state()?;
+            }
+            ScheduledAction::ApplyPreset { preset } => {
+                let preset =
+                    self.config.presets.get(preset).cloned().ok_or_else(|| {
+                        SlmError::Config(format!("Preset {} is not defined", preset))
+                    })?;
+                let pattern = self.pattern_to_slm(preset.space, preset.pattern)?;
+                self.update_state(Some(pattern), Some(preset.fresnel), None)?
+                    .send_current_state()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the tasks whose time has come since the last poll
+    pub fn poll_schedule(&mut self) {
+        let now = Local::now();
+        let last_check = self.state.schedule.last_check;
+        if now.signed_duration_since(last_check).num_seconds() < 1 {
+            return;
+        }
+        self.state.schedule.last_check = now;
+
+        let tasks: Vec<ScheduledTask> = self
+            .config
+            .schedule
+            .iter()
+            .chain(&self.state.schedule.tasks)
+            .cloned()
+            .collect();
+        for task in tasks {
+            let result = is_due(&task, last_check, now).and_then(|due| {
+                if due {
+                    info!("Running scheduled task {}", task.name);
+                    let origin = format!("schedule/{}", task.name);
+                    self.state.last_modified_by = Some(origin.clone());
+                    self.run_scheduled_action(&task.action)?;
+                    self.journal(&origin, "scheduledTask");
+                }
+                Ok(())
+            });
+            if let Err(err) = result {
+                error!("Error {} in scheduled task {}; continuing", err, task.name);
+            }
+        }
+    }
+}
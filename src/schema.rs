@@ -1,14 +1,15 @@
-//! This module contains types that client sends to and recieves from the server.
+//! This module contains the controller config; the types that client sends to
+//! and recieves from the server live in the `slm-protocol` crate.
 
 use std::collections::HashMap;
-use std::fmt;
 use std::path::PathBuf;
 
 use flexi_logger::LevelFilter;
-use serde::{
-    de::{Deserializer, Error, MapAccess, Visitor},
-    Deserialize, Serialize,
-};
+use serde::{Deserialize, Deserializer};
+
+pub use slm_protocol::*;
+
+use crate::util::Subtopic;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct DirPath {
@@ -16,6 +17,13 @@ pub struct DirPath {
     pub flatness_corr_patterns: PathBuf,
 }
 
+impl DirPath {
+    /// Calibration data edited at runtime is kept next to the flatness corrections
+    pub fn calibration_store(&self) -> PathBuf {
+        self.flatness_corr_patterns.join("calibration.json")
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Microscope {
     pub serial_nr: String,
@@ -25,6 +33,49 @@ pub struct Microscope {
 pub struct MqttConfig {
     pub broker_ip: String,
     pub port: u16,
+    /// Compression of large outgoing messages; received messages are
+    /// decompressed regardless, recognized by the header of their format
+    pub compression: Option<CompressionConfig>,
+    /// Messages are also published in these encodings, on the aim topic with
+    /// the encoding as a suffix; commands are accepted in every encoding
+    #[serde(default)]
+    pub publish_encodings: Vec<Encoding>,
+    /// Compressed messages decompressing to more bytes are rejected
+    #[serde(default = "default_max_decompressed_bytes")]
+    pub max_decompressed_bytes: u64,
+}
+
+fn default_max_decompressed_bytes() -> u64 {
+    crate::compression::DEFAULT_MAX_DECOMPRESSED_BYTES
+}
+
+/// Encoding of a message, named by the suffix of its topic;
+/// JSON is sent without a suffix
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Json,
+    Cbor,
+    Msgpack,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionFormat {
+    Zstd,
+    Gzip,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CompressionConfig {
+    pub format: CompressionFormat,
+    /// Smaller messages are sent as plain JSON
+    #[serde(default = "default_compression_min_bytes")]
+    pub min_bytes: usize,
+}
+
+fn default_compression_min_bytes() -> usize {
+    64 * 1024
 }
 
 impl MqttConfig {
@@ -51,17 +102,99 @@ pub struct SLMCalibScaling {
 pub struct PatternComputationDebug {
     #[serde(rename = "save_computed_pattern_to_image_file")]
     pub save_computed_to_image: bool,
+    /// Save the pattern after each stage of the computation (base, flatness,
+    /// blaze, fresnel, quantized) as images on every update
+    #[serde(default)]
+    pub dump_stages: bool,
+    #[serde(default = "default_stage_dump_dir")]
+    pub stage_dump_dir: PathBuf,
+    /// Images of only this many latest updates are kept
+    #[serde(default = "default_max_stage_dumps")]
+    pub max_stage_dumps: usize,
+}
+
+fn default_stage_dump_dir() -> PathBuf {
+    PathBuf::from("debug")
+}
+
+fn default_max_stage_dumps() -> usize {
+    20
+}
+
+/// What to do when a correction is enabled but its data is missing
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingCorrectionPolicy {
+    /// Fail the update and keep the old pattern
+    #[default]
+    Fail,
+    /// Publish a warning and display the pattern without the correction
+    Warn,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct PatternComputationConfig {
     pub slm_calib_scaling: SLMCalibScaling,
     pub add_flatness_correction: bool,
+    #[serde(default)]
+    pub missing_correction: MissingCorrectionPolicy,
+    /// Store corrected flatness patterns losslessly as zstd-compressed npy files
+    #[serde(default)]
+    pub compress_corrections: bool,
+    /// Keep accumulated flatness corrections in f64, converting to f32
+    /// only when they are added to the computed pattern
+    #[serde(default)]
+    pub f64_corrections: bool,
     pub debug: Option<PatternComputationDebug>,
+    /// Output a binary amplitude pattern instead of phase levels, for driving a DMD
+    pub binary: Option<BinaryEncoding>,
+    /// How complex patterns are displayed on the phase-only panel
+    #[serde(default)]
+    pub complex_encoding: ComplexEncoding,
+    /// Recompute the displayed pattern whenever its flatness correction changes
+    #[serde(default = "default_true")]
+    pub recompute_on_correction_change: bool,
+    /// Float type the layers of the pattern are summed in
+    #[serde(default)]
+    pub precision: Precision,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Precision {
+    /// Faster, enough for a few layers
+    #[default]
+    F32,
+    /// For many layers with large phases, where f32 rounding shows as banding
+    F64,
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
 #[serde(rename_all = "snake_case")]
+pub enum ComplexEncoding {
+    /// Display only the phase, dropping the amplitude
+    #[default]
+    PhaseOnly,
+    /// Double-phase method: each checkerboard pair of pixels shows two phases
+    /// whose average field has the requested amplitude
+    DoublePhase,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "dithering", rename_all = "snake_case")]
+pub enum BinaryEncoding {
+    /// Pixels whose phase is within `duty / 2` of a period from zero are switched on
+    Threshold { duty: f32 },
+    /// The fringe intensity `(1 + cos(phase)) / 2` is dithered with a 4x4 Bayer matrix
+    Ordered,
+}
+
 #[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum LogLevel {
     Debug,
     Info,
@@ -86,6 +219,19 @@ impl LogLevel {
 #[derive(Deserialize, Debug, Clone)]
 pub struct Logging {
     pub log_level: LogLevel,
+    /// `chrono` format of the timestamps; `"%Y-%m-%dT%H:%M:%S%.3f%:z"` is ISO 8601
+    /// with milliseconds
+    #[serde(default = "default_timestamp_format")]
+    pub timestamp_format: String,
+    /// Timestamps in UTC instead of the local time zone
+    #[serde(default)]
+    pub utc: bool,
+}
+
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f %:z";
+
+fn default_timestamp_format() -> String {
+    DEFAULT_TIMESTAMP_FORMAT.to_owned()
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -95,217 +241,594 @@ pub struct DefaultState {
     pub pattern: PatternParams,
 }
 
+fn default_blaze_phi_max() -> f32 {
+    80.0
+}
+
+fn default_blaze_reference_wavelength() -> u32 {
+    488
+}
+
+fn default_blaze_offset_factor() -> f32 {
+    1.1
+}
+
+/// Blazed grating added to every pattern, steering the beam away from the zeroth order
 #[derive(Deserialize, Debug, Clone)]
-pub struct Config {
-    pub microscope: Microscope,
-    pub dir_path: DirPath,
-    pub mqtt: MqttConfig,
-    pub screen: ScreenConfig,
-    pub compute_pattern: PatternComputationConfig,
-    pub image_file_extensions: Vec<String>,
-    pub logging: Logging,
-    pub defaults: DefaultState,
+pub struct BlazeConfig {
+    /// Phase across the whole panel at the reference wavelength, in radians divided by 2 pi
+    #[serde(default = "default_blaze_phi_max")]
+    pub phi_max: f32,
+    #[serde(default = "default_blaze_reference_wavelength")]
+    pub reference_wavelength: u32,
+    /// Constant phase offset, relative to `phi_max`
+    #[serde(default = "default_blaze_offset_factor")]
+    pub offset_factor: f32,
+}
+
+impl Default for BlazeConfig {
+    fn default() -> Self {
+        BlazeConfig {
+            phi_max: default_blaze_phi_max(),
+            reference_wavelength: default_blaze_reference_wavelength(),
+            offset_factor: default_blaze_offset_factor(),
+        }
+    }
 }
 
-impl Config {
-    pub fn main_topic(&self) -> &str {
-        &self.microscope.serial_nr
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct SafetyConfig {
+    /// Attenuation in percent that is always applied, even if less is requested
+    #[serde(default)]
+    pub min_attenuation: f32,
+    /// Larger fresnel values are rejected
+    pub max_fresnel: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ControlConfig {
+    /// Subtopics (e.g. `"calibration/aim"`) whose commands are accepted while a client
+    /// holds control or in maintenance mode, and which may release control held by any client
+    #[serde(default)]
+    pub override_subtopics: Vec<String>,
+}
+
+/// Check that the system clock is synchronized, since journals and saved
+/// corrections are ordered by their timestamps
+#[derive(Deserialize, Debug, Clone)]
+pub struct ClockConfig {
+    /// Larger offsets from the time server are reported as unsynchronized
+    #[serde(default = "default_max_clock_offset_ms")]
+    pub max_offset_ms: f64,
+    #[serde(default = "default_clock_check_interval_secs")]
+    pub check_interval_secs: u64,
+}
+
+fn default_max_clock_offset_ms() -> f64 {
+    1000.0
+}
+
+fn default_clock_check_interval_secs() -> u64 {
+    600
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            max_offset_ms: default_max_clock_offset_ms(),
+            check_interval_secs: default_clock_check_interval_secs(),
+        }
     }
 }
 
-#[serde(rename_all = "snake_case")]
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub enum MessageType {
-    Log,
-    Device,
-    Status,
+/// Lasers the wavelength is selected from, by name; illumination sources like
+/// the LED aren't lasers the pattern is computed for
+#[derive(Deserialize, Debug, Clone)]
+pub struct LaserSelectionConfig {
+    #[serde(default = "default_ignored_lasers")]
+    pub ignored: Vec<String>,
+    /// Only these lasers are selected from, if set
+    pub only: Option<Vec<String>>,
+    /// Another laser has to be stronger than the selected one by this many
+    /// intensity percentage points to be selected instead
+    #[serde(default)]
+    pub hysteresis_percent: f32,
+    /// Seconds after a switch during which the selection is kept while the
+    /// selected laser stays on
+    #[serde(default)]
+    pub min_dwell_secs: f32,
+}
+
+fn default_ignored_lasers() -> Vec<String> {
+    vec!["led".to_owned()]
+}
+
+impl Default for LaserSelectionConfig {
+    fn default() -> Self {
+        LaserSelectionConfig {
+            ignored: default_ignored_lasers(),
+            only: None,
+            hysteresis_percent: 0.0,
+            min_dwell_secs: 0.0,
+        }
+    }
+}
+
+impl LaserSelectionConfig {
+    pub fn is_selectable(&self, name: &str) -> bool {
+        let listed = |names: &Vec<String>| names.iter().any(|listed| listed == name);
+        !listed(&self.ignored) && self.only.as_ref().is_none_or(listed)
+    }
+}
+
+/// Directory watched for JSON command files
+#[derive(Deserialize, Debug, Clone)]
+pub struct DropDirConfig {
+    pub path: PathBuf,
+    /// The commands are treated as if they arrived on this subtopic, for the permissions
+    #[serde(default = "default_drop_dir_subtopic")]
+    pub subtopic: String,
+}
+
+fn default_drop_dir_subtopic() -> String {
+    "calibration/aim".to_owned()
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct LaserState {
+/// ZeroMQ sockets for acquisition software on the same machine, avoiding the
+/// broker round trip; needs the zeromq feature
+#[derive(Deserialize, Debug, Clone)]
+pub struct ZeroMqConfig {
+    /// REP socket taking one JSON message per request, and replying with the
+    /// JSON array of messages sent meanwhile (e.g. `"tcp://127.0.0.1:5555"`)
+    pub rep_endpoint: String,
+    /// PUB socket publishing every message the controller sends
+    pub pub_endpoint: Option<String>,
+    /// Requests are treated as if they arrived on this subtopic, for the permissions
+    #[serde(default = "default_zeromq_subtopic")]
+    pub subtopic: String,
+}
+
+fn default_zeromq_subtopic() -> String {
+    "zmq/aim".to_owned()
+}
+
+/// Every shown pattern is also written to a named shared-memory segment, for
+/// an external process (e.g. a vendor SDK wrapper) driving the SLM
+#[derive(Deserialize, Debug, Clone)]
+pub struct SharedMemoryConfig {
+    /// Segment name; on Linux it's `/dev/shm/<name>`, as for `shm_open`
     pub name: String,
-    pub state: u32, // assuming u32, could be bool?
-    pub wavelength: u32,
-    pub intensity: u32, // Not sure if intensity is u32 or f32
 }
 
-#[serde(tag = "command")]
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum LaserCommand {
-    #[serde(rename = "get")]
-    Get,
-    #[serde(rename = "availablePatterns")]
-    AvailablePatterns,
-    #[serde(rename = "set")]
-    Set { lasers: Vec<LaserState> },
+/// Frames of exactly the screen size published as raw pixels, row by row, are
+/// shown as they are; 8-bit frames directly, 16-bit little-endian ones by
+/// their high byte
+#[derive(Deserialize, Debug, Clone)]
+pub struct RawFramesConfig {
+    #[serde(default = "default_raw_frames_subtopic")]
+    pub subtopic: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct AimState {
-    pub pattern: PatternParams,
-    pub fresnel: u32,
+fn default_raw_frames_subtopic() -> String {
+    "frames".to_owned()
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct CorrectionPatternDeltas {
-    pub wavelength: u32,
-    pub imagedata: String,
-    pub shape_xy: [usize; 2],
+/// Every shown pattern is appended to an HDF5 file per session; needs the hdf5 feature
+#[derive(Deserialize, Debug, Clone)]
+pub struct SessionLogConfig {
+    pub directory: PathBuf,
+    /// Only every nth pixel along x and y is kept
+    #[serde(default = "default_session_log_downsample")]
+    pub downsample: usize,
+    /// zlib compression level from 0 to 9
+    #[serde(default = "default_session_log_compression")]
+    pub compression: u32,
+    /// The next file of the session is started once one exceeds this size
+    #[serde(default = "default_session_log_max_file_mb")]
+    pub max_file_mb: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
-pub struct APatternProp {
-    pub values: Vec<String>,
+fn default_session_log_downsample() -> usize {
+    1
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
-pub struct APattern {
-    #[serde(flatten)]
-    pub property_values: HashMap<String, APatternProp>,
-    pub properties: Vec<String>,
+fn default_session_log_compression() -> u32 {
+    4
 }
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
-pub struct AvailablePatterns {
-    #[serde(flatten)]
-    pub patterns: HashMap<String, APattern>,
-    #[serde(rename = "patternNames")]
-    pub pattern_names: Vec<String>,
-}
-
-#[serde(tag = "command")]
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum AimCommand {
-    #[serde(rename = "get")]
-    Get,
-    #[serde(rename = "getAllPatterns")]
-    GetAllPatterns,
-    #[serde(rename = "set")]
-    Set(AimState),
-    PreStack(AimState),
-    #[serde(rename = "setpattern")]
-    SetPattern {
-        pattern: PatternParams,
-    },
-    #[serde(rename = "setfresnel")]
-    SetFresnel {
-        value: u32,
-    },
-    #[serde(rename = "response")]
-    Response {
-        reply: String,
-    },
-    #[serde(rename = "uploadimage")]
-    UploadImage {
-        name: String,
-        imagedata: String,
-    },
-    #[serde(rename = "deleteimage")]
-    DeleteImage {
-        name: String,
-    },
-    #[serde(rename = "disconnect")]
-    Disconnect,
-    #[serde(rename = "setCorrectionPatternDeltas")]
-    SetCorrectionPatternDeltas(CorrectionPatternDeltas),
-    // Skip deserializing, because it has the same name as setCorrectionPatternDeltas
-    #[serde(rename = "setCorrectionPatternDeltas", skip_deserializing)]
-    SetCorrectionPatternDeltasResponse {
-        wavelength: u32,
-        success: bool,
-    },
-    #[serde(rename = "availablePatterns")]
-    AvailablePatterns {
-        patterns: AvailablePatterns,
-    },
-    #[serde(rename = "reboot")]
-    Reboot,
+
+fn default_session_log_max_file_mb() -> u64 {
+    1024
 }
 
-#[serde(tag = "command")]
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum EmbeddedCommand {
-    #[serde(rename = "initdone")]
-    InitDone,
-    #[serde(rename = "set")]
-    Set,
+/// MJPEG stream of the shown pattern over HTTP, for watching the SLM remotely
+#[derive(Deserialize, Debug, Clone)]
+pub struct VideoStreamConfig {
+    /// Address to listen on, e.g. `"0.0.0.0:8080"`; every path serves the stream
+    pub address: String,
+    #[serde(default = "default_video_stream_fps")]
+    pub fps: f32,
+    /// JPEG quality from 1 to 100
+    #[serde(default = "default_video_stream_quality")]
+    pub quality: u8,
+    /// Clients beyond this many are turned away, each one takes a thread
+    #[serde(default = "default_video_stream_max_clients")]
+    pub max_clients: usize,
 }
 
-#[serde(tag = "device", rename_all = "snake_case")]
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum MessageData {
-    Embedded(EmbeddedCommand),
-    Lasers(LaserCommand),
-    Aim(AimCommand),
+fn default_video_stream_fps() -> f32 {
+    2.0
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Message {
-    #[serde(rename = "type")]
-    pub m_type: MessageType, // type is a Rust keyword
-    pub data: MessageData,
+fn default_video_stream_quality() -> u8 {
+    80
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct SpotPattern {
-    pub position_xy: (f32, f32),
-    pub diameter: f32,
-    pub gradient_xy: (f32, f32),
-    pub background_gradient_xy: (f32, f32),
+fn default_video_stream_max_clients() -> usize {
+    8
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct CustomPattern {
-    pub filename: String,
+/// gRPC server for acquisition software speaking it natively; needs the grpc feature
+#[derive(Deserialize, Debug, Clone)]
+pub struct GrpcConfig {
+    /// Address to listen on, e.g. `"0.0.0.0:50051"`
+    pub address: String,
+    /// Requests are treated as if they arrived on this subtopic, for the permissions
+    #[serde(default = "default_grpc_subtopic")]
+    pub subtopic: String,
 }
 
-struct BasePatternVistior {}
+fn default_grpc_subtopic() -> String {
+    "grpc/aim".to_owned()
+}
 
-impl<'de> Visitor<'de> for BasePatternVistior {
-    type Value = BasePattern;
+/// Named set of settings replacing the top-level ones while it's active
+#[derive(Deserialize, Debug, Clone)]
+pub struct Profile {
+    /// State applied when switching to the profile
+    pub defaults: Option<DefaultState>,
+    pub blaze: Option<BlazeConfig>,
+    pub safety: Option<SafetyConfig>,
+}
 
-    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a very special map")
-    }
+#[derive(Deserialize, Debug, Clone)]
+pub struct GamepadConfig {
+    /// Tilt change per second at full stick deflection, in radians per pixel
+    pub tilt_sensitivity: f32,
+    /// Fresnel change per second at full stick deflection
+    pub fresnel_sensitivity: f32,
+    /// Stick deflections below this fraction are ignored
+    pub dead_zone: f32,
+    pub poll_interval_ms: u64,
+}
 
-    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
-    where
-        A: MapAccess<'de>,
-    {
-        let entry: (String, HashMap<String, String>) = map
-            .next_entry()?
-            .ok_or(Error::custom("empty base pattern"))?;
-        Ok(BasePattern {
-            filename: entry.0.clone(),
-            properties: entry.1,
-        })
-    }
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum LengthPrefix {
+    U32Le,
+    U32Be,
 }
 
-impl<'de> Deserialize<'de> for BasePattern {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        deserializer.deserialize_map(BasePatternVistior {})
-    }
+fn default_serial_timeout_ms() -> u64 {
+    1000
+}
+
+/// Frame layout for SLMs that take patterns over a serial (or USB CDC) link:
+/// header, optional pixel count, row-major 8-bit pixels, footer
+#[derive(Deserialize, Debug, Clone)]
+pub struct SerialDisplayConfig {
+    pub port: String,
+    pub baud_rate: u32,
+    #[serde(default)]
+    pub header: Vec<u8>,
+    pub length_prefix: Option<LengthPrefix>,
+    #[serde(default)]
+    pub footer: Vec<u8>,
+    /// Bytes the device replies with once a frame is accepted
+    pub ack: Option<Vec<u8>>,
+    #[serde(default = "default_serial_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum DisplayBackend {
+    /// Fullscreen window on the SLM's video output
+    #[default]
+    Video,
+    Serial(SerialDisplayConfig),
+}
+
+/// Transient frames shown before each new pattern so the liquid crystal settles faster
+#[derive(Deserialize, Debug, Clone)]
+pub struct OverdriveConfig {
+    /// 256x256 u8 npy table, indexed by (previous gray level, target gray level)
+    pub lut_file: PathBuf,
+    /// Number of overdrive frames shown before the target pattern
+    pub frames: u32,
+    pub frame_ms: u64,
+}
+
+/// Serial input that sends a byte whenever the photodiode sees the SLM change
+#[derive(Deserialize, Debug, Clone)]
+pub struct PhotodiodeConfig {
+    pub port: String,
+    pub baud_rate: u32,
+    #[serde(default = "default_serial_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusConfig {
+    /// How often the status message is published
+    pub interval_secs: u64,
+    /// Warn when less space is free on the base patterns partition, in MB
+    #[serde(default = "default_min_disk_free_mb")]
+    pub min_disk_free_mb: u64,
+    /// Warn when more memory is in use, in percent
+    #[serde(default = "default_max_memory_used_percent")]
+    pub max_memory_used_percent: f32,
+    /// Commands processed within this many seconds are included in the
+    /// command metrics
+    #[serde(default = "default_metrics_window_secs")]
+    pub metrics_window_secs: u64,
+}
+
+fn default_min_disk_free_mb() -> u64 {
+    500
+}
+
+fn default_max_memory_used_percent() -> f32 {
+    90.0
+}
+
+fn default_metrics_window_secs() -> u64 {
+    300
+}
+
+/// Protection of the liquid crystal against static patterns shown for days
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdleConfig {
+    /// Seconds without commands after which the panel goes idle
+    pub timeout_secs: u64,
+    #[serde(flatten)]
+    pub mode: IdleMode,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum IdleMode {
+    /// Shift the whole pattern through a full phase cycle every `period_secs`
+    Drift { period_secs: u64 },
+    /// Alternate between the pattern and its inverse every `period_secs`,
+    /// so the average gray level is balanced
+    Invert { period_secs: u64 },
 }
-#[derive(Serialize, Debug, Clone)]
-pub struct BasePattern {
-    pub filename: String,
-    pub properties: HashMap<String, String>,
+
+fn default_sysfs_scale() -> f32 {
+    1.0
 }
 
-#[serde(untagged)]
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub enum PatternParams {
-    Spot {
-        spot: SpotPattern,
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum SensorSource {
+    /// Run a command that prints the temperature in degrees Celsius
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
     },
-    Custom {
-        custom: CustomPattern,
+    /// Read a number from a file, e.g. sysfs thermal zones report millidegrees (scale 0.001)
+    Sysfs {
+        path: PathBuf,
+        #[serde(default = "default_sysfs_scale")]
+        scale: f32,
     },
-    Base {
-        #[serde(flatten)]
-        base: BasePattern,
+    /// Send a query over a serial port and parse the reply line
+    Serial {
+        port: String,
+        baud_rate: u32,
+        query: String,
     },
 }
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SensorConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: SensorSource,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TemperatureScaling {
+    /// Name of the sensor the correction is based on
+    pub sensor: String,
+    /// (temperature in degrees Celsius, scale factor multiplier) pairs,
+    /// linearly interpolated; sorted by temperature when the config is read
+    pub table: Vec<(f32, f32)>,
+}
+
+/// Only the calibration software may reboot or update the PC, change corrections,
+/// switch to maintenance mode or restore backups (which hold the corrections),
+/// and only the GUI may change the custom patterns or create backups
+fn default_permissions() -> HashMap<String, Vec<String>> {
+    let calibration = vec!["calibration/aim".to_owned()];
+    let gui = vec!["gui/aim".to_owned()];
+    vec![
+        ("reboot", calibration.clone()),
+        ("update", calibration.clone()),
+        ("setMaintenanceMode", calibration.clone()),
+        ("setCorrectionPatternDeltas", calibration.clone()),
+        ("setCorrectionPatternDeltasBatch", calibration.clone()),
+        ("adddefectmask", calibration.clone()),
+        ("removedefectmask", calibration.clone()),
+        ("restoreBackup", calibration),
+        ("uploadimage", gui.clone()),
+        ("deleteimage", gui.clone()),
+        ("derivePattern", gui.clone()),
+        ("createBackup", gui),
+    ]
+    .into_iter()
+    .map(|(command, subtopics)| (command.to_owned(), subtopics))
+    .collect()
+}
+
+/// The configured permissions over the defaults, so listing one command doesn't
+/// lift the restrictions on the others
+fn merge_permissions<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<HashMap<String, Vec<String>>, D::Error> {
+    let mut permissions = default_permissions();
+    permissions.extend(HashMap::<String, Vec<String>>::deserialize(deserializer)?);
+    Ok(permissions)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    pub microscope: Microscope,
+    pub dir_path: DirPath,
+    pub mqtt: MqttConfig,
+    pub screen: ScreenConfig,
+    #[serde(default)]
+    pub display: DisplayBackend,
+    pub overdrive: Option<OverdriveConfig>,
+    pub shared_memory: Option<SharedMemoryConfig>,
+    pub photodiode: Option<PhotodiodeConfig>,
+    pub compute_pattern: PatternComputationConfig,
+    pub image_file_extensions: Vec<String>,
+    pub logging: Logging,
+    pub defaults: DefaultState,
+    pub gamepad: Option<GamepadConfig>,
+    /// Named pattern + fresnel presets
+    #[serde(default)]
+    pub presets: HashMap<String, AimState>,
+    /// Preset to apply when the laser with the given wavelength becomes active
+    #[serde(default)]
+    pub laser_presets: HashMap<u32, String>,
+    #[serde(default)]
+    pub laser_selection: LaserSelectionConfig,
+    pub status: Option<StatusConfig>,
+    #[serde(default)]
+    pub sensors: Vec<SensorConfig>,
+    /// How often the sensors are read, independent of the status interval
+    #[serde(default = "default_sensor_interval_secs")]
+    pub sensor_interval_secs: u64,
+    pub temperature_scaling: Option<TemperatureScaling>,
+    /// Subtopics (e.g. `"calibration/aim"`) each command is accepted from, by command name;
+    /// commands that aren't listed are accepted from any subtopic; entries replace
+    /// the defaults of their command only
+    #[serde(
+        default = "default_permissions",
+        deserialize_with = "merge_permissions"
+    )]
+    pub permissions: HashMap<String, Vec<String>>,
+    /// Maximum number of commands per second, by command name;
+    /// commands over the limit are dropped and reported as a warning
+    #[serde(default)]
+    pub rate_limits: HashMap<String, f32>,
+    #[serde(default)]
+    pub blaze: BlazeConfig,
+    #[serde(default)]
+    pub safety: SafetyConfig,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// Profile active at startup
+    pub profile: Option<String>,
+    /// Tasks run every day; more can be added over MQTT
+    #[serde(default)]
+    pub schedule: Vec<ScheduledTask>,
+    /// Convention for the positions of computed patterns and scans,
+    /// reported to clients with `getCapabilities`
+    #[serde(default)]
+    pub coordinates: CoordinateSystem,
+    #[serde(default)]
+    pub slm_geometry: SlmGeometry,
+    /// Burn-in protection while no commands arrive; off if not set
+    pub idle: Option<IdleConfig>,
+    #[serde(default)]
+    pub control: ControlConfig,
+    /// Seconds after `PreStack` without a follow-up command (e.g. because the
+    /// acquisition crashed) until the previous state is restored; no limit if not set
+    pub prestack_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub clock: ClockConfig,
+    /// Commands are also read from files dropped here; off if not set
+    pub drop_dir: Option<DropDirConfig>,
+    pub zeromq: Option<ZeroMqConfig>,
+    pub grpc: Option<GrpcConfig>,
+    pub video_stream: Option<VideoStreamConfig>,
+    pub session_log: Option<SessionLogConfig>,
+    pub raw_frames: Option<RawFramesConfig>,
+    /// Frames saved with `captureFrame` go to a directory per run in here
+    #[serde(default = "default_capture_dir")]
+    pub capture_dir: PathBuf,
+    /// Archives made with `createBackup` go in here
+    #[serde(default = "default_backup_dir")]
+    pub backup_dir: PathBuf,
+    /// Scan trajectories with more points are rejected
+    #[serde(default = "default_max_scan_points")]
+    pub max_scan_points: usize,
+}
+
+fn default_sensor_interval_secs() -> u64 {
+    10
+}
+
+fn default_capture_dir() -> PathBuf {
+    PathBuf::from("captures")
+}
+
+fn default_backup_dir() -> PathBuf {
+    PathBuf::from("backups")
+}
+
+fn default_max_scan_points() -> usize {
+    100_000
+}
+
+impl Config {
+    pub fn main_topic(&self) -> &str {
+        &self.microscope.serial_nr
+    }
+
+    /// Whether `command` may be sent on `topic`
+    pub fn is_permitted(&self, command: &str, topic: &str) -> bool {
+        match self.permissions.get(command) {
+            Some(subtopics) => subtopics
+                .iter()
+                .any(|subtopic| self.main_topic().subtopic(subtopic) == topic),
+            None => true,
+        }
+    }
+
+    /// Whether commands sent on `topic` bypass control held by a client
+    pub fn overrides_control(&self, topic: &str) -> bool {
+        self.control
+            .override_subtopics
+            .iter()
+            .any(|subtopic| self.main_topic().subtopic(subtopic) == topic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_permissions_keep_the_other_defaults() {
+        let permissions =
+            merge_permissions(serde_json::json!({ "setprofile": ["gui/aim"] })).unwrap();
+        assert_eq!(permissions["setprofile"], ["gui/aim"]);
+        assert_eq!(permissions["reboot"], ["calibration/aim"]);
+        assert_eq!(permissions["update"], ["calibration/aim"]);
+    }
+
+    #[test]
+    fn configured_permissions_replace_their_defaults() {
+        let permissions = merge_permissions(serde_json::json!({
+            "reboot": ["calibration/aim", "gui/aim"]
+        }))
+        .unwrap();
+        assert_eq!(permissions["reboot"], ["calibration/aim", "gui/aim"]);
+        assert_eq!(permissions.len(), default_permissions().len());
+    }
+}
@@ -0,0 +1,136 @@
+//! `send` subcommand: publish one command to a running controller and wait for
+//! its acknowledgement, so scripts don't have to assemble the JSON by hand
+
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use mqtt::{Client, ConnectOptionsBuilder, Message as MqttMessage};
+
+use crate::{
+    compression,
+    schema::{AimCommand, Message, MessageData, MessageType},
+    util::Subtopic,
+    Result, SlmError,
+};
+
+const USAGE: &str = "Usage: rasp_pi send [--broker <URI>] [--subtopic <subtopic>] \
+                     [--timeout <seconds>] <serial number> <command JSON>";
+
+struct SendOptions {
+    broker: String,
+    /// Controllers keep one sequence number per subtopic, so this one
+    /// shouldn't be shared with other clients
+    subtopic: String,
+    timeout: Duration,
+    serial_nr: String,
+    command: AimCommand,
+}
+
+fn parse_args(args: &[String]) -> Result<SendOptions> {
+    let usage = || SlmError::Request(USAGE.to_owned());
+    let mut broker = "tcp://localhost:1883".to_owned();
+    let mut subtopic = "cli/aim".to_owned();
+    let mut timeout = Duration::from_secs(10);
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--broker" => broker = args.next().ok_or_else(usage)?.clone(),
+            "--subtopic" => subtopic = args.next().ok_or_else(usage)?.clone(),
+            "--timeout" => {
+                let secs = args.next().ok_or_else(usage)?;
+                timeout = Duration::from_secs(secs.parse().map_err(|_| usage())?);
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    match positional.as_slice() {
+        [serial_nr, command] => Ok(SendOptions {
+            broker,
+            subtopic,
+            timeout,
+            serial_nr: serial_nr.to_string(),
+            command: serde_json::from_str(command)?,
+        }),
+        _ => Err(usage()),
+    }
+}
+
+/// Publish the command with a fresh sequence number and print the controller's
+/// messages until it acknowledges the command or reports that it failed
+pub fn send(args: &[String]) -> Result<()> {
+    let options = parse_args(args)?;
+    let mut client = Client::new(options.broker.as_str())?;
+    client.connect(ConnectOptionsBuilder::new().clean_session(true).finalize())?;
+    let messages = client.start_consuming();
+    let topic_aim = options.serial_nr.as_str().subtopic("aim");
+    client.subscribe(&topic_aim, 0)?;
+
+    // milliseconds since the epoch keep increasing between invocations
+    let seq = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| SlmError::Request(err.to_string()))?
+        .as_millis() as u64;
+    let message = Message {
+        m_type: MessageType::Device,
+        seq: Some(seq),
+        expect: None,
+        client: None,
+        data: MessageData::Aim(options.command),
+    };
+    let topic = options.serial_nr.as_str().subtopic(&options.subtopic);
+    client.publish(MqttMessage::new(&topic, serde_json::to_vec(&message)?, 0))?;
+
+    let deadline = Instant::now() + options.timeout;
+    let result = loop {
+        let remaining = match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) => remaining,
+            None => {
+                break Err(SlmError::Request(format!(
+                    "No acknowledgement within {} s",
+                    options.timeout.as_secs()
+                )))
+            }
+        };
+        let mqtt_message = match messages.recv_timeout(remaining) {
+            Ok(Some(mqtt_message)) => mqtt_message,
+            // the deadline is checked on the next pass
+            Err(RecvTimeoutError::Timeout) => continue,
+            // paho sends None when the connection is lost
+            Ok(None) | Err(RecvTimeoutError::Disconnected) => {
+                break Err(SlmError::Request(
+                    "Lost the connection to the broker before the acknowledgement".to_owned(),
+                ))
+            }
+        };
+        if mqtt_message.topic() != topic_aim {
+            continue;
+        }
+        let payload = compression::decompress(
+            mqtt_message.payload(),
+            compression::DEFAULT_MAX_DECOMPRESSED_BYTES,
+        )?;
+        println!("{}", String::from_utf8_lossy(&payload));
+        match serde_json::from_slice::<Message>(&payload) {
+            Ok(Message {
+                data: MessageData::Aim(AimCommand::Ack { seq: acked, .. }),
+                ..
+            }) if acked == seq => break Ok(()),
+            Ok(Message {
+                data:
+                    MessageData::Aim(AimCommand::Error {
+                        message,
+                        seq: Some(failed),
+                        ..
+                    }),
+                ..
+            }) if failed == seq => break Err(SlmError::Request(message)),
+            _ => (),
+        }
+    };
+
+    client.disconnect(None)?;
+    result
+}
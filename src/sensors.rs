@@ -0,0 +1,253 @@
+//! Panel and driver temperature sensors, read on a worker thread since
+//! commands and serial sensors can take a while to answer
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use log::error;
+use serialport::{SerialPort, SerialPortSettings};
+
+use crate::{
+    schema::{SensorConfig, SensorReading, SensorSource},
+    Context, Result, SlmError,
+};
+
+pub trait TemperatureSensor: Send {
+    fn name(&self) -> &str;
+    fn read_celsius(&mut self) -> Result<f32>;
+}
+
+fn parse_temperature(text: &str) -> Result<f32> {
+    text.trim()
+        .parse::<f32>()
+        .map_err(|err| SlmError::Sensor(format!("Can't parse temperature {:?}: {}", text, err)))
+}
+
+struct CommandSensor {
+    name: String,
+    command: String,
+    args: Vec<String>,
+}
+
+impl TemperatureSensor for CommandSensor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_celsius(&mut self) -> Result<f32> {
+        let output = Command::new(&self.command).args(&self.args).output()?;
+        if !output.status.success() {
+            Err(SlmError::Sensor(format!(
+                "{} exited with {}",
+                self.command, output.status
+            )))?
+        }
+        parse_temperature(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+struct SysfsSensor {
+    name: String,
+    path: PathBuf,
+    scale: f32,
+}
+
+impl TemperatureSensor for SysfsSensor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_celsius(&mut self) -> Result<f32> {
+        Ok(parse_temperature(&fs::read_to_string(&self.path)?)? * self.scale)
+    }
+}
+
+struct SerialSensor {
+    name: String,
+    port_name: String,
+    baud_rate: u32,
+    query: String,
+    // Opened on first use, and reopened after an error
+    port: Option<Box<dyn SerialPort>>,
+}
+
+impl SerialSensor {
+    fn query(&mut self) -> Result<String> {
+        if self.port.is_none() {
+            let settings = SerialPortSettings {
+                baud_rate: self.baud_rate,
+                timeout: Duration::from_millis(500),
+                ..Default::default()
+            };
+            self.port = Some(serialport::open_with_settings(&self.port_name, &settings)?);
+        }
+        let port = self.port.as_mut().unwrap();
+
+        port.write_all(self.query.as_bytes())?;
+
+        let mut reply = Vec::new();
+        let mut byte = [0; 1];
+        while byte[0] != b'\n' {
+            port.read_exact(&mut byte)?;
+            reply.push(byte[0]);
+        }
+        Ok(String::from_utf8_lossy(&reply).into_owned())
+    }
+}
+
+impl TemperatureSensor for SerialSensor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_celsius(&mut self) -> Result<f32> {
+        let reply = self.query();
+        if reply.is_err() {
+            self.port = None;
+        }
+        parse_temperature(&reply?)
+    }
+}
+
+fn open_sensors(configs: &[SensorConfig]) -> Vec<Box<dyn TemperatureSensor>> {
+    configs
+        .iter()
+        .map(|config| -> Box<dyn TemperatureSensor> {
+            let name = config.name.clone();
+            match config.source.clone() {
+                SensorSource::Command { command, args } => Box::new(CommandSensor {
+                    name,
+                    command,
+                    args,
+                }),
+                SensorSource::Sysfs { path, scale } => Box::new(SysfsSensor { name, path, scale }),
+                SensorSource::Serial {
+                    port,
+                    baud_rate,
+                    query,
+                } => Box::new(SerialSensor {
+                    name,
+                    port_name: port,
+                    baud_rate,
+                    query,
+                    port: None,
+                }),
+            }
+        })
+        .collect()
+}
+
+fn read_sensors(sensors: &mut [Box<dyn TemperatureSensor>]) -> Vec<SensorReading> {
+    sensors
+        .iter_mut()
+        .map(|sensor| SensorReading {
+            name: sensor.name().to_owned(),
+            celsius: sensor
+                .read_celsius()
+                .map_err(|err| error!("Can't read sensor {}: {}", sensor.name(), err))
+                .ok(),
+        })
+        .collect()
+}
+
+/// Readings of all sensors every `interval`, from the worker thread
+pub struct SensorPoller {
+    readings: Receiver<Vec<SensorReading>>,
+}
+
+impl SensorPoller {
+    /// `None` without sensors; the thread ends with the poller
+    pub fn spawn(configs: &[SensorConfig], interval: Duration) -> Option<Self> {
+        if configs.is_empty() {
+            return None;
+        }
+        let mut sensors = open_sensors(configs);
+        let (sender, readings) = mpsc::channel();
+        thread::spawn(move || {
+            while sender.send(read_sensors(&mut sensors)).is_ok() {
+                thread::sleep(interval);
+            }
+        });
+        Some(SensorPoller { readings })
+    }
+}
+
+impl<'a> Context<'a> {
+    /// Take the latest readings into use; failed sensors have no temperature
+    pub fn poll_sensors(&mut self, poller: &SensorPoller) {
+        for readings in poller.readings.try_iter() {
+            for reading in readings {
+                match reading.celsius {
+                    Some(celsius) => self.state.temperatures.insert(reading.name, celsius),
+                    None => self.state.temperatures.remove(&reading.name),
+                };
+            }
+        }
+    }
+
+    /// Latest reading of each configured sensor
+    pub fn sensor_readings(&self) -> Vec<SensorReading> {
+        self.config
+            .sensors
+            .iter()
+            .map(|sensor| SensorReading {
+                name: sensor.name.clone(),
+                celsius: self.state.temperatures.get(&sensor.name).copied(),
+            })
+            .collect()
+    }
+}
+
+/// Linearly interpolate a (temperature, value) table, clamping outside of its range
+pub fn interpolate(table: &[(f32, f32)], temperature: f32) -> Option<f32> {
+    let first = table.first()?;
+    let last = table.last()?;
+    if temperature <= first.0 {
+        return Some(first.1);
+    }
+    if temperature >= last.0 {
+        return Some(last.1);
+    }
+
+    table.windows(2).find_map(|pair| {
+        let ((t0, v0), (t1, v1)) = (pair[0], pair[1]);
+        if temperature >= t0 && temperature <= t1 {
+            Some(v0 + (v1 - v0) * (temperature - t0) / (t1 - t0))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: [(f32, f32); 3] = [(20.0, 1.0), (30.0, 1.1), (40.0, 1.3)];
+
+    #[test]
+    fn interpolates_between_entries() {
+        assert_eq!(interpolate(&TABLE, 20.0), Some(1.0));
+        assert!((interpolate(&TABLE, 25.0).unwrap() - 1.05).abs() < 1e-6);
+        assert_eq!(interpolate(&TABLE, 30.0), Some(1.1));
+        assert!((interpolate(&TABLE, 37.5).unwrap() - 1.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clamps_outside_of_the_table() {
+        assert_eq!(interpolate(&TABLE, -10.0), Some(1.0));
+        assert_eq!(interpolate(&TABLE, 100.0), Some(1.3));
+        assert_eq!(interpolate(&[(25.0, 2.0)], 0.0), Some(2.0));
+        assert_eq!(interpolate(&[(25.0, 2.0)], 50.0), Some(2.0));
+    }
+
+    #[test]
+    fn empty_table_has_no_value() {
+        assert_eq!(interpolate(&[], 25.0), None);
+    }
+}
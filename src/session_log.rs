@@ -0,0 +1,252 @@
+//! HDF5 file per session with every displayed pattern, its timestamp and the
+//! state, for traceability-heavy experiments; needs the hdf5 feature.
+//!
+//! Frames are datasets `frame_000001`, ... with `timestamp` (RFC 3339) and
+//! `state` (JSON) string attributes, indexed by (x, y) like the npy files. A new
+//! file is started once the current one exceeds the size cap.
+
+#[cfg(feature = "hdf5")]
+use log::info;
+
+#[cfg(feature = "hdf5")]
+use crate::schema::SessionLogConfig;
+use crate::{Context, Result, SlmError};
+
+#[cfg(feature = "hdf5")]
+mod h5 {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::raw::c_void;
+    use std::path::Path;
+
+    use hdf5_sys::{
+        h5::{hsize_t, H5open},
+        h5a::{H5Aclose, H5Acreate2, H5Awrite},
+        h5d::{H5Dclose, H5Dcreate2, H5Dwrite},
+        h5f::{H5Fclose, H5Fcreate, H5Fget_filesize, H5F_ACC_TRUNC},
+        h5i::hid_t,
+        h5p::{
+            H5Pclose, H5Pcreate, H5Pset_chunk, H5Pset_deflate, H5P_CLS_DATASET_CREATE, H5P_DEFAULT,
+        },
+        h5s::{H5S_class_t, H5Sclose, H5Screate, H5Screate_simple, H5S_ALL},
+        h5t::{H5Tclose, H5Tcopy, H5Tset_size, H5T_C_S1, H5T_NATIVE_UINT8},
+    };
+
+    use crate::{Result, SlmError};
+
+    /// HDF5 returns negative values on failure
+    fn check<T: Into<i64> + Copy>(value: T, function: &str) -> Result<T> {
+        if value.into() < 0 {
+            Err(SlmError::Io(io::Error::other(format!(
+                "{} failed",
+                function
+            ))))
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn c_string(string: &str) -> Result<CString> {
+        CString::new(string)
+            .map_err(|err| SlmError::Io(io::Error::new(io::ErrorKind::InvalidInput, err)))
+    }
+
+    pub struct File(hid_t);
+
+    impl File {
+        pub fn create(path: &Path) -> Result<Self> {
+            let path = c_string(&path.to_string_lossy())?;
+            unsafe {
+                check(H5open(), "H5open")?;
+                let file = H5Fcreate(path.as_ptr(), H5F_ACC_TRUNC, H5P_DEFAULT, H5P_DEFAULT);
+                Ok(File(check(file, "H5Fcreate")?))
+            }
+        }
+
+        pub fn size(&self) -> Result<u64> {
+            let mut size: hsize_t = 0;
+            unsafe { check(H5Fget_filesize(self.0, &mut size), "H5Fget_filesize")? };
+            Ok(size as u64)
+        }
+
+        /// Write a zlib-compressed 2D `u8` dataset with string attributes
+        pub fn write_frame(
+            &self,
+            name: &str,
+            (width, height): (usize, usize),
+            pixels: &[u8],
+            compression: u32,
+            attributes: &[(&str, &str)],
+        ) -> Result<()> {
+            let name = c_string(name)?;
+            let dims = [width as hsize_t, height as hsize_t];
+            unsafe {
+                let space = check(
+                    H5Screate_simple(2, dims.as_ptr(), dims.as_ptr()),
+                    "H5Screate_simple",
+                )?;
+                let plist = check(H5Pcreate(*H5P_CLS_DATASET_CREATE), "H5Pcreate")?;
+                check(H5Pset_chunk(plist, 2, dims.as_ptr()), "H5Pset_chunk")?;
+                check(H5Pset_deflate(plist, compression), "H5Pset_deflate")?;
+                let dataset = H5Dcreate2(
+                    self.0,
+                    name.as_ptr(),
+                    *H5T_NATIVE_UINT8,
+                    space,
+                    H5P_DEFAULT,
+                    plist,
+                    H5P_DEFAULT,
+                );
+                H5Pclose(plist);
+                H5Sclose(space);
+                let dataset = check(dataset, "H5Dcreate2")?;
+                let result = check(
+                    H5Dwrite(
+                        dataset,
+                        *H5T_NATIVE_UINT8,
+                        H5S_ALL,
+                        H5S_ALL,
+                        H5P_DEFAULT,
+                        pixels.as_ptr() as *const c_void,
+                    ),
+                    "H5Dwrite",
+                )
+                .and_then(|_| {
+                    for (name, value) in attributes {
+                        write_string_attribute(dataset, name, value)?;
+                    }
+                    Ok(())
+                });
+                H5Dclose(dataset);
+                result
+            }
+        }
+    }
+
+    /// Fixed-length string attribute, which every HDF5 reader supports
+    unsafe fn write_string_attribute(object: hid_t, name: &str, value: &str) -> Result<()> {
+        let name = c_string(name)?;
+        let string_type = check(H5Tcopy(*H5T_C_S1), "H5Tcopy")?;
+        check(H5Tset_size(string_type, value.len().max(1)), "H5Tset_size")?;
+        let space = check(H5Screate(H5S_class_t::H5S_SCALAR), "H5Screate")?;
+        let attribute = H5Acreate2(
+            object,
+            name.as_ptr(),
+            string_type,
+            space,
+            H5P_DEFAULT,
+            H5P_DEFAULT,
+        );
+        let result = check(attribute, "H5Acreate2").and_then(|attribute| {
+            let written = check(
+                H5Awrite(attribute, string_type, value.as_ptr() as *const c_void),
+                "H5Awrite",
+            );
+            H5Aclose(attribute);
+            written.map(|_| ())
+        });
+        H5Sclose(space);
+        H5Tclose(string_type);
+        result
+    }
+
+    impl Drop for File {
+        fn drop(&mut self) {
+            unsafe { H5Fclose(self.0) };
+        }
+    }
+}
+
+#[cfg(feature = "hdf5")]
+pub struct SessionLog {
+    file: h5::File,
+    /// Start of the session, in the file names
+    started: String,
+    part: u32,
+    frames: u64,
+}
+
+#[cfg(feature = "hdf5")]
+impl SessionLog {
+    fn open_part(config: &SessionLogConfig, started: &str, part: u32) -> Result<h5::File> {
+        std::fs::create_dir_all(&config.directory)?;
+        let path = config
+            .directory
+            .join(format!("session-{}-{:03}.h5", started, part));
+        info!("Logging displayed patterns to {}", path.display());
+        h5::File::create(&path)
+    }
+}
+
+impl<'a> Context<'a> {
+    /// Start the session file, so a broken log directory is reported at startup
+    #[cfg(feature = "hdf5")]
+    pub fn open_session_log(&mut self) -> Result<()> {
+        let config = match &self.config.session_log {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        if config.downsample == 0 || config.compression > 9 {
+            Err(SlmError::Config(format!(
+                "Invalid session log downsampling {} or compression {}",
+                config.downsample, config.compression
+            )))?
+        }
+        let started = self.state.capture_session.clone();
+        self.state.session_log = Some(SessionLog {
+            file: SessionLog::open_part(config, &started, 1)?,
+            started,
+            part: 1,
+            frames: 0,
+        });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "hdf5"))]
+    pub fn open_session_log(&mut self) -> Result<()> {
+        match self.config.session_log {
+            Some(_) => Err(SlmError::Config(
+                "Session logging needs the hdf5 feature".to_owned(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Append a displayed pattern to the session file
+    #[cfg(feature = "hdf5")]
+    pub fn log_session_frame(&mut self, pattern: &ndarray::Array2<u8>) -> Result<()> {
+        let config = match &self.config.session_log {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        let state = serde_json::to_string(&self.state_report()?)?;
+        let log = match &mut self.state.session_log {
+            Some(log) => log,
+            None => return Ok(()),
+        };
+        if log.file.size()? >= config.max_file_mb * 1024 * 1024 {
+            log.part += 1;
+            log.file = SessionLog::open_part(config, &log.started, log.part)?;
+        }
+
+        let step = config.downsample as isize;
+        let downsampled = pattern.slice(ndarray::s![..;step, ..;step]);
+        let pixels: Vec<u8> = downsampled.iter().copied().collect();
+        log.frames += 1;
+        log.file.write_frame(
+            &format!("frame_{:06}", log.frames),
+            downsampled.dim(),
+            &pixels,
+            config.compression,
+            &[
+                ("timestamp", &chrono::Local::now().to_rfc3339()),
+                ("state", &state),
+            ],
+        )
+    }
+
+    #[cfg(not(feature = "hdf5"))]
+    pub fn log_session_frame(&mut self, _pattern: &ndarray::Array2<u8>) -> Result<()> {
+        Ok(())
+    }
+}
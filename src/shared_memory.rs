@@ -0,0 +1,114 @@
+//! Shown patterns written to a named shared-memory segment, for an external
+//! process driving the SLM while this controller computes the patterns.
+//!
+//! The segment starts with a little-endian header: a `u64` frame counter,
+//! then the `u32` width and height. The gray levels follow row by row. The
+//! counter is odd while a frame is written and even once it's complete, so a
+//! reader copies the frame when the counter is even and unchanged afterwards.
+//! The segment is resized in place if the pattern size changes, with the
+//! counter odd meanwhile, so readers remap it when the header size changes.
+
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+use memmap2::MmapMut;
+
+use crate::{Context, Result};
+
+const HEADER_SIZE: usize = 16;
+
+pub struct SharedFrames {
+    file: File,
+    map: MmapMut,
+    size: (usize, usize),
+    counter: u64,
+}
+
+fn segment_path(name: &str) -> PathBuf {
+    if cfg!(target_os = "linux") {
+        PathBuf::from("/dev/shm").join(name)
+    } else {
+        std::env::temp_dir().join(name)
+    }
+}
+
+impl SharedFrames {
+    fn create(name: &str, size: (usize, usize)) -> Result<Self> {
+        // not truncated, readers may still have it mapped
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(segment_path(name))?;
+        if file.metadata()?.len() < HEADER_SIZE as u64 {
+            file.set_len(HEADER_SIZE as u64)?;
+        }
+        // other processes only read it
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        // the counter of a segment left by an earlier run keeps increasing
+        let mut counter = [0; 8];
+        counter.copy_from_slice(&map[0..8]);
+        let counter = (u64::from_le_bytes(counter) + 1) & !1;
+        let mut frames = SharedFrames {
+            file,
+            map,
+            size: (0, 0),
+            counter,
+        };
+        frames.resize(size)?;
+        Ok(frames)
+    }
+
+    fn resize(&mut self, (width, height): (usize, usize)) -> Result<()> {
+        self.set_counter(self.counter + 1);
+        fence(Ordering::Release);
+        self.file.set_len((HEADER_SIZE + width * height) as u64)?;
+        self.map = unsafe { MmapMut::map_mut(&self.file)? };
+        self.map[8..12].copy_from_slice(&(width as u32).to_le_bytes());
+        self.map[12..16].copy_from_slice(&(height as u32).to_le_bytes());
+        self.size = (width, height);
+        self.set_counter(self.counter + 1);
+        Ok(())
+    }
+
+    fn set_counter(&mut self, counter: u64) {
+        self.counter = counter;
+        // mappings are page aligned
+        let shared = unsafe { &*(self.map.as_mut_ptr() as *const AtomicU64) };
+        shared.store(counter.to_le(), Ordering::Release);
+    }
+
+    fn write(&mut self, pattern: &ndarray::Array2<u8>) {
+        let (width, _) = self.size;
+        self.set_counter(self.counter + 1);
+        // the pixels aren't written before readers can see the odd counter
+        fence(Ordering::Release);
+        let pixels = &mut self.map[HEADER_SIZE..];
+        for ((x, y), value) in pattern.indexed_iter() {
+            pixels[y * width + x] = *value;
+        }
+        self.set_counter(self.counter + 1);
+    }
+}
+
+impl<'a> Context<'a> {
+    /// Write a shown pattern to the shared-memory segment, if one is configured
+    pub fn export_shared_memory(&mut self, pattern: &ndarray::Array2<u8>) -> Result<()> {
+        let config = match &self.config.shared_memory {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        let frames = match self.state.shared_frames.take() {
+            Some(frames) => frames,
+            None => SharedFrames::create(&config.name, pattern.dim())?,
+        };
+        let frames = self.state.shared_frames.insert(frames);
+        if frames.size != pattern.dim() {
+            frames.resize(pattern.dim())?;
+        }
+        frames.write(pattern);
+        Ok(())
+    }
+}
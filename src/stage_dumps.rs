@@ -0,0 +1,87 @@
+//! Images of the pattern after each stage of its computation, for finding the
+//! stage that introduces an artefact
+
+use std::collections::BTreeSet;
+use std::fs;
+
+use chrono::Local;
+use log::error;
+use ndarray::Array2;
+
+use crate::{
+    phase::{quantize, Phase, IMAGE_LEVELS},
+    schema::PatternComputationDebug,
+    Result,
+};
+
+/// Stages of one update, collected only if dumping is enabled
+pub struct StageDumps {
+    stages: Option<Vec<(&'static str, Array2<u8>)>>,
+}
+
+impl StageDumps {
+    pub fn new(debug: Option<&PatternComputationDebug>) -> Self {
+        Self {
+            stages: debug.filter(|debug| debug.dump_stages).map(|_| Vec::new()),
+        }
+    }
+
+    /// Record the phase after `stage` in the gray levels of an image
+    pub fn add<T: Phase>(&mut self, stage: &'static str, pattern: &Array2<T>) {
+        if let Some(stages) = &mut self.stages {
+            let levels = T::from_f32(IMAGE_LEVELS);
+            stages.push((stage, pattern.mapv(|e| quantize(e, levels))));
+        }
+    }
+
+    /// Write the recorded stages and the quantized pattern; errors are only
+    /// logged, the pattern is shown regardless
+    pub fn finish(self, debug: Option<&PatternComputationDebug>, quantized: &Array2<u8>) {
+        if let (Some(stages), Some(debug)) = (self.stages, debug) {
+            if let Err(err) = write(debug, stages, quantized) {
+                error!("Error {} while dumping the pattern stages; continuing", err);
+            }
+        }
+    }
+}
+
+/// Images are named `<update time>_<index>_<stage>.png`, so the updates sort
+/// by name and only the latest `max_stage_dumps` are kept
+fn write(
+    debug: &PatternComputationDebug,
+    stages: Vec<(&'static str, Array2<u8>)>,
+    quantized: &Array2<u8>,
+) -> Result<()> {
+    let dir = &debug.stage_dump_dir;
+    fs::create_dir_all(dir)?;
+    let update = Local::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+    let images = stages
+        .iter()
+        .map(|(stage, image)| (*stage, image))
+        .chain(std::iter::once(("quantized", quantized)));
+    for (index, (stage, image)) in images.enumerate() {
+        let path = dir.join(format!("{}_{}_{}.png", update, index, stage));
+        ndarray_image::save_gray_image(path, image.view())?;
+    }
+
+    let mut dumps = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some("png".as_ref()) {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some((update, _)) = name.split_once('_') {
+            dumps.push((update.to_owned(), path.clone()));
+        }
+    }
+    let updates: BTreeSet<_> = dumps.iter().map(|(update, _)| update).collect();
+    let expired = updates.len().saturating_sub(debug.max_stage_dumps);
+    let expired: BTreeSet<_> = updates.into_iter().take(expired).cloned().collect();
+    for (update, path) in &dumps {
+        if expired.contains(update) {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! Summary of the effective configuration, published and logged at startup
+
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+
+use crate::{
+    build_info::build_info,
+    schema::{
+        AimCommand, DirectoryStatus, Message, MessageData, MessageType, SizeMismatch,
+        StartupSummary,
+    },
+    storage::{is_npy, stored_size},
+    Context, Result,
+};
+
+fn directory_status(name: &str, path: &Path) -> Result<DirectoryStatus> {
+    let absolute = std::env::current_dir()?.join(path);
+    let exists = absolute.is_dir();
+    info!(
+        "Directory {}: {}{}",
+        name,
+        absolute.display(),
+        if exists { "" } else { " (missing)" }
+    );
+    Ok(DirectoryStatus {
+        name: name.to_owned(),
+        path: absolute.to_string_lossy().into_owned(),
+        exists,
+    })
+}
+
+impl<'a> Context<'a> {
+    /// Pattern files in `dir` whose size isn't the screen size, found now
+    /// rather than when their wavelength or pattern is selected
+    fn size_mismatches(&self, dir: &Path) -> Vec<SizeMismatch> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            // reported as missing among the directories
+            Err(_) => return vec![],
+        };
+        let mut mismatches = Vec::new();
+        for path in entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            let name = path.to_string_lossy();
+            let is_pattern = is_npy(&path)
+                || self
+                    .config
+                    .image_file_extensions
+                    .iter()
+                    .any(|ext| name.ends_with(ext.as_str()));
+            if !path.is_file() || !is_pattern {
+                continue;
+            }
+            match stored_size(&path) {
+                Ok(size) if size != self.config.screen.size => {
+                    warn!(
+                        "{} is {}x{}, the screen {}x{}",
+                        name, size.0, size.1, self.config.screen.size.0, self.config.screen.size.1
+                    );
+                    mismatches.push(SizeMismatch {
+                        path: name.into_owned(),
+                        size,
+                    });
+                }
+                Ok(_) => (),
+                Err(err) => warn!("Can't read the size of {}: {}", name, err),
+            }
+        }
+        mismatches.sort_by(|a, b| a.path.cmp(&b.path));
+        mismatches
+    }
+
+    pub fn send_startup_summary(&mut self) -> Result<&mut Self> {
+        let dir_path = &self.config.dir_path;
+        let directories = vec![
+            directory_status("base_patterns", &dir_path.base_patterns)?,
+            directory_status(
+                "custom_patterns",
+                &dir_path.base_patterns.join("custom_patterns"),
+            )?,
+            directory_status("flatness_corr_patterns", &dir_path.flatness_corr_patterns)?,
+            directory_status("working", Path::new("."))?,
+        ];
+
+        let screen_size = self.config.screen.size;
+        let screen_mode = self.screen_context.mode;
+        match screen_mode {
+            Some(mode) => info!(
+                "Screen {}x{}, display mode {}x{} at {} Hz",
+                screen_size.0, screen_size.1, mode.size.0, mode.size.1, mode.refresh_hz
+            ),
+            None => info!("Screen {}x{}", screen_size.0, screen_size.1),
+        }
+        let wavelengths = self.available_wavelengths();
+        info!("Calibrated wavelengths: {:?}", wavelengths);
+        let mut size_mismatches =
+            self.size_mismatches(&self.config.dir_path.flatness_corr_patterns);
+        size_mismatches.extend(self.size_mismatches(&self.config.dir_path.base_patterns));
+
+        self.send_aim_message(&Message {
+            m_type: MessageType::Status,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Startup(StartupSummary {
+                build: build_info(),
+                directories,
+                screen_size,
+                screen_mode,
+                wavelengths,
+                size_mismatches,
+            })),
+        })
+    }
+}
@@ -0,0 +1,28 @@
+//! Periodic status message, published independent of any requests
+
+use crate::{
+    build_info::build_info,
+    schema::{AimCommand, Message, MessageData, MessageType, StatusReport},
+    Context, Result,
+};
+
+impl<'a> Context<'a> {
+    pub fn send_status(&mut self, backlog: usize) -> Result<&mut Self> {
+        let health = self.check_health()?;
+        let report = StatusReport {
+            temperatures: self.sensor_readings(),
+            backlog,
+            build: build_info(),
+            idle: self.state.idle.is_idle(),
+            health,
+            commands: self.state.metrics.report(),
+        };
+        self.send_aim_message(&Message {
+            m_type: MessageType::Status,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::Status(Box::new(report))),
+        })
+    }
+}
@@ -0,0 +1,196 @@
+//! Reading and writing phase arrays: grayscale images and (optionally compressed) npy files
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use ndarray::Array2;
+use ndarray_npy::{ReadNpyExt, ReadableElement, WritableElement, WriteNpyExt};
+
+use crate::{
+    phase::{level_phase, quantize, IMAGE_LEVELS},
+    Array, Array64, Dim, Result, SlmError,
+};
+
+pub const NPY_EXTENSION: &str = ".npy";
+/// Extension of losslessly stored, zstd-compressed npy phase arrays
+pub const COMPRESSED_NPY_EXTENSION: &str = ".npy.zst";
+
+fn path_ends_with(path: &Path, suffix: &str) -> bool {
+    path.to_str()
+        .map(|path| path.ends_with(suffix))
+        .unwrap_or(false)
+}
+
+/// Marks the calibration shipped with the panel, which is never changed
+const FACTORY_MARKER: &str = "_factory";
+
+/// Factory files are named `<name>_factory.<extension>`
+pub fn is_factory_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    let stem = match name.strip_suffix(COMPRESSED_NPY_EXTENSION) {
+        Some(stem) => stem,
+        None => name.rsplit_once('.').map_or(name, |(stem, _)| stem),
+    };
+    stem.ends_with(FACTORY_MARKER)
+}
+
+/// Make a factory file read-only
+pub fn protect(path: &Path) -> Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+/// Refuse to write or delete a factory file
+pub fn check_not_factory(path: &Path) -> Result<()> {
+    if is_factory_file(path) {
+        Err(SlmError::Protected(path.display().to_string()))?
+    }
+    Ok(())
+}
+
+/// Make the factory files in `dir` read-only, returning how many there are
+pub fn protect_factory_files(dir: &Path) -> Result<usize> {
+    let mut protected = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_factory_file(&path) {
+            protect(&path)?;
+            protected += 1;
+        }
+    }
+    Ok(protected)
+}
+
+pub fn remove_file(path: &Path) -> Result<()> {
+    check_not_factory(path)?;
+    Ok(fs::remove_file(path)?)
+}
+
+pub fn is_npy(path: &Path) -> bool {
+    path_ends_with(path, NPY_EXTENSION) || path_ends_with(path, COMPRESSED_NPY_EXTENSION)
+}
+
+pub fn read_npy<T: ReadableElement>(path: &Path) -> Result<Array2<T>> {
+    if path_ends_with(path, COMPRESSED_NPY_EXTENSION) {
+        Ok(Array2::<T>::read_npy(zstd::Decoder::new(File::open(
+            path,
+        )?)?)?)
+    } else {
+        Ok(Array2::<T>::read_npy(File::open(path)?)?)
+    }
+}
+
+pub fn save_npy<T: WritableElement>(path: &Path, array: &Array2<T>) -> Result<()> {
+    check_not_factory(path)?;
+    if path_ends_with(path, COMPRESSED_NPY_EXTENSION) {
+        let mut encoder = zstd::Encoder::new(File::create(path)?, 0)?;
+        array.write_npy(&mut encoder)?;
+        encoder.finish()?;
+    } else {
+        array.write_npy(File::create(path)?)?;
+    }
+    Ok(())
+}
+
+/// The `shape` of an npy header, read without the data
+fn read_npy_shape(mut reader: impl Read) -> Result<Vec<usize>> {
+    let invalid = |what: &str| SlmError::Pattern(format!("Invalid npy header: {}", what));
+    let mut preamble = [0; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != b"\x93NUMPY" {
+        Err(invalid("no magic string"))?
+    }
+    let header_len = if preamble[6] == 1 {
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        u16::from_le_bytes(len) as usize
+    } else {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        u32::from_le_bytes(len) as usize
+    };
+    let mut header = vec![0; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+    let shape = header
+        .split("'shape':")
+        .nth(1)
+        .and_then(|rest| rest.split(')').next())
+        .map(|shape| shape.trim().trim_start_matches('('))
+        .ok_or_else(|| invalid("no shape"))?;
+    shape
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().map_err(|_| invalid("shape isn't numeric")))
+        .collect()
+}
+
+/// Panel size (x, y) of a stored phase array or image, from its header
+pub fn stored_size(path: &Path) -> Result<(u32, u32)> {
+    if !is_npy(path) {
+        // the rows of an image run along panel x when it is read, see `read_image_from_file`
+        let (width, height) = image::image_dimensions(path)?;
+        return Ok((height, width));
+    }
+    let shape = if path_ends_with(path, COMPRESSED_NPY_EXTENSION) {
+        read_npy_shape(zstd::Decoder::new(File::open(path)?)?)?
+    } else {
+        read_npy_shape(File::open(path)?)?
+    };
+    // x along the first axis, like the arrays of the panel
+    match shape[..] {
+        [size_x, size_y] => Ok((size_x as u32, size_y as u32)),
+        _ => Err(SlmError::Pattern(format!(
+            "{} is not a 2d array",
+            path.display()
+        )))?,
+    }
+}
+
+/// Read a stored phase array, which may have been saved in either precision
+pub fn read_npy_phase(path: &Path) -> Result<Array64> {
+    match read_npy::<f64>(path) {
+        Ok(array) => Ok(array),
+        Err(_) => Ok(read_npy::<f32>(path)?.mapv(f64::from)),
+    }
+}
+
+pub fn read_image_from_file(path: &Path, dim: Option<Dim>) -> Result<Array> {
+    if is_npy(path) {
+        let array = read_npy_phase(path)?;
+        return Ok(match dim {
+            Some(dim) => {
+                Array::from_shape_fn(dim, |id| array.get(id).map(|&e| e as f32).unwrap_or(0.0))
+            }
+            None => array.mapv(|e| e as f32),
+        });
+    }
+
+    // a 2d array with !u8! elements
+    let array = ndarray_image::open_gray_image(path)?;
+
+    let normalize = |e: &u8| level_phase(*e, IMAGE_LEVELS);
+
+    if let Some(dim) = dim {
+        Ok(Array::from_shape_fn(dim, |id| {
+            array.get(id).map(normalize).unwrap_or(0.0)
+        }))
+    } else {
+        Ok(array.map(normalize))
+    }
+}
+
+pub fn save_image(path: &Path, array: &Array) -> Result<()> {
+    check_not_factory(path)?;
+    Ok(ndarray_image::save_gray_image(
+        path,
+        array.mapv(|e| quantize(e, IMAGE_LEVELS)).view(),
+    )?)
+}
@@ -0,0 +1,125 @@
+//! Fresnel sweeps with a fixed dwell, so an autofocus routine in the
+//! acquisition software only has to follow the published step indices
+
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::{
+    schema::{AimCommand, Message, MessageData, MessageType, ResponseCode},
+    Context, Result, SlmError,
+};
+
+pub struct FresnelSweepRun {
+    values: Vec<u32>,
+    index: usize,
+    dwell: Duration,
+    shown_at: Instant,
+    /// Fresnel restored once the sweep is over
+    previous: u32,
+}
+
+/// `steps` values evenly spaced from `from` to `to`, both included
+fn sweep_values(from: u32, to: u32, steps: u32) -> Vec<u32> {
+    if steps == 1 {
+        return vec![from];
+    }
+    (0..steps)
+        .map(|step| {
+            let t = step as f32 / (steps - 1) as f32;
+            (from as f32 + (to as f32 - from as f32) * t).round() as u32
+        })
+        .collect()
+}
+
+impl<'a> Context<'a> {
+    fn show_sweep_step(&mut self, run: &mut FresnelSweepRun) -> Result<()> {
+        let fresnel = run.values[run.index];
+        self.update_state(None, Some(fresnel), None)?;
+        run.shown_at = Instant::now();
+        self.checkpoint(run.index as u32);
+        self.send_aim_message(&Message {
+            m_type: MessageType::Device,
+            seq: None,
+            expect: None,
+            client: None,
+            data: MessageData::Aim(AimCommand::SweepStep {
+                index: run.index as u32,
+                total: run.values.len() as u32,
+                fresnel,
+            }),
+        })?;
+        Ok(())
+    }
+
+    /// `first` is the index of the first step shown, when resuming
+    pub fn start_fresnel_sweep(
+        &mut self,
+        from: u32,
+        to: u32,
+        steps: u32,
+        dwell_ms: u64,
+        first: u32,
+    ) -> Result<&mut Self> {
+        if steps == 0 {
+            Err(SlmError::Request("Fresnel sweep needs steps".to_owned()))?
+        }
+        if first >= steps {
+            Err(SlmError::Request(format!(
+                "Fresnel sweep has no step {}",
+                first
+            )))?
+        }
+        if let Some(run) = self.state.fresnel_sweep.take() {
+            self.state.fresnel = run.previous;
+        }
+
+        info!(
+            "Sweeping fresnel from {} to {} in {} steps",
+            from, to, steps
+        );
+        self.begin_checkpoint(
+            AimCommand::FresnelSweep {
+                from,
+                to,
+                steps,
+                dwell_ms,
+            },
+            steps,
+        );
+        let mut run = FresnelSweepRun {
+            values: sweep_values(from, to, steps),
+            index: first as usize,
+            dwell: Duration::from_millis(dwell_ms),
+            shown_at: Instant::now(),
+            previous: self.state.fresnel,
+        };
+        self.show_sweep_step(&mut run)?;
+        self.state.fresnel_sweep = Some(run);
+        Ok(self)
+    }
+
+    /// Advance the running sweep once the dwell time is over
+    pub fn poll_fresnel_sweep(&mut self) -> Result<()> {
+        let mut run = match self.state.fresnel_sweep.take() {
+            Some(run) if run.shown_at.elapsed() >= run.dwell => run,
+            run => {
+                self.state.fresnel_sweep = run;
+                return Ok(());
+            }
+        };
+
+        run.index += 1;
+        if run.index < run.values.len() {
+            self.show_sweep_step(&mut run)?;
+            self.state.fresnel_sweep = Some(run);
+        } else {
+            info!("Fresnel sweep done");
+            self.clear_checkpoint();
+            self.update_state(None, Some(run.previous), None)?
+                .send_current_state()?
+                .send_response(ResponseCode::FresnelSweepDone)?;
+        }
+        Ok(())
+    }
+}
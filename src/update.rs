@@ -0,0 +1,147 @@
+//! Remote update of the controller binary, so microscopes don't have to be
+//! updated one by one with a USB stick
+
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{
+    schema::{AimCommand, Message, MessageData, MessageType, ResponseCode},
+    util::sha256_hex,
+    Context, Result, SlmError,
+};
+use log::info;
+
+/// Give up on downloads that take longer, in seconds
+const DOWNLOAD_TIMEOUT: &str = "300";
+
+/// Interval of the progress messages while downloading
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Download running on a worker thread, so the controller keeps serving
+/// requests in the meantime
+pub struct PendingUpdate {
+    download: JoinHandle<Result<()>>,
+    staged: PathBuf,
+    reported_at: Instant,
+}
+
+fn download(url: &str, path: &Path) -> Result<()> {
+    let status = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location"])
+        .args(["--max-time", DOWNLOAD_TIMEOUT])
+        .arg("--output")
+        .arg(path)
+        .arg(url)
+        .status()?;
+    if !status.success() {
+        Err(SlmError::Request(format!(
+            "Downloading {} failed ({})",
+            url, status
+        )))?
+    }
+    Ok(())
+}
+
+fn download_verified(url: &str, sha256: &str, staged: &Path) -> Result<()> {
+    let actual = download(url, staged).and_then(|_| sha256_hex(staged))?;
+    if !actual.eq_ignore_ascii_case(sha256.trim()) {
+        Err(SlmError::Request(format!(
+            "Update checksum is {}, expected {}",
+            actual, sha256
+        )))?
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+impl<'a> Context<'a> {
+    /// Start downloading the new binary next to the running one; it's verified
+    /// and installed by `poll_update` once the download is done
+    pub fn start_update(&mut self, url: &str, sha256: &str) -> Result<&mut Self> {
+        if self.state.update.is_some() {
+            Err(SlmError::Conflict(
+                "An update is already downloading".to_owned(),
+            ))?
+        }
+        let staged = std::env::current_exe()?.with_extension("update");
+
+        info!("Downloading update from {}", url);
+        let (url, sha256, path) = (url.to_owned(), sha256.to_owned(), staged.clone());
+        let download = thread::spawn(move || {
+            let verified = download_verified(&url, &sha256, &path);
+            if verified.is_err() {
+                // nothing is left half-installed
+                let _ = fs::remove_file(&path);
+            }
+            verified
+        });
+        self.state.update = Some(PendingUpdate {
+            download,
+            staged,
+            reported_at: Instant::now(),
+        });
+        Ok(self)
+    }
+
+    /// Report the bytes downloaded so far, and install the update and reboot
+    /// once it's downloaded and verified
+    pub fn poll_update(&mut self) -> Result<()> {
+        let update = match &mut self.state.update {
+            Some(update) => update,
+            None => return Ok(()),
+        };
+        if !update.download.is_finished() {
+            if update.reported_at.elapsed() < PROGRESS_INTERVAL {
+                return Ok(());
+            }
+            update.reported_at = Instant::now();
+            let downloaded_bytes = fs::metadata(&update.staged).map_or(0, |meta| meta.len());
+            self.send_aim_message(&Message {
+                m_type: MessageType::Device,
+                seq: None,
+                expect: None,
+                client: None,
+                data: MessageData::Aim(AimCommand::UpdateProgress { downloaded_bytes }),
+            })?;
+            return Ok(());
+        }
+
+        let update = self.state.update.take().expect("update is pending");
+        update
+            .download
+            .join()
+            .map_err(|_| SlmError::Request("Update download panicked".to_owned()))??;
+        self.install_update(&update.staged)?
+            .send_response(ResponseCode::UpdateInstalled)?;
+        system_shutdown::reboot()?;
+        Ok(())
+    }
+
+    /// Swap the verified binary with the running one; the running binary is
+    /// kept with the `.previous` extension
+    fn install_update(&mut self, staged: &Path) -> Result<&mut Self> {
+        let current = std::env::current_exe()?;
+        let previous = current.with_extension("previous");
+
+        make_executable(staged)?;
+        fs::copy(&current, &previous)?;
+        fs::rename(staged, &current)?;
+        info!("Installed update to {}", current.display());
+        Ok(self)
+    }
+}
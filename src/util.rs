@@ -1,3 +1,11 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::Result;
+
 pub trait Subtopic {
     fn subtopic<S: AsRef<str>>(&self, topic: S) -> String;
 }
@@ -7,3 +15,13 @@ impl Subtopic for &str {
         format!("{}/{}", self, topic.as_ref())
     }
 }
+
+pub fn sha256_hex(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
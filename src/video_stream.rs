@@ -0,0 +1,153 @@
+//! MJPEG stream of the shown pattern over HTTP, so remote users can watch the
+//! SLM from the control room, e.g. with a browser or VLC
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Luma};
+use log::{info, warn};
+
+use crate::{Context, Result, SlmError};
+
+const BOUNDARY: &str = "frame";
+
+/// Latest encoded frame, shared with the client threads
+type LatestFrame = Arc<Mutex<Option<Arc<Vec<u8>>>>>;
+
+pub struct VideoStream {
+    latest: LatestFrame,
+    /// Number of connected clients; frames are only encoded while there are any
+    clients: Arc<AtomicUsize>,
+    encoded: Instant,
+    /// Pattern of the latest frame, which is reused while it's shown
+    source: Option<ndarray::Array2<u8>>,
+}
+
+/// Send the latest frame whenever it changes, until the client disconnects
+fn serve_client(mut stream: TcpStream, latest: LatestFrame, interval: Duration) -> io::Result<()> {
+    // the request itself doesn't matter; every path serves the stream
+    let mut line = String::new();
+    let mut reader = BufReader::new(stream.try_clone()?);
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\nCache-Control: no-cache\r\n\
+         Content-Type: multipart/x-mixed-replace; boundary={}\r\n\r\n",
+        BOUNDARY
+    )?;
+
+    let mut sent: Option<Arc<Vec<u8>>> = None;
+    loop {
+        let frame = latest.lock().expect("not poisoned").clone();
+        if let Some(frame) = frame {
+            if !matches!(&sent, Some(sent) if Arc::ptr_eq(sent, &frame)) {
+                write!(
+                    stream,
+                    "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                    BOUNDARY,
+                    frame.len()
+                )?;
+                stream.write_all(&frame)?;
+                stream.write_all(b"\r\n")?;
+                sent = Some(frame);
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+fn encode_jpeg(pattern: &ndarray::Array2<u8>, quality: u8) -> Result<Vec<u8>> {
+    let (width, height) = pattern.dim();
+    let image = ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+        Luma([pattern[(x as usize, y as usize)]])
+    });
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageLuma8(image).write_to(&mut jpeg, ImageOutputFormat::Jpeg(quality))?;
+    Ok(jpeg)
+}
+
+impl<'a> Context<'a> {
+    /// Accept stream clients in a background thread, each served by a thread
+    /// of its own, up to `max_clients`
+    pub fn open_video_stream(&mut self) -> Result<()> {
+        let config = match &self.config.video_stream {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        if !(config.fps.is_finite() && config.fps > 0.0) {
+            Err(SlmError::Config(format!(
+                "Invalid video stream frame rate {}",
+                config.fps
+            )))?
+        }
+        let interval = Duration::from_secs_f32(1.0 / config.fps);
+        let listener = TcpListener::bind(&config.address)?;
+        info!("Streaming the pattern on http://{}", config.address);
+
+        let latest = LatestFrame::default();
+        let clients = Arc::new(AtomicUsize::new(0));
+        let (client_latest, client_count) = (latest.clone(), clients.clone());
+        let max_clients = config.max_clients;
+        thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(|stream| stream.ok()) {
+                let peer = stream.peer_addr().ok();
+                // counted here rather than in the client thread, so a burst can't overshoot
+                if client_count.load(Ordering::Relaxed) >= max_clients {
+                    warn!("Turning away video stream client {:?}, too many", peer);
+                    let _ = stream.write_all(b"HTTP/1.0 503 Service Unavailable\r\n\r\n");
+                    continue;
+                }
+                client_count.fetch_add(1, Ordering::Relaxed);
+                let (latest, clients) = (client_latest.clone(), client_count.clone());
+                thread::spawn(move || {
+                    let result = serve_client(stream, latest, interval);
+                    clients.fetch_sub(1, Ordering::Relaxed);
+                    if let Err(err) = result {
+                        info!("Video stream client {:?} left: {}", peer, err);
+                    }
+                });
+            }
+            warn!("Stopped accepting video stream clients");
+        });
+
+        self.state.video_stream = Some(VideoStream {
+            latest,
+            clients,
+            encoded: Instant::now(),
+            source: None,
+        });
+        Ok(())
+    }
+
+    /// Encode the shown pattern at the configured frame rate while anyone watches
+    pub fn poll_video_stream(&mut self) -> Result<()> {
+        let (config, stream) = match (&self.config.video_stream, &mut self.state.video_stream) {
+            (Some(config), Some(stream)) => (config, stream),
+            _ => return Ok(()),
+        };
+        if stream.encoded.elapsed().as_secs_f32() < 1.0 / config.fps
+            || stream.clients.load(Ordering::Relaxed) == 0
+        {
+            return Ok(());
+        }
+        stream.encoded = Instant::now();
+
+        if let Some(pattern) = &self.state.displayed {
+            if stream.source.as_ref() == Some(pattern) {
+                return Ok(());
+            }
+            let jpeg = encode_jpeg(pattern, config.quality)?;
+            *stream.latest.lock().expect("not poisoned") = Some(Arc::new(jpeg));
+            stream.source = Some(pattern.clone());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+//! ZeroMQ sockets speaking the MQTT JSON messages, for acquisition software on
+//! the same machine whose closed loops can't wait for the broker round trip
+
+#[cfg(feature = "zeromq")]
+use log::info;
+
+#[cfg(not(feature = "zeromq"))]
+use crate::SlmError;
+use crate::{schema::Message, Context, Result};
+
+#[cfg(feature = "zeromq")]
+pub struct ZeroMq {
+    // the sockets are closed before their context
+    rep: zmq::Socket,
+    publisher: Option<zmq::Socket>,
+    _context: zmq::Context,
+}
+
+impl<'a> Context<'a> {
+    #[cfg(feature = "zeromq")]
+    pub fn open_zeromq(&mut self) -> Result<()> {
+        let config = match &self.config.zeromq {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        let context = zmq::Context::new();
+        let rep = context.socket(zmq::REP)?;
+        rep.bind(&config.rep_endpoint)?;
+        info!("Serving ZeroMQ requests on {}", config.rep_endpoint);
+        let publisher = match &config.pub_endpoint {
+            Some(endpoint) => {
+                let publisher = context.socket(zmq::PUB)?;
+                publisher.bind(endpoint)?;
+                info!("Publishing on ZeroMQ at {}", endpoint);
+                Some(publisher)
+            }
+            None => None,
+        };
+        self.state.zeromq = Some(ZeroMq {
+            rep,
+            publisher,
+            _context: context,
+        });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "zeromq"))]
+    pub fn open_zeromq(&mut self) -> Result<()> {
+        match self.config.zeromq {
+            Some(_) => Err(SlmError::Config(
+                "ZeroMQ sockets need the zeromq feature".to_owned(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Answer a pending request, if there is one
+    #[cfg(feature = "zeromq")]
+    pub fn poll_zeromq(&mut self) -> Result<()> {
+        let payload = match &self.state.zeromq {
+            Some(zeromq) => match zeromq.rep.recv_bytes(zmq::DONTWAIT) {
+                Ok(payload) => payload,
+                Err(zmq::Error::EAGAIN) => return Ok(()),
+                Err(err) => Err(err)?,
+            },
+            None => return Ok(()),
+        };
+
+        let subtopic = self
+            .config
+            .zeromq
+            .as_ref()
+            .map_or("zmq/aim".to_owned(), |config| config.subtopic.clone());
+        // a REP socket has to answer every request before it takes the next one
+        let reply = match self.process_local_message(&subtopic, payload) {
+            Ok(replies) => serde_json::to_vec(&replies)?,
+            Err(err) => serde_json::to_vec(&serde_json::json!({ "error": err.to_string() }))?,
+        };
+        if let Some(zeromq) = &self.state.zeromq {
+            zeromq.rep.send(reply, 0)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "zeromq"))]
+    pub fn poll_zeromq(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "zeromq")]
+    pub fn publish_zeromq(&self, message: &Message) -> Result<()> {
+        if let Some(ZeroMq {
+            publisher: Some(publisher),
+            ..
+        }) = &self.state.zeromq
+        {
+            publisher.send(serde_json::to_vec(message)?, 0)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "zeromq"))]
+    pub fn publish_zeromq(&self, _message: &Message) -> Result<()> {
+        Ok(())
+    }
+}